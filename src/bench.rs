@@ -1,6 +1,7 @@
 extern crate test;
 
 use super::*;
+use lexer::{Expression, Token};
 use test::Bencher;
 
 #[bench]
@@ -11,8 +12,7 @@ fn lexing_bench(b: &mut Bencher) {
 
 #[bench]
 fn parsing_bench(b: &mut Bencher) {
-    use lexer::Expression;
-    let dummy = vec![
+    let dummy: Vec<Token> = vec![
         Expression::Key("fn".to_owned()),
         Expression::Ident("test".to_owned()),
         Expression::Lparen,
@@ -23,30 +23,113 @@ fn parsing_bench(b: &mut Bencher) {
         Expression::Semicolon,
         Expression::Rbrace,
         Expression::EOF,
-    ];
+    ]
+    .into_iter()
+    .map(Token::from)
+    .collect();
 
     b.iter(move || parser::parse(dummy.clone()));
 }
 
 #[bench]
 fn exec_bench(b: &mut Bencher) {
-    use lexer::Expression;
-    let dummy = parser::parse(vec![
-        Expression::Key("fn".to_owned()),
-        Expression::Ident("main".to_owned()),
-        Expression::Lparen,
-        Expression::Rparen,
-        Expression::Lbrace,
-        Expression::Key("print".to_owned()),
-        Expression::Word("hello world".to_owned()),
-        Expression::Semicolon,
-        Expression::Rbrace,
-        Expression::Ident("main".to_owned()),
-        Expression::Lparen,
-        Expression::Rparen,
-        Expression::Semicolon,
-        Expression::EOF,
-    ]);
+    let dummy = parser::parse(
+        vec![
+            Expression::Key("fn".to_owned()),
+            Expression::Ident("main".to_owned()),
+            Expression::Lparen,
+            Expression::Rparen,
+            Expression::Lbrace,
+            Expression::Key("print".to_owned()),
+            Expression::Word("hello world".to_owned()),
+            Expression::Semicolon,
+            Expression::Rbrace,
+            Expression::Ident("main".to_owned()),
+            Expression::Lparen,
+            Expression::Rparen,
+            Expression::Semicolon,
+            Expression::EOF,
+        ]
+        .into_iter()
+        .map(Token::from)
+        .collect(),
+    );
+
+    b.iter(|| {
+        interpreter::run(
+            dummy.clone().unwrap(),
+            parser::ExprNode::Illegal(None).into(),
+        )
+    });
+}
 
-    b.iter(|| interpreter::run(dummy.clone().unwrap(), parser::ExprNode::Illegal(None)));
+const LOOP: &str = "
+    fn step(x) { return x * 3 + 1; }
+    fn main() {
+        total = 0;
+        for (i = 0; i < 1000; i++) {
+            total = total + step(i) - i * 2;
+        }
+        return total;
+    }
+    main();
+";
+
+//a loop that only uses local variables, like the inner loops of a simulation
+const WHILE: &str = "
+    fn main() {
+        i = 0;
+        total = 0;
+        while i < 10000 {
+            total = total + i * 2 % 7;
+            i = i + 1;
+        }
+        return total;
+    }
+    main();
+";
+
+//deep recursion, which is all calls
+const CALLS: &str = "
+    fn fib(n) { if n < 2 { return n; } return fib(n - 1) + fib(n - 2); }
+    fib(15);
+";
+
+fn script_bench(b: &mut Bencher, source: &str, backend: interpreter::Backend) {
+    let mut tree = parser::parse(lexer::run(source).unwrap()).unwrap();
+    b.iter(|| {
+        let mut runtime = interpreter::Runtime::new();
+        runtime.set_backend(backend);
+        runtime.run_script(&mut tree, &interpreter::StackFrame::new())
+    });
+}
+
+#[bench]
+fn tree_walker_loop_bench(b: &mut Bencher) {
+    script_bench(b, LOOP, interpreter::Backend::TreeWalker);
+}
+
+#[bench]
+fn vm_loop_bench(b: &mut Bencher) {
+    script_bench(b, LOOP, interpreter::Backend::Vm);
+}
+
+#[bench]
+fn tree_walker_while_bench(b: &mut Bencher) {
+    script_bench(b, WHILE, interpreter::Backend::TreeWalker);
+}
+
+#[bench]
+fn vm_while_bench(b: &mut Bencher) {
+    script_bench(b, WHILE, interpreter::Backend::Vm);
+}
+
+#[bench]
+fn tree_walker_calls_bench(b: &mut Bencher) {
+    script_bench(b, CALLS, interpreter::Backend::TreeWalker);
+}
+
+#[bench]
+fn vm_calls_bench(b: &mut Bencher) {
+    script_bench(b, CALLS, interpreter::Backend::Vm);
 }
@@ -1,9 +1,13 @@
 use std::fs;
+use std::thread;
 
 
 #[macro_use]
 extern crate clap;
 
+//scripts run on a thread with this much stack, which fits the default call depth in an optimized build
+const STACK_SIZE: usize = 64 * 1024 * 1024;
+
 fn main() {
     
     let matches = clap_app!(app => 
@@ -12,14 +16,20 @@ fn main() {
         (author: "Emerald <@Emerald#6666>")
         (about: "Parses and runs emerald script")
         (@arg debug: -d --debug "Display debugging information")
+        (@arg vm: --vm "Compile the script to bytecode and run it on the VM")
         (@arg PATH: +required "Path of the file to run")
         (@arg ARGS: ... +use_delimiter "Arguments to pass to the script")
         ).get_matches();
 
     let debug = matches.is_present("debug");
+    let backend = if matches.is_present("vm") {
+        gem::Backend::Vm
+    } else {
+        gem::Backend::TreeWalker
+    };
 
     if let Some(path) = matches.value_of("PATH") {
-        let data = fs::read_to_string(&path).unwrap_or_else(|e| {
+        let data = fs::read_to_string(path).unwrap_or_else(|e| {
             panic!("Couldn't read file {}: {}", path, e);
         });
         let args = if let Some(tmp) = matches.values_of("ARGS"){
@@ -27,7 +37,16 @@ fn main() {
         }else {
             "".to_string()
         };
-        gem::run(data, &args, debug);
-        return;
+        let name = path.to_owned();
+        let script = thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(move || {
+                if let Err(e) = gem::run_file_with_backend(&name, data, &args, debug, backend) {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            })
+            .expect("Couldn't start the thread to run the script");
+        script.join().unwrap_or_else(|e| std::panic::resume_unwind(e));
     }
 }
\ No newline at end of file
@@ -0,0 +1,73 @@
+#[cfg(test)]
+mod tests;
+
+use crate::error::Error;
+use crate::interpreter::{undefined, FromValue, IntoArgs, IntoValue, Runtime, StackFrame};
+use crate::{lexer, parser};
+
+///Runs scripts for a host application. A script is loaded once, and then the host can call the functions it
+///defines and read or change its global variables as many times as it needs to.
+pub struct Engine {
+    runtime: Runtime,
+    globals: StackFrame,
+}
+
+impl Engine {
+    ///Creates an engine with only the builtin functions defined
+    pub fn new() -> Engine {
+        Engine {
+            runtime: Runtime::new(),
+            globals: StackFrame::new(),
+        }
+    }
+
+    ///Runs `source`, keeping any functions and variables it defines. Loading another script adds to what's
+    ///already there
+    pub fn load(&mut self, source: &str) -> Result<(), Error> {
+        self.load_file("<input>", source)
+    }
+
+    ///Same as `load`, but errors will point at the file `name`
+    pub fn load_file(&mut self, name: &str, source: &str) -> Result<(), Error> {
+        let mut ast = parser::parse(lexer::run_file(name, source)?)?;
+        self.runtime.reset_usage();
+        self.runtime.run_script(&mut ast, &self.globals)?;
+        Ok(())
+    }
+
+    ///Calls the script function `name` with `args`, usually a tuple like `(1, "two")`, and converts what it
+    ///returns into a `T`
+    pub fn call<T: FromValue>(&mut self, name: &str, args: impl IntoArgs) -> Result<T, Error> {
+        let func = self.globals.get_var(name).ok_or_else(|| undefined(name))?;
+        self.runtime.reset_usage();
+        let val = self.runtime.call_function(&func, args.into_args())?;
+        T::from_value(val)
+    }
+
+    ///Reads the global variable `name` as a `T`
+    pub fn get<T: FromValue>(&self, name: &str) -> Result<T, Error> {
+        let val = self.globals.get_var(name).ok_or_else(|| undefined(name))?;
+        T::from_value(val)
+    }
+
+    ///Sets the global variable `name`, creating it if it doesn't exist yet
+    pub fn set(&mut self, name: &str, val: impl IntoValue) {
+        self.globals.define(name.to_owned(), val.into_value());
+    }
+
+    ///The runtime scripts are run with, which is where native functions get registered
+    pub fn runtime(&mut self) -> &mut Runtime {
+        &mut self.runtime
+    }
+
+    ///The scope that holds everything the loaded scripts defined at the top level
+    pub fn globals(&self) -> &StackFrame {
+        &self.globals
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,109 @@
+use crate::engine::Engine;
+use crate::error::{Error, RuntimeErrorKind};
+use crate::interpreter::{Backend, Value};
+
+const PLUGIN: &str = "
+    count = 0;
+    greeting = \"hello\";
+
+    fn on_event(name, amount) {
+        count = count + amount;
+        return name + \" x\" + count;
+    }
+
+    fn average(a, b) {
+        return (a + b) / 2.0;
+    }
+
+    fn nothing() {}
+";
+
+#[test]
+fn calling_functions() {
+    let mut engine = Engine::new();
+    engine.load(PLUGIN).unwrap();
+
+    let first: String = engine.call("on_event", ("click", 2)).unwrap();
+    let second: String = engine.call("on_event", ("click", 3)).unwrap();
+    assert_eq!(first, "click x2");
+    assert_eq!(second, "click x5");
+
+    assert_eq!(engine.call::<f64>("average", (1, 2)), Ok(1.5));
+    assert_eq!(engine.call::<()>("nothing", ()), Ok(()));
+    assert_eq!(
+        engine.call::<Value>("average", vec![Value::Int(3), Value::Int(5)]),
+        Ok(Value::Float(4.0))
+    );
+
+    let err = engine.call::<Value>("missing", ()).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::RuntimeError {
+            kind: RuntimeErrorKind::UndefinedName,
+            ..
+        }
+    ));
+    let err = engine.call::<Value>("average", (1,)).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::RuntimeError {
+            kind: RuntimeErrorKind::WrongArgumentCount,
+            ..
+        }
+    ));
+    //asking for the wrong type is an error rather than a panic
+    let err = engine.call::<i64>("on_event", ("key", 1)).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::RuntimeError {
+            kind: RuntimeErrorKind::TypeMismatch,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn globals() {
+    let mut engine = Engine::new();
+    engine.load(PLUGIN).unwrap();
+
+    assert_eq!(engine.get::<i64>("count"), Ok(0));
+    assert_eq!(engine.get::<String>("greeting"), Ok("hello".to_owned()));
+
+    engine.set("count", 10);
+    engine.set("visitor", "sam");
+    let msg: String = engine.call("on_event", ("hi", 1)).unwrap();
+    assert_eq!(msg, "hi x11");
+    assert_eq!(engine.get::<i64>("count"), Ok(11));
+
+    //later scripts can see what earlier ones defined
+    engine
+        .load("fn greet() { return greeting + \" \" + visitor; }")
+        .unwrap();
+    assert_eq!(
+        engine.call::<String>("greet", ()),
+        Ok("hello sam".to_owned())
+    );
+
+    assert!(engine.get::<Value>("nope").is_err());
+}
+
+#[test]
+fn scripts_that_use_each_other() {
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        let mut engine = Engine::new();
+        engine.runtime().set_backend(backend);
+        //each script's functions use something the other one defines, so whichever is loaded first uses a name
+        //that isn't there yet
+        engine
+            .load("fn uses_later() { return helper(2) + offset; }\nfn double(n) { return n * 2; }")
+            .unwrap();
+        engine
+            .load("offset = 1;\nfn helper(n) { return double(n) + 1; }")
+            .unwrap();
+        assert_eq!(engine.call::<i64>("uses_later", ()), Ok(6));
+
+        //code that runs straight away still can't use names nothing has defined
+        assert!(engine.load("x = still_missing;").is_err());
+    }
+}
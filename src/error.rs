@@ -0,0 +1,149 @@
+use crate::lexer::Span;
+use std::fmt;
+
+///The different things that can go wrong while tokenizing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LexErrorKind {
+    InvalidNumber,
+    UnterminatedString,
+    InvalidEscape,
+    UnmatchedBrace,
+}
+
+///The different things that can go wrong while building the tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    UnexpectedToken,
+    UnexpectedEof,
+    UnknownKeyword,
+    InvalidAssignment,
+}
+
+///The different things that can go wrong while running a script
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeErrorKind {
+    UndefinedName,
+    TypeMismatch,
+    WrongArgumentCount,
+    IndexOutOfBounds,
+    MissingProperty,
+    InvalidOperation,
+    Overflow,
+    DivisionByZero,
+    InvalidJson,
+    LimitExceeded,
+    PermissionDenied,
+    IoError,
+}
+
+///Everything that can go wrong between reading a script and running it
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    LexError {
+        kind: LexErrorKind,
+        message: String,
+        span: Span,
+        notes: Vec<String>,
+    },
+    ParseError {
+        kind: ParseErrorKind,
+        message: String,
+        span: Span,
+        notes: Vec<String>,
+    },
+    ///Runtime errors only get a span once they reach the node that caused them, so they might not have one
+    RuntimeError {
+        kind: RuntimeErrorKind,
+        message: String,
+        span: Option<Span>,
+        notes: Vec<String>,
+    },
+}
+
+impl Error {
+    pub fn lex(kind: LexErrorKind, message: impl Into<String>, span: Span) -> Error {
+        Error::LexError {
+            kind,
+            message: message.into(),
+            span,
+            notes: vec![],
+        }
+    }
+
+    pub fn parse(kind: ParseErrorKind, message: impl Into<String>, span: Span) -> Error {
+        Error::ParseError {
+            kind,
+            message: message.into(),
+            span,
+            notes: vec![],
+        }
+    }
+
+    pub fn runtime(kind: RuntimeErrorKind, message: impl Into<String>) -> Error {
+        Error::RuntimeError {
+            kind,
+            message: message.into(),
+            span: None,
+            notes: vec![],
+        }
+    }
+
+    ///Points a runtime error at `span` if it doesn't already point somewhere more specific
+    pub fn at(mut self, new: &Span) -> Error {
+        if let Error::RuntimeError {
+            span: span @ None, ..
+        } = &mut self
+        {
+            *span = Some(new.clone());
+        }
+        self
+    }
+
+    ///Adds some extra information to be shown after the main message
+    pub fn with_note(mut self, note: impl Into<String>) -> Error {
+        match &mut self {
+            Error::LexError { notes, .. }
+            | Error::ParseError { notes, .. }
+            | Error::RuntimeError { notes, .. } => notes.push(note.into()),
+        }
+        self
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Error::LexError { message, .. }
+            | Error::ParseError { message, .. }
+            | Error::RuntimeError { message, .. } => message,
+        }
+    }
+
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            Error::LexError { span, .. } | Error::ParseError { span, .. } => Some(span),
+            Error::RuntimeError { span, .. } => span.as_ref(),
+        }
+    }
+
+    pub fn notes(&self) -> &[String] {
+        match self {
+            Error::LexError { notes, .. }
+            | Error::ParseError { notes, .. }
+            | Error::RuntimeError { notes, .. } => notes,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(span) = self.span() {
+            write!(f, "{}: ", span)?;
+        }
+        write!(f, "{}", self.message())?;
+        for note in self.notes() {
+            write!(f, "\n    note: {}", note)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
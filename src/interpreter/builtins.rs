@@ -1,71 +1,335 @@
-use std::collections::HashMap;
-use crate::interpreter::Value;
+use crate::error::{Error, RuntimeErrorKind};
+use crate::interpreter::capabilities::{io_error, Capability};
+use crate::interpreter::{json, Runtime, Value};
 use console::Term;
+use std::collections::HashMap;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::rc::Rc;
+use std::{env, fs};
+
+///The signature of functions implemented in rust. They get the runtime so they can call any functions they're passed
+pub type NativeFn = dyn Fn(&mut Runtime, Vec<Value>) -> Result<Value, Error>;
+
+///A function implemented in rust that scripts can call
+#[derive(Clone)]
+pub struct Builtin {
+    pub func: Rc<NativeFn>,
+    ///How many arguments the function takes, or `None` if it checks them itself
+    pub arity: Option<usize>,
+    ///The capability a runtime needs to be able to call the function
+    pub capability: Option<Capability>,
+}
+
+impl Builtin {
+    pub fn new<F>(func: F, arity: Option<usize>) -> Builtin
+    where
+        F: Fn(&mut Runtime, Vec<Value>) -> Result<Value, Error> + 'static,
+    {
+        Builtin {
+            func: Rc::new(func),
+            arity,
+            capability: None,
+        }
+    }
+
+    ///Only lets runtimes with `cap` call the function
+    pub fn needs(mut self, cap: Capability) -> Builtin {
+        self.capability = Some(cap);
+        self
+    }
+}
 
-pub fn get_functions() -> HashMap<String, Box<dyn Fn(Vec<Value>) -> Value>> {
-    let mut hash: HashMap<String, Box<dyn Fn(Vec<Value>) -> Value>> = HashMap::new();
-    hash.insert("print".to_owned(), Box::new(em_print));
-    hash.insert("println".to_owned(), Box::new(em_println));
-    hash.insert("number".to_owned(), Box::new(em_number));
-    hash.insert("readln".to_owned(), Box::new(em_readln));
-    hash.insert("read".to_owned(), Box::new(em_read));
+pub fn get_functions() -> HashMap<String, Builtin> {
+    let mut hash: HashMap<String, Builtin> = HashMap::new();
+    hash.insert(
+        "print".to_owned(),
+        Builtin::new(em_print, None).needs(Capability::Io),
+    );
+    hash.insert(
+        "println".to_owned(),
+        Builtin::new(em_println, None).needs(Capability::Io),
+    );
+    hash.insert(
+        "eprint".to_owned(),
+        Builtin::new(em_eprint, None).needs(Capability::Io),
+    );
+    hash.insert(
+        "eprintln".to_owned(),
+        Builtin::new(em_eprintln, None).needs(Capability::Io),
+    );
+    hash.insert("number".to_owned(), Builtin::new(em_number, None));
+    hash.insert(
+        "readln".to_owned(),
+        Builtin::new(em_readln, None).needs(Capability::Io),
+    );
+    hash.insert(
+        "read".to_owned(),
+        Builtin::new(em_read, None).needs(Capability::Io),
+    );
+    hash.insert(
+        "json.parse".to_owned(),
+        Builtin::new(em_json_parse, Some(1)),
+    );
+    hash.insert(
+        "json.stringify".to_owned(),
+        Builtin::new(em_json_stringify, None),
+    );
+    hash.insert(
+        "fs.read".to_owned(),
+        Builtin::new(em_fs_read, Some(1)).needs(Capability::Fs),
+    );
+    hash.insert(
+        "fs.write".to_owned(),
+        Builtin::new(em_fs_write, Some(2)).needs(Capability::Fs),
+    );
+    hash.insert(
+        "fs.exists".to_owned(),
+        Builtin::new(em_fs_exists, Some(1)).needs(Capability::Fs),
+    );
+    hash.insert(
+        "fs.list".to_owned(),
+        Builtin::new(em_fs_list, Some(1)).needs(Capability::Fs),
+    );
+    hash.insert(
+        "env.get".to_owned(),
+        Builtin::new(em_env_get, Some(1)).needs(Capability::Env),
+    );
 
     hash
 }
 
-fn em_print(args: Vec<Value>) -> Value {
-    print!("{}", args[0]);
-    Value::Null
+///Gets the first argument passed to a builtin, or complains that there wasn't one
+fn first_arg<'a>(name: &str, args: &'a [Value]) -> Result<&'a Value, Error> {
+    args.first().ok_or_else(|| {
+        Error::runtime(
+            RuntimeErrorKind::WrongArgumentCount,
+            format!("Expected 1 argument for {}, got 0", name),
+        )
+    })
 }
 
-fn em_println(args: Vec<Value>) -> Value {
-    println!("{}", args[0]);
-    Value::Null
+fn em_print(rt: &mut Runtime, args: Vec<Value>) -> Result<Value, Error> {
+    let val = rt.display(first_arg("print", &args)?)?;
+    write!(rt.streams.stdout, "{}", val).map_err(|e| io_error("stdout", e))?;
+    Ok(Value::Null)
 }
 
-fn em_number(args: Vec<Value>) -> Value {
-    let v = args[0].clone();
-    match v {
+fn em_println(rt: &mut Runtime, args: Vec<Value>) -> Result<Value, Error> {
+    let val = rt.display(first_arg("println", &args)?)?;
+    writeln!(rt.streams.stdout, "{}", val).map_err(|e| io_error("stdout", e))?;
+    Ok(Value::Null)
+}
+
+fn em_eprint(rt: &mut Runtime, args: Vec<Value>) -> Result<Value, Error> {
+    let val = rt.display(first_arg("eprint", &args)?)?;
+    write!(rt.streams.stderr, "{}", val).map_err(|e| io_error("stderr", e))?;
+    Ok(Value::Null)
+}
+
+fn em_eprintln(rt: &mut Runtime, args: Vec<Value>) -> Result<Value, Error> {
+    let val = rt.display(first_arg("eprintln", &args)?)?;
+    writeln!(rt.streams.stderr, "{}", val).map_err(|e| io_error("stderr", e))?;
+    Ok(Value::Null)
+}
+
+///Shows the prompt if there is one, making sure it's visible before waiting for input
+fn prompt(rt: &mut Runtime, args: &[Value]) -> Result<(), Error> {
+    if let Some(p) = args.first() {
+        let p = rt.display(p)?;
+        write!(rt.streams.stdout, "{}", p).map_err(|e| io_error("stdout", e))?;
+    }
+    rt.streams.stdout.flush().map_err(|e| io_error("stdout", e))
+}
+
+fn em_number(_: &mut Runtime, args: Vec<Value>) -> Result<Value, Error> {
+    let v = first_arg("number", &args)?.clone();
+    Ok(match v {
         Value::EmString(s) => {
-            if let Ok(p) = s.parse::<f32>() {
+            if let Ok(i) = s.parse::<i64>() {
+                Value::Int(i)
+            } else if let Ok(p) = s.parse::<f64>() {
                 Value::Float(p)
-            }else {
+            } else {
                 Value::Null
             }
         }
-        Value::EmBool(b) => Value::Float(b as i32 as f32),
-        Value::Float(_) => v,
-        _ => Value::Null
+        Value::EmBool(b) => Value::Int(b as i64),
+        Value::Int(_) | Value::Float(_) => v,
+        _ => Value::Null,
+    })
+}
+
+///Reads a line without its line ending, or null once the input runs out
+fn em_readln(rt: &mut Runtime, args: Vec<Value>) -> Result<Value, Error> {
+    prompt(rt, &args)?;
+    let mut line = String::new();
+    if rt
+        .streams
+        .stdin
+        .read_line(&mut line)
+        .map_err(|e| io_error("stdin", e))?
+        == 0
+    {
+        return Ok(Value::Null);
     }
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    rt.allocate(line.len())?;
+    Ok(Value::EmString(line))
 }
 
-fn em_readln(args: Vec<Value>) -> Value {
-    let buf = Term::stdout();
-    if args.len() > 0 {
-        buf.write_str(&format!("{}", args[0])).unwrap_or(());
+///Reads a single character, or null once the input runs out. A terminal gives it as soon as the key is pressed.
+fn em_read(rt: &mut Runtime, args: Vec<Value>) -> Result<Value, Error> {
+    prompt(rt, &args)?;
+    if rt.streams.terminal && io::stdin().is_terminal() {
+        return Ok(match Term::stdout().read_char() {
+            Ok(c) => Value::EmString(String::from(c)),
+            Err(_) => Value::Null,
+        });
+    }
+    //utf-8 characters are up to 4 bytes long, and the first byte says how many more there are
+    let mut bytes = vec![];
+    let stdin = &mut rt.streams.stdin;
+    loop {
+        let mut byte = [0];
+        if stdin.read(&mut byte).map_err(|e| io_error("stdin", e))? == 0 {
+            break;
+        }
+        bytes.push(byte[0]);
+        let len = match bytes[0] {
+            b if b >= 0xF0 => 4,
+            b if b >= 0xE0 => 3,
+            b if b >= 0xC0 => 2,
+            _ => 1,
+        };
+        if bytes.len() >= len {
+            break;
+        }
+    }
+    if bytes.is_empty() {
+        Ok(Value::Null)
+    } else {
+        Ok(Value::EmString(
+            String::from_utf8_lossy(&bytes).into_owned(),
+        ))
     }
-    let input = buf.read_line();
-    match input {
-        Ok(s) => Value::EmString(s),
-        Err(_) => Value::Null
+}
+
+fn em_json_parse(rt: &mut Runtime, args: Vec<Value>) -> Result<Value, Error> {
+    match first_arg("json.parse", &args)? {
+        //what gets built is about as big as the text it comes from
+        Value::EmString(s) => rt.allocate(s.len()).and_then(|_| json::parse(s)),
+        other => Err(Error::runtime(
+            RuntimeErrorKind::TypeMismatch,
+            format!("json.parse expects a string, found {}", other.type_name()),
+        )),
+    }
+}
+
+///Turns a value into JSON, with an optional number of spaces to indent by
+fn em_json_stringify(rt: &mut Runtime, args: Vec<Value>) -> Result<Value, Error> {
+    let val = first_arg("json.stringify", &args)?;
+    let indent = match args.get(1) {
+        None | Some(Value::Null) => None,
+        Some(Value::Int(n)) if (0..=json::MAX_INDENT as i64).contains(n) => Some(*n as usize),
+        Some(Value::Int(n)) if *n > 0 => {
+            return Err(Error::runtime(
+                RuntimeErrorKind::InvalidOperation,
+                format!("An indent of {} is too big for json.stringify", n),
+            )
+            .with_note(format!("indents can be up to {} spaces", json::MAX_INDENT)))
+        }
+        Some(other) => {
+            return Err(Error::runtime(
+                RuntimeErrorKind::TypeMismatch,
+                format!(
+                    "The indent for json.stringify has to be a positive integer, found {}",
+                    other
+                ),
+            ))
+        }
+    };
+    if args.len() > 2 {
+        return Err(Error::runtime(
+            RuntimeErrorKind::WrongArgumentCount,
+            format!(
+                "Expected 1 or 2 arguments for json.stringify, got {}",
+                args.len()
+            ),
+        ));
     }
+    json::stringify(rt, val, indent).map(Value::EmString)
 }
 
-fn em_read(args: Vec<Value>) -> Value {
-    let buf = Term::stdout();
-    if args.len() > 0 {
-        buf.write_str(&format!("{}", args[0])).unwrap_or(());
+///Gets an argument that has to be a string
+fn string_arg<'a>(name: &str, args: &'a [Value], i: usize) -> Result<&'a str, Error> {
+    match args.get(i) {
+        Some(Value::EmString(s)) => Ok(s),
+        other => Err(Error::runtime(
+            RuntimeErrorKind::TypeMismatch,
+            format!(
+                "{} expects a string, found {}",
+                name,
+                other.map_or("nothing", Value::type_name)
+            ),
+        )),
     }
-    let input = buf.read_char();
-    match input {
-        Ok(s) => Value::EmString(String::from(s)),
-        Err(_) => Value::Null
+}
+
+fn em_fs_read(rt: &mut Runtime, args: Vec<Value>) -> Result<Value, Error> {
+    let path = string_arg("fs.read", &args, 0)?;
+    let file = rt.capabilities().fs.resolve(path, false)?;
+    let contents = fs::read_to_string(file).map_err(|e| io_error(path, e))?;
+    rt.allocate(contents.len())?;
+    Ok(Value::EmString(contents))
+}
+
+///Writes the second argument to a file, replacing anything that was in it
+fn em_fs_write(rt: &mut Runtime, args: Vec<Value>) -> Result<Value, Error> {
+    let path = string_arg("fs.write", &args, 0)?;
+    let contents = rt.display(&args[1])?;
+    rt.capabilities()
+        .fs
+        .create(path)?
+        .write_all(contents.as_bytes())
+        .map_err(|e| io_error(path, e))?;
+    Ok(Value::Null)
+}
+
+fn em_fs_exists(rt: &mut Runtime, args: Vec<Value>) -> Result<Value, Error> {
+    let path = string_arg("fs.exists", &args, 0)?;
+    let file = rt.capabilities().fs.resolve(path, false)?;
+    Ok(Value::EmBool(file.exists()))
+}
+
+///Lists the names of the files in a directory, sorted so the order is always the same
+fn em_fs_list(rt: &mut Runtime, args: Vec<Value>) -> Result<Value, Error> {
+    let path = string_arg("fs.list", &args, 0)?;
+    let dir = rt.capabilities().fs.resolve(path, false)?;
+    let mut names = vec![];
+    for entry in fs::read_dir(dir).map_err(|e| io_error(path, e))? {
+        let entry = entry.map_err(|e| io_error(path, e))?;
+        names.push(entry.file_name().to_string_lossy().into_owned());
     }
+    names.sort();
+    Ok(Value::array(
+        names.into_iter().map(Value::EmString).collect(),
+    ))
+}
+
+///Reads an environment variable, giving null if it isn't set
+fn em_env_get(_: &mut Runtime, args: Vec<Value>) -> Result<Value, Error> {
+    let name = string_arg("env.get", &args, 0)?;
+    Ok(env::var(name).map_or(Value::Null, Value::EmString))
 }
 
 // fn em_readKey(args: Vec<Value>) -> Value {
 //     let buf = Term::stdout();
-//     if args.len() > 0 {
+//     if !args.is_empty() {
 //         buf.write_str(&format!("{}", args[0])).unwrap_or(());
 //     }
 //     let input = buf.read_key();
@@ -73,4 +337,4 @@ fn em_read(args: Vec<Value>) -> Value {
 //         Ok(s) => Value::EmString(String::from(s)),
 //         Err(_) => Value::Null
 //     }
-// }
\ No newline at end of file
+// }
@@ -0,0 +1,160 @@
+use crate::error::{Error, RuntimeErrorKind};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+
+///The groups of builtins a runtime can be allowed to use. Calling a builtin from a group the runtime wasn't
+///given is a `PermissionDenied` error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capabilities {
+    ///Printing to and reading from the terminal
+    pub io: bool,
+    ///Which files can be read and written
+    pub fs: FsAccess,
+    ///Reading environment variables
+    pub env: bool,
+}
+
+///How much of the file system scripts can see. Paths are relative to the directory they're limited to, and
+///can't be used to get outside of it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FsAccess {
+    Denied,
+    ReadOnly(PathBuf),
+    ReadWrite(PathBuf),
+    ///Any file the process itself can get to, with paths relative to the working directory
+    Full,
+}
+
+///A group of builtins that need permission to be called
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    Io,
+    Fs,
+    Env,
+}
+
+impl Default for Capabilities {
+    ///Terminal I/O only, which is what scripts could always do
+    fn default() -> Self {
+        Capabilities {
+            io: true,
+            fs: FsAccess::Denied,
+            env: false,
+        }
+    }
+}
+
+impl Capabilities {
+    ///Everything, for scripts that are trusted as much as the program running them
+    pub fn all() -> Capabilities {
+        Capabilities {
+            io: true,
+            fs: FsAccess::Full,
+            env: true,
+        }
+    }
+
+    ///Nothing but computation
+    pub fn none() -> Capabilities {
+        Capabilities {
+            io: false,
+            fs: FsAccess::Denied,
+            env: false,
+        }
+    }
+
+    pub fn allows(&self, cap: Capability) -> bool {
+        match cap {
+            Capability::Io => self.io,
+            Capability::Fs => self.fs != FsAccess::Denied,
+            Capability::Env => self.env,
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Capability::Io => write!(f, "io"),
+            Capability::Fs => write!(f, "fs"),
+            Capability::Env => write!(f, "env"),
+        }
+    }
+}
+
+pub(crate) fn permission_denied(message: impl Into<String>) -> Error {
+    Error::runtime(RuntimeErrorKind::PermissionDenied, message)
+}
+
+pub(crate) fn io_error(path: &str, err: std::io::Error) -> Error {
+    Error::runtime(RuntimeErrorKind::IoError, format!("{}: {}", path, err))
+}
+
+impl FsAccess {
+    ///Works out which file `path` refers to, making sure the script is allowed to read it, or write to it
+    ///if `write` is set
+    pub(crate) fn resolve(&self, path: &str, write: bool) -> Result<PathBuf, Error> {
+        let (root, writable) = match self {
+            FsAccess::Denied => {
+                return Err(permission_denied(format!("Can't access {}", path))
+                    .with_note("the runtime wasn't given the fs capability"))
+            }
+            FsAccess::Full => return Ok(PathBuf::from(path)),
+            FsAccess::ReadOnly(dir) => (dir, false),
+            FsAccess::ReadWrite(dir) => (dir, true),
+        };
+        if write && !writable {
+            return Err(permission_denied(format!("Can't write to {}", path))
+                .with_note("files can only be read in this runtime"));
+        }
+        let root = root
+            .canonicalize()
+            .map_err(|e| io_error(&root.display().to_string(), e))?;
+        let full = root.join(path);
+        //links and `..` are followed before checking, so they can't be used to escape. A file that's about to
+        //be created doesn't exist yet, so the directory it will go in is checked instead.
+        let resolved = match full.canonicalize() {
+            Ok(p) => p,
+            //a link to something that doesn't exist can't be followed to check it, and writing through it would
+            //create whatever it points at
+            Err(_)
+                if full
+                    .symlink_metadata()
+                    .is_ok_and(|m| m.file_type().is_symlink()) =>
+            {
+                return Err(permission_denied(format!("Can't access {}", path))
+                    .with_note("links to files that don't exist can't be used"))
+            }
+            Err(e) => match (full.parent(), full.file_name()) {
+                (Some(parent), Some(name)) => parent
+                    .canonicalize()
+                    .map_err(|_| io_error(path, e))?
+                    .join(name),
+                _ => return Err(io_error(path, e)),
+            },
+        };
+        if !resolved.starts_with(&root) {
+            return Err(permission_denied(format!("Can't access {}", path))
+                .with_note(format!("scripts can only use files in {}", root.display())));
+        }
+        Ok(resolved)
+    }
+
+    ///Opens the file `path` refers to for writing, replacing anything that was in it. In a limited directory
+    ///the file can't be a link, so one can't be swapped in after the path was checked.
+    pub(crate) fn create(&self, path: &str) -> Result<File, Error> {
+        let file = self.resolve(path, true)?;
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            if *self != FsAccess::Full {
+                options.custom_flags(libc::O_NOFOLLOW);
+            }
+        }
+        options.open(file).map_err(|e| io_error(path, e))
+    }
+}
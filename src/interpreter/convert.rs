@@ -0,0 +1,328 @@
+use crate::error::{Error, RuntimeErrorKind};
+use crate::interpreter::types::{EmMap, MapKey};
+use crate::interpreter::Value;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+///Turns a rust value into something scripts can use
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+///Turns a script value back into a rust type, failing if it's the wrong kind of value
+pub trait FromValue: Sized {
+    fn from_value(val: Value) -> Result<Self, Error>;
+}
+
+///The arguments for calling a script function from rust, usually a tuple like `(1, "two")`
+pub trait IntoArgs {
+    fn into_args(self) -> Vec<Value>;
+}
+
+///Unpacks the arguments a native function was called with, usually into a tuple like `(i64, String)`
+pub trait FromArgs: Sized {
+    fn from_args(args: Vec<Value>) -> Result<Self, Error>;
+}
+
+///Complains that `found` isn't the type of script value that was `expected`
+fn mismatch(expected: &str, found: &Value) -> Error {
+    Error::runtime(
+        RuntimeErrorKind::TypeMismatch,
+        format!("Expected {}, found {}", expected, found.type_name()),
+    )
+    .with_note(format!("the value was {}", found))
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(val: Value) -> Result<Value, Error> {
+        Ok(val)
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::Null
+    }
+}
+
+//Functions without a return give back null, so that's what unit expects
+impl FromValue for () {
+    fn from_value(val: Value) -> Result<(), Error> {
+        match val {
+            Value::Null => Ok(()),
+            other => Err(mismatch("null", &other)),
+        }
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::EmBool(self)
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(val: Value) -> Result<bool, Error> {
+        match val {
+            Value::EmBool(b) => Ok(b),
+            other => Err(mismatch("bool", &other)),
+        }
+    }
+}
+
+//Every integer type that fits in an i64 can become a script integer
+macro_rules! into_int {
+    ($($ty:ty),+) => {
+        $(impl IntoValue for $ty {
+            fn into_value(self) -> Value {
+                Value::Int(self as i64)
+            }
+        })+
+    };
+}
+
+into_int!(i8, i16, i32, i64, u8, u16, u32);
+
+//Script integers can be read back as any integer type, as long as the number fits
+macro_rules! from_int {
+    ($($ty:ty),+) => {
+        $(impl FromValue for $ty {
+            fn from_value(val: Value) -> Result<$ty, Error> {
+                match val {
+                    Value::Int(i) => <$ty>::try_from(i).map_err(|_| {
+                        Error::runtime(
+                            RuntimeErrorKind::Overflow,
+                            format!("{} doesn't fit in a {}", i, stringify!($ty)),
+                        )
+                    }),
+                    other => Err(mismatch("int", &other)),
+                }
+            }
+        })+
+    };
+}
+
+from_int!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Value {
+        Value::Float(self)
+    }
+}
+
+impl IntoValue for f32 {
+    fn into_value(self) -> Value {
+        Value::Float(self as f64)
+    }
+}
+
+//Integers can always be used where a float is wanted, the same way they are in scripts
+impl FromValue for f64 {
+    fn from_value(val: Value) -> Result<f64, Error> {
+        match val {
+            Value::Float(f) => Ok(f),
+            Value::Int(i) => Ok(i as f64),
+            other => Err(mismatch("float", &other)),
+        }
+    }
+}
+
+impl FromValue for f32 {
+    fn from_value(val: Value) -> Result<f32, Error> {
+        f64::from_value(val).map(|f| f as f32)
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::EmString(self)
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::EmString(self.to_owned())
+    }
+}
+
+impl FromValue for String {
+    fn from_value(val: Value) -> Result<String, Error> {
+        match val {
+            Value::EmString(s) => Ok(s),
+            other => Err(mismatch("string", &other)),
+        }
+    }
+}
+
+//None is null, anything else is converted as usual
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        match self {
+            Some(v) => v.into_value(),
+            None => Value::Null,
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(val: Value) -> Result<Option<T>, Error> {
+        match val {
+            Value::Null => Ok(None),
+            other => T::from_value(other).map(Some),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        Value::array(self.into_iter().map(IntoValue::into_value).collect())
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(val: Value) -> Result<Vec<T>, Error> {
+        match val {
+            Value::EmArray(items) => {
+                let items = items.borrow().clone();
+                items
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, v)| {
+                        T::from_value(v)
+                            .map_err(|e| e.with_note(format!("at index {} of the array", i)))
+                    })
+                    .collect()
+            }
+            other => Err(mismatch("array", &other)),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for HashMap<String, T> {
+    fn into_value(self) -> Value {
+        let mut map = EmMap::default();
+        for (k, v) in self {
+            map.entries.insert(MapKey::Str(k), v.into_value());
+        }
+        Value::map(map)
+    }
+}
+
+impl<T: FromValue> FromValue for HashMap<String, T> {
+    fn from_value(val: Value) -> Result<HashMap<String, T>, Error> {
+        match val {
+            Value::Map(m) => {
+                let entries = m.borrow().entries.clone();
+                entries
+                    .into_iter()
+                    .map(|(k, v)| match k {
+                        MapKey::Str(s) => {
+                            let v = T::from_value(v)
+                                .map_err(|e| e.with_note(format!("at key \"{}\" of the map", s)))?;
+                            Ok((s, v))
+                        }
+                        other => Err(mismatch("string", &other.to_value())
+                            .with_note("only maps with string keys can be converted")),
+                    })
+                    .collect()
+            }
+            other => Err(mismatch("map", &other)),
+        }
+    }
+}
+
+impl IntoArgs for Vec<Value> {
+    fn into_args(self) -> Vec<Value> {
+        self
+    }
+}
+
+impl IntoArgs for () {
+    fn into_args(self) -> Vec<Value> {
+        vec![]
+    }
+}
+
+impl FromArgs for Vec<Value> {
+    fn from_args(args: Vec<Value>) -> Result<Vec<Value>, Error> {
+        Ok(args)
+    }
+}
+
+impl FromArgs for () {
+    fn from_args(args: Vec<Value>) -> Result<(), Error> {
+        take::<0>(args).map(|_| ())
+    }
+}
+
+///Makes sure there are exactly `N` arguments
+fn take<const N: usize>(args: Vec<Value>) -> Result<[Value; N], Error> {
+    let found = args.len();
+    <[Value; N]>::try_from(args).map_err(|_| {
+        Error::runtime(
+            RuntimeErrorKind::WrongArgumentCount,
+            format!("Expected {} arguments, got {}", N, found),
+        )
+    })
+}
+
+///Tuples are passed as separate arguments, and turn into arrays when they're used as a single value
+macro_rules! tuples {
+    ($len:literal: $($name:ident),+) => {
+        impl<$($name: IntoValue),+> IntoArgs for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn into_args(self) -> Vec<Value> {
+                let ($($name,)+) = self;
+                vec![$($name.into_value()),+]
+            }
+        }
+
+        impl<$($name: FromValue),+> FromArgs for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn from_args(args: Vec<Value>) -> Result<Self, Error> {
+                let [$($name),+] = take::<$len>(args)?;
+                Ok(($($name::from_value($name)?,)+))
+            }
+        }
+
+        impl<$($name: IntoValue),+> IntoValue for ($($name,)+) {
+            fn into_value(self) -> Value {
+                Value::array(self.into_args())
+            }
+        }
+
+        impl<$($name: FromValue),+> FromValue for ($($name,)+) {
+            fn from_value(val: Value) -> Result<Self, Error> {
+                match val {
+                    Value::EmArray(items) => {
+                        let items = items.borrow().clone();
+                        let len = items.len();
+                        Self::from_args(items).map_err(|e| match e {
+                            Error::RuntimeError { kind: RuntimeErrorKind::WrongArgumentCount, .. } => {
+                                Error::runtime(
+                                    RuntimeErrorKind::TypeMismatch,
+                                    format!("Expected an array of {} elements, found {}", $len, len),
+                                )
+                            }
+                            e => e,
+                        })
+                    }
+                    other => Err(mismatch("array", &other)),
+                }
+            }
+        }
+    };
+}
+
+tuples!(1: A);
+tuples!(2: A, B);
+tuples!(3: A, B, C);
+tuples!(4: A, B, C, D);
+tuples!(5: A, B, C, D, E);
+tuples!(6: A, B, C, D, E, F);
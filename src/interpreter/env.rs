@@ -0,0 +1,154 @@
+use crate::interpreter::{StackFrame, Value};
+use crate::parser::Slot;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+///The local variables of a scope, in the slots the resolver gave them. A slot stays empty until its variable
+///is first assigned.
+pub(crate) struct Frame {
+    pub(crate) slots: RefCell<Vec<Option<Value>>>,
+    pub(crate) parent: Option<Rc<Frame>>,
+}
+
+impl Frame {
+    pub(crate) fn new(slots: usize, parent: Option<Rc<Frame>>) -> Frame {
+        Frame {
+            slots: RefCell::new(vec![None; slots]),
+            parent,
+        }
+    }
+
+    ///A function's frame, with the arguments in the first slots where the resolver put the parameters
+    pub(crate) fn with_args(slots: usize, args: Vec<Value>, parent: Option<Rc<Frame>>) -> Frame {
+        let mut vals = Vec::with_capacity(slots);
+        vals.extend(args.into_iter().map(Some));
+        vals.resize(slots, None);
+        Frame {
+            slots: RefCell::new(vals),
+            parent,
+        }
+    }
+
+    ///The frame `depth` scopes out from this one
+    pub(crate) fn ancestor(&self, depth: usize) -> &Frame {
+        let mut frame = self;
+        for _ in 0..depth {
+            frame = frame
+                .parent
+                .as_deref()
+                .expect("Variable resolved past the outermost frame");
+        }
+        frame
+    }
+
+    pub(crate) fn get(&self, slot: Slot) -> Option<Value> {
+        self.ancestor(slot.depth).slots.borrow()[slot.index].clone()
+    }
+
+    pub(crate) fn set(&self, slot: Slot, val: Value) {
+        self.ancestor(slot.depth).slots.borrow_mut()[slot.index] = Some(val);
+    }
+}
+
+///Everything the tree walker can see from where it is: the frames of the scopes it's in, innermost first,
+///and the globals by name
+#[derive(Clone)]
+pub(crate) struct Env {
+    pub(crate) locals: Option<Rc<Frame>>,
+    pub(crate) globals: StackFrame,
+}
+
+impl Env {
+    ///The top level of a script, where every variable is a global
+    pub(crate) fn global(globals: &StackFrame) -> Env {
+        Env {
+            locals: None,
+            globals: globals.clone(),
+        }
+    }
+
+    ///Enters a scope with `locals` slots. The resolver doesn't count scopes without any variables, so they
+    ///don't get a frame either.
+    pub(crate) fn scope(&self, locals: usize) -> Env {
+        if locals == 0 {
+            return self.clone();
+        }
+        Env {
+            locals: Some(Rc::new(Frame::new(locals, self.locals.clone()))),
+            globals: self.globals.clone(),
+        }
+    }
+
+    ///Enters the scope of a function that needs `locals` slots and was called with `args`
+    pub(crate) fn call(&self, locals: usize, args: Vec<Value>) -> Env {
+        if locals == 0 {
+            return self.clone();
+        }
+        Env {
+            locals: Some(Rc::new(Frame::with_args(locals, args, self.locals.clone()))),
+            globals: self.globals.clone(),
+        }
+    }
+
+    fn frame(&self) -> &Frame {
+        self.locals
+            .as_deref()
+            .expect("Local variable used outside of any scope")
+    }
+
+    ///Reads a variable, or gives `None` if it hasn't been assigned yet
+    pub(crate) fn get(&self, var: Option<Slot>, name: &str) -> Option<Value> {
+        match var {
+            Some(slot) => self.frame().get(slot),
+            None => self.globals.get_var(name),
+        }
+    }
+
+    ///Assigns to a variable, creating the global if it doesn't exist yet
+    pub(crate) fn set(&self, var: Option<Slot>, name: &str, val: Value) {
+        match var {
+            Some(slot) => self.frame().set(slot, val),
+            None => self.globals.set_var(name.to_owned(), val),
+        }
+    }
+
+    ///Like `set`, but a global is always made in the innermost global scope
+    pub(crate) fn define(&self, var: Option<Slot>, name: &str, val: Value) {
+        match var {
+            Some(slot) => self.frame().set(slot, val),
+            None => self.globals.define(name.to_owned(), val),
+        }
+    }
+}
+
+//Frames can hold functions that hold the frame, so like stack frames these only look at which one it is
+impl fmt::Debug for Env {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.locals {
+            Some(frame) => write!(f, "Env({:p}, {:?})", Rc::as_ptr(frame), self.globals),
+            None => write!(f, "Env({:?})", self.globals),
+        }
+    }
+}
+
+impl PartialEq for Env {
+    fn eq(&self, other: &Env) -> bool {
+        let same_locals = match (&self.locals, &other.locals) {
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        same_locals && self.globals == other.globals
+    }
+}
+
+impl PartialOrd for Env {
+    fn partial_cmp(&self, other: &Env) -> Option<std::cmp::Ordering> {
+        if self == other {
+            Some(std::cmp::Ordering::Equal)
+        } else {
+            None
+        }
+    }
+}
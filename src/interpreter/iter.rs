@@ -0,0 +1,118 @@
+use crate::error::{Error, RuntimeErrorKind};
+use crate::interpreter::Value;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::vec;
+
+//What for-in loops go through. The tree walker and the VM both loop with these, so they agree on what every
+//kind of value gives.
+
+///Hands out what a for-in loop goes through one at a time, along with how many came before it
+pub(crate) struct Items {
+    source: Source,
+    count: i64,
+}
+
+enum Source {
+    Array(Rc<RefCell<Vec<Value>>>, usize), //the array, the index of the next element
+    Values(vec::IntoIter<Value>),
+    Range(Option<i64>, i64, i64, bool), //the next number, end, step, whether the end is included
+}
+
+impl Items {
+    ///Goes through the elements of an array, the characters of a string, or the keys of a map or object.
+    ///Arrays are read as the loop goes, so it sees elements that get added to them while it runs.
+    pub(crate) fn new(val: Value) -> Result<Items, Error> {
+        let source = match val {
+            Value::EmArray(v) => Source::Array(v, 0),
+            Value::EmString(s) => Source::Values(
+                s.chars()
+                    .map(|c| Value::EmString(c.to_string()))
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+            ),
+            Value::Map(m) => Source::Values(m.borrow().keys().into_iter()),
+            Value::Object(o) => Source::Values(
+                o.borrow()
+                    .data_members()
+                    .into_iter()
+                    .map(|(k, _)| Value::EmString(k.clone()))
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+            ),
+            other => {
+                return Err(Error::runtime(
+                    RuntimeErrorKind::TypeMismatch,
+                    format!("Can't loop over {}", other.type_name()),
+                )
+                .with_note("for-in loops go through arrays, strings, maps, objects, and ranges"))
+            }
+        };
+        Ok(Items { source, count: 0 })
+    }
+
+    ///Counts from `start` up to `end` by `step`, or down to it if `step` is negative. A null step counts by one.
+    pub(crate) fn range(
+        start: Value,
+        end: Value,
+        step: Value,
+        inclusive: bool,
+    ) -> Result<Items, Error> {
+        let step = match step {
+            Value::Null => 1,
+            Value::Int(0) => {
+                return Err(Error::runtime(
+                    RuntimeErrorKind::InvalidOperation,
+                    "A range can't have a step of 0",
+                ))
+            }
+            other => range_int(other)?,
+        };
+        Ok(Items {
+            source: Source::Range(Some(range_int(start)?), range_int(end)?, step, inclusive),
+            count: 0,
+        })
+    }
+}
+
+impl Iterator for Items {
+    type Item = (i64, Value);
+
+    fn next(&mut self) -> Option<(i64, Value)> {
+        let item = match &mut self.source {
+            Source::Array(v, i) => {
+                let item = v.borrow().get(*i).cloned()?;
+                *i += 1;
+                item
+            }
+            Source::Values(vals) => vals.next()?,
+            Source::Range(next, end, step, inclusive) => {
+                let n = (*next)?;
+                let more = match (*step > 0, *inclusive) {
+                    (true, true) => n <= *end,
+                    (true, false) => n < *end,
+                    (false, true) => n >= *end,
+                    (false, false) => n > *end,
+                };
+                if !more {
+                    return None;
+                }
+                //a range that would go past the biggest integer stops instead
+                *next = n.checked_add(*step);
+                Value::Int(n)
+            }
+        };
+        self.count += 1;
+        Some((self.count - 1, item))
+    }
+}
+
+fn range_int(val: Value) -> Result<i64, Error> {
+    match val {
+        Value::Int(i) => Ok(i),
+        other => Err(Error::runtime(
+            RuntimeErrorKind::TypeMismatch,
+            format!("Ranges are made of integers, found {}", other),
+        )),
+    }
+}
@@ -0,0 +1,432 @@
+use crate::error::{Error, RuntimeErrorKind};
+use crate::interpreter::types::{EmMap, MapKey};
+use crate::interpreter::{Runtime, Value};
+use std::iter::Peekable;
+use std::str::Chars;
+
+//Reading and writing JSON for the json.parse and json.stringify builtins
+
+///How deeply arrays and objects can be nested, so bad input or an array that holds itself can't overflow the stack
+const MAX_DEPTH: usize = 512;
+
+///Reads a JSON document. Objects become maps, and numbers are integers unless they have a fraction or an
+///exponent, or are too big to be one
+pub fn parse(src: &str) -> Result<Value, Error> {
+    let mut p = Parser {
+        chars: src.chars().peekable(),
+        line: 1,
+        col: 1,
+        depth: 0,
+    };
+    p.skip_whitespace();
+    let val = p.value()?;
+    p.skip_whitespace();
+    match p.peek() {
+        None => Ok(val),
+        Some(c) => Err(p.error(format!("Unexpected {:?} after the end of the JSON", c))),
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    col: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, message: impl Into<String>) -> Error {
+        Error::runtime(RuntimeErrorKind::InvalidJson, message).with_note(format!(
+            "at line {}, column {} of the JSON",
+            self.line, self.col
+        ))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(
+            self.peek(),
+            Some(' ') | Some('\t') | Some('\n') | Some('\r')
+        ) {
+            self.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), Error> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(self.error(format!("Expected {:?}, found {:?}", expected, c))),
+            None => Err(self.error(format!(
+                "Expected {:?}, found the end of the JSON",
+                expected
+            ))),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, Error> {
+        match self.peek() {
+            Some('{') => self.nested(Parser::object),
+            Some('[') => self.nested(Parser::array),
+            Some('"') => self.string().map(Value::EmString),
+            Some('t') => self.word("true", Value::EmBool(true)),
+            Some('f') => self.word("false", Value::EmBool(false)),
+            Some('n') => self.word("null", Value::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(c) => Err(self.error(format!("Unexpected {:?}", c))),
+            None => Err(self.error("Unexpected end of the JSON")),
+        }
+    }
+
+    ///Reads an array or object, keeping track of how deep we are
+    fn nested(&mut self, read: fn(&mut Self) -> Result<Value, Error>) -> Result<Value, Error> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(self.error("JSON is nested too deeply"));
+        }
+        let res = read(self);
+        self.depth -= 1;
+        res
+    }
+
+    fn word(&mut self, word: &str, val: Value) -> Result<Value, Error> {
+        for c in word.chars() {
+            self.expect(c)?;
+        }
+        Ok(val)
+    }
+
+    fn array(&mut self) -> Result<Value, Error> {
+        self.expect('[')?;
+        self.skip_whitespace();
+        let mut items = vec![];
+        if self.peek() == Some(']') {
+            self.next();
+            return Ok(Value::array(items));
+        }
+        loop {
+            self.skip_whitespace();
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(Value::array(items)),
+                Some(c) => return Err(self.error(format!("Expected ',' or ']', found {:?}", c))),
+                None => return Err(self.error("Unterminated array")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, Error> {
+        self.expect('{')?;
+        self.skip_whitespace();
+        let mut map = EmMap::default();
+        if self.peek() == Some('}') {
+            self.next();
+            return Ok(Value::map(map));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.error("Expected a string for the key"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            self.skip_whitespace();
+            let val = self.value()?;
+            map.entries.insert(MapKey::Str(key), val);
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(Value::map(map)),
+                Some(c) => return Err(self.error(format!("Expected ',' or '}}', found {:?}", c))),
+                None => return Err(self.error("Unterminated object")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => s.push(self.escape()?),
+                Some(c) if (c as u32) < 0x20 => {
+                    return Err(self.error("Control characters in strings have to be escaped"))
+                }
+                Some(c) => s.push(c),
+                None => return Err(self.error("Unterminated string")),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, Error> {
+        match self.next() {
+            Some('"') => Ok('"'),
+            Some('\\') => Ok('\\'),
+            Some('/') => Ok('/'),
+            Some('b') => Ok('\u{8}'),
+            Some('f') => Ok('\u{c}'),
+            Some('n') => Ok('\n'),
+            Some('r') => Ok('\r'),
+            Some('t') => Ok('\t'),
+            Some('u') => {
+                let first = self.hex4()?;
+                //characters outside the basic plane are written as two escapes, called a surrogate pair
+                let code = if (0xD800..0xDC00).contains(&first) {
+                    if self.next() != Some('\\') || self.next() != Some('u') {
+                        return Err(self.error("Expected the second half of a surrogate pair"));
+                    }
+                    let second = self.hex4()?;
+                    if !(0xDC00..0xE000).contains(&second) {
+                        return Err(self.error("Expected the second half of a surrogate pair"));
+                    }
+                    0x10000 + ((first - 0xD800) << 10) + (second - 0xDC00)
+                } else {
+                    first
+                };
+                char::from_u32(code)
+                    .ok_or_else(|| self.error(format!("Invalid unicode escape {:04x}", code)))
+            }
+            Some(c) => Err(self.error(format!("Unknown escape \\{}", c))),
+            None => Err(self.error("Unterminated string")),
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, Error> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self.next().and_then(|c| c.to_digit(16));
+            code =
+                code * 16 + digit.ok_or_else(|| self.error("Expected 4 hex digits after \\u"))?;
+        }
+        Ok(code)
+    }
+
+    fn number(&mut self) -> Result<Value, Error> {
+        let mut text = String::new();
+        let mut float = false;
+        while let Some(c) = self.peek() {
+            match c {
+                '0'..='9' | '-' | '+' => {}
+                '.' | 'e' | 'E' => float = true,
+                _ => break,
+            }
+            text.push(c);
+            self.next();
+        }
+        if !valid_number(&text) {
+            return Err(self.error(format!("Invalid number {}", text)));
+        }
+        if !float {
+            if let Ok(i) = text.parse::<i64>() {
+                return Ok(Value::Int(i));
+            }
+        }
+        text.parse::<f64>()
+            .map(Value::Float)
+            .map_err(|_| self.error(format!("Invalid number {}", text)))
+    }
+}
+
+///Checks that `text` is a number JSON allows, since rust is happy to parse things like `+1` and `01`
+fn valid_number(text: &str) -> bool {
+    let b = text.as_bytes();
+    let mut i = 0;
+    let digits = |i: &mut usize| {
+        let start = *i;
+        while *i < b.len() && b[*i].is_ascii_digit() {
+            *i += 1;
+        }
+        *i - start
+    };
+
+    if b.first() == Some(&b'-') {
+        i += 1;
+    }
+    let int_start = i;
+    let n = digits(&mut i);
+    if n == 0 || (n > 1 && b[int_start] == b'0') {
+        return false;
+    }
+    if b.get(i) == Some(&b'.') {
+        i += 1;
+        if digits(&mut i) == 0 {
+            return false;
+        }
+    }
+    if matches!(b.get(i), Some(b'e') | Some(b'E')) {
+        i += 1;
+        if matches!(b.get(i), Some(b'+') | Some(b'-')) {
+            i += 1;
+        }
+        if digits(&mut i) == 0 {
+            return false;
+        }
+    }
+    i == b.len()
+}
+
+///The most spaces json.stringify can indent by, the same as JavaScript allows
+pub const MAX_INDENT: usize = 10;
+
+///Writes a value as JSON, putting each element on its own line indented by `indent` spaces if it's given. What's
+///written is counted against the runtime's allocation limit as it goes, since an array that's in a value more
+///than once is written out every time.
+pub fn stringify(rt: &mut Runtime, val: &Value, indent: Option<usize>) -> Result<String, Error> {
+    let mut w = Writer {
+        rt,
+        out: String::new(),
+        charged: 0,
+        indent,
+    };
+    w.value(val, 0)?;
+    w.charge()?;
+    Ok(w.out)
+}
+
+struct Writer<'a> {
+    rt: &'a mut Runtime,
+    out: String,
+    charged: usize, //how much of `out` has been counted against the allocation limit
+    indent: Option<usize>,
+}
+
+impl Writer<'_> {
+    ///Counts everything written since the last time against the allocation limit
+    fn charge(&mut self) -> Result<(), Error> {
+        self.rt.allocate(self.out.len() - self.charged)?;
+        self.charged = self.out.len();
+        Ok(())
+    }
+
+    fn value(&mut self, val: &Value, depth: usize) -> Result<(), Error> {
+        if depth > MAX_DEPTH {
+            return Err(Error::runtime(
+                RuntimeErrorKind::InvalidJson,
+                "Value is nested too deeply to turn into JSON",
+            )
+            .with_note("this can happen when an array or map contains itself"));
+        }
+        let out = &mut self.out;
+        match val {
+            Value::Null => out.push_str("null"),
+            Value::EmBool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Value::Int(i) => out.push_str(&i.to_string()),
+            //debug formatting always keeps the decimal point, so the number is still a float when it's read back
+            Value::Float(f) if f.is_finite() => out.push_str(&format!("{:?}", f)),
+            Value::EmString(s) | Value::Name(s) => write_string(out, s),
+            Value::EmArray(items) => {
+                let items = items.borrow();
+                let entries: Vec<(Option<String>, &Value)> =
+                    items.iter().map(|v| (None, v)).collect();
+                self.container(('[', ']'), &entries, depth)?;
+            }
+            Value::Map(m) => {
+                let m = m.borrow();
+                //JSON keys are always strings, so any other key is written the way it prints
+                let entries: Vec<(Option<String>, &Value)> = m
+                    .entries
+                    .iter()
+                    .map(|(k, v)| (Some(k.to_value().to_string()), v))
+                    .collect();
+                self.container(('{', '}'), &entries, depth)?;
+            }
+            Value::Object(o) => {
+                let o = o.borrow();
+                let entries: Vec<(Option<String>, &Value)> = o
+                    .data_members()
+                    .into_iter()
+                    .map(|(k, v)| (Some(k.clone()), v))
+                    .collect();
+                self.container(('{', '}'), &entries, depth)?;
+            }
+            other => {
+                return Err(Error::runtime(
+                    RuntimeErrorKind::TypeMismatch,
+                    format!("Can't turn {} into JSON", other),
+                ))
+            }
+        }
+        self.charge()
+    }
+
+    fn container(
+        &mut self,
+        (open, close): (char, char),
+        entries: &[(Option<String>, &Value)],
+        depth: usize,
+    ) -> Result<(), Error> {
+        self.out.push(open);
+        for (i, (key, val)) in entries.iter().enumerate() {
+            if i > 0 {
+                self.out.push(',');
+            }
+            self.new_line(depth + 1)?;
+            if let Some(key) = key {
+                write_string(&mut self.out, key);
+                self.out.push(':');
+                if self.indent.is_some() {
+                    self.out.push(' ');
+                }
+            }
+            self.value(val, depth + 1)?;
+        }
+        if !entries.is_empty() {
+            self.new_line(depth)?;
+        }
+        self.out.push(close);
+        Ok(())
+    }
+
+    ///Starts a new line indented for `depth` if there's an indent, counting the spaces before they're added
+    fn new_line(&mut self, depth: usize) -> Result<(), Error> {
+        if let Some(n) = self.indent {
+            let width = n.checked_mul(depth).ok_or_else(|| {
+                Error::runtime(
+                    RuntimeErrorKind::InvalidOperation,
+                    "JSON is indented too far to write",
+                )
+            })?;
+            self.charge()?;
+            self.rt.allocate(width + 1)?;
+            self.out.push('\n');
+            self.out.push_str(&" ".repeat(width));
+            self.charged = self.out.len();
+        }
+        Ok(())
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
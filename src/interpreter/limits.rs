@@ -0,0 +1,150 @@
+use crate::error::{Error, RuntimeErrorKind};
+use std::time::{Duration, Instant};
+
+///How deeply calls can nest unless the host picks something else
+pub const DEFAULT_CALL_DEPTH: usize = 10_000;
+
+///How much of the thread's stack has to be left for another call to start. A level of calls in the tree
+///walker takes about 5 KiB of stack in an optimized build and 50 KiB in a debug build, and builtins that call
+///back into scripts need room on top of that.
+const STACK_RED_ZONE: usize = 256 * 1024;
+
+///Bounds on how much work a script can do, for running code that can't be trusted. A script that goes over
+///one stops with a `LimitExceeded` error instead of hanging or crashing the host. By default only the call
+///depth is limited, everything else is unbounded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    ///The most steps a script can take, which bounds loops that never end. A step is a node for the tree
+    ///walker and an instruction for the VM, so the same script takes a different number of steps on each.
+    pub max_steps: Option<u64>,
+    ///How deeply function calls can nest, `DEFAULT_CALL_DEPTH` by default. Calls also stop once the stack
+    ///of the thread running the script is nearly used up, so on Linux no value can overflow it. Elsewhere the
+    ///stack isn't checked, and the value is only safe if the thread has 5 KiB of stack per level in an
+    ///optimized build, or 50 KiB in a debug build. The default needs 50 MiB in an optimized build.
+    pub max_call_depth: Option<usize>,
+    ///How long a script can run for
+    pub max_duration: Option<Duration>,
+    ///Roughly how many bytes can be spent creating strings, arrays, maps, and objects
+    pub max_allocation: Option<usize>,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_steps: None,
+            max_call_depth: Some(DEFAULT_CALL_DEPTH),
+            max_duration: None,
+            max_allocation: None,
+        }
+    }
+}
+
+///Keeps track of what a script has used so far, counted from the last reset
+#[derive(Debug, Default)]
+pub(crate) struct Budget {
+    pub limits: Limits,
+    steps: u64,
+    depth: usize,
+    allocated: usize,
+    started: Option<Instant>,
+}
+
+fn exceeded(message: String) -> Error {
+    Error::runtime(RuntimeErrorKind::LimitExceeded, message)
+}
+
+impl Budget {
+    ///Starts counting from zero again. Calls that are still running keep their depth
+    pub fn reset(&mut self) {
+        self.steps = 0;
+        self.allocated = 0;
+        self.started = None;
+    }
+
+    ///Counts one evaluated node or instruction, also checking the clock
+    pub fn step(&mut self) -> Result<(), Error> {
+        self.steps += 1;
+        if let Some(max) = self.limits.max_steps {
+            if self.steps > max {
+                return Err(exceeded(format!("Script ran for more than {} steps", max)));
+            }
+        }
+        if let Some(max) = self.limits.max_duration {
+            //the clock starts on the first step, so time spent before the script runs doesn't count
+            let started = *self.started.get_or_insert_with(Instant::now);
+            if started.elapsed() > max {
+                return Err(exceeded(format!("Script ran for longer than {:?}", max)));
+            }
+        }
+        Ok(())
+    }
+
+    ///Called before a function body runs, which has to be matched by `exit_call`
+    pub fn enter_call(&mut self) -> Result<(), Error> {
+        if let Some(max) = self.limits.max_call_depth {
+            if self.depth >= max {
+                return Err(
+                    exceeded(format!("Function calls nested more than {} deep", max))
+                        .with_note("this usually means a function keeps calling itself"),
+                );
+            }
+        }
+        if remaining_stack().is_some_and(|left| left < STACK_RED_ZONE) {
+            return Err(exceeded("Function calls used up the stack".to_owned())
+                .with_note("this usually means a function keeps calling itself"));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    pub fn exit_call(&mut self) {
+        self.depth -= 1;
+    }
+
+    pub fn allocate(&mut self, bytes: usize) -> Result<(), Error> {
+        self.allocated = self.allocated.saturating_add(bytes);
+        match self.limits.max_allocation {
+            Some(max) if self.allocated > max => Err(exceeded(format!(
+                "Script allocated more than {} bytes",
+                max
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+thread_local! {
+    //the lowest address of this thread's stack, which it grows down towards
+    static STACK_END: Option<usize> = stack_end();
+}
+
+///How many bytes of stack the current thread has left, if that can be found out
+fn remaining_stack() -> Option<usize> {
+    let here = 0u8;
+    let here = &here as *const u8 as usize;
+    STACK_END.with(|end| end.map(|end| here.saturating_sub(end)))
+}
+
+#[cfg(target_os = "linux")]
+fn stack_end() -> Option<usize> {
+    let mut addr = std::ptr::null_mut();
+    let mut size = 0;
+    //SAFETY: the attributes are initialized by pthread_getattr_np before they're read, and destroyed after
+    unsafe {
+        let mut attr = std::mem::zeroed();
+        if libc::pthread_getattr_np(libc::pthread_self(), &mut attr) != 0 {
+            return None;
+        }
+        let found = libc::pthread_attr_getstack(&attr, &mut addr, &mut size);
+        libc::pthread_attr_destroy(&mut attr);
+        if found != 0 {
+            return None;
+        }
+    }
+    Some(addr as usize)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn stack_end() -> Option<usize> {
+    None
+}
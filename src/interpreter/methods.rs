@@ -0,0 +1,425 @@
+use crate::error::{Error, RuntimeErrorKind};
+use crate::interpreter::types::EmMap;
+use crate::interpreter::{compare, equal, wrong_arg_count, Runtime, Value};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::convert::TryInto;
+use std::mem;
+use std::rc::Rc;
+
+//Methods for the built in types, which can be called like the methods of an object, e.g. `m.keys()`
+
+///Calls the method `name` on a map
+pub fn map_method(
+    rt: &mut Runtime,
+    map: &Rc<RefCell<EmMap>>,
+    name: &str,
+    args: Vec<Value>,
+) -> Result<Value, Error> {
+    let res = match name {
+        "has" => {
+            let [key] = take_args("map.has", args)?;
+            Value::EmBool(map.borrow().has(&key)?)
+        }
+        "remove" => {
+            let [key] = take_args("map.remove", args)?;
+            return map.borrow_mut().remove(&key);
+        }
+        "keys" => {
+            let [] = take_args("map.keys", args)?;
+            Value::array(map.borrow().keys())
+        }
+        "values" => {
+            let [] = take_args("map.values", args)?;
+            Value::array(map.borrow().values())
+        }
+        "len" => {
+            let [] = take_args("map.len", args)?;
+            Value::Int(map.borrow().entries.len() as i64)
+        }
+        _ => return Err(no_method("map", name)),
+    };
+    rt.allocate(size_of(&res))?;
+    Ok(res)
+}
+
+///Calls the method `name` on a string. Strings are counted and indexed by character rather than by byte, so
+///nothing can cut one in the middle of a character.
+pub fn string_method(
+    rt: &mut Runtime,
+    s: &str,
+    name: &str,
+    args: Vec<Value>,
+) -> Result<Value, Error> {
+    let res = match name {
+        "len" => {
+            let [] = take_args("string.len", args)?;
+            Value::Int(s.chars().count() as i64)
+        }
+        "split" => {
+            let [sep] = take_args("string.split", args)?;
+            let sep = string_arg("string.split", sep)?;
+            if sep.is_empty() {
+                return Err(Error::runtime(
+                    RuntimeErrorKind::InvalidOperation,
+                    "Can't split a string on an empty separator",
+                )
+                .with_note("use chars() to get the characters of a string"));
+            }
+            Value::array(
+                s.split(&sep)
+                    .map(|p| Value::EmString(p.to_owned()))
+                    .collect(),
+            )
+        }
+        "join" => {
+            let [items] = take_args("string.join", args)?;
+            match items {
+                Value::EmArray(v) => {
+                    //showing an object can run code that changes the array
+                    let items = v.borrow().clone();
+                    let parts = items
+                        .iter()
+                        .map(|item| rt.display(item))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    Value::EmString(parts.join(s))
+                }
+                other => return Err(wrong_type("string.join", "an array", &other)),
+            }
+        }
+        "trim" => {
+            let [] = take_args("string.trim", args)?;
+            Value::EmString(s.trim().to_owned())
+        }
+        "replace" => {
+            let [from, to] = take_args("string.replace", args)?;
+            let from = string_arg("string.replace", from)?;
+            let to = string_arg("string.replace", to)?;
+            Value::EmString(s.replace(&from, &to))
+        }
+        "find" => {
+            //gives the character the match starts at, or -1 if there isn't one
+            let [sub] = take_args("string.find", args)?;
+            let sub = string_arg("string.find", sub)?;
+            let found = s.find(&sub).map(|i| s[..i].chars().count() as i64);
+            Value::Int(found.unwrap_or(-1))
+        }
+        "starts_with" => {
+            let [prefix] = take_args("string.starts_with", args)?;
+            let prefix = string_arg("string.starts_with", prefix)?;
+            Value::EmBool(s.starts_with(&prefix))
+        }
+        "upper" => {
+            let [] = take_args("string.upper", args)?;
+            Value::EmString(s.to_uppercase())
+        }
+        "lower" => {
+            let [] = take_args("string.lower", args)?;
+            Value::EmString(s.to_lowercase())
+        }
+        "chars" => {
+            let [] = take_args("string.chars", args)?;
+            Value::array(s.chars().map(|c| Value::EmString(c.to_string())).collect())
+        }
+        "slice" => {
+            let len = s.chars().count();
+            let (start, end) = slice_range("string.slice", args, len)?;
+            Value::EmString(s.chars().skip(start).take(end - start).collect())
+        }
+        _ => return Err(no_method("string", name)),
+    };
+    rt.allocate(size_of(&res))?;
+    Ok(res)
+}
+
+///Calls the method `name` on an array. The array isn't borrowed while functions passed to it run, so they can
+///look at it or change it without anything going wrong.
+pub fn array_method(
+    rt: &mut Runtime,
+    array: &Rc<RefCell<Vec<Value>>>,
+    name: &str,
+    args: Vec<Value>,
+) -> Result<Value, Error> {
+    let len = array.borrow().len();
+    let res = match name {
+        "len" => {
+            let [] = take_args("array.len", args)?;
+            Value::Int(len as i64)
+        }
+        "push" => {
+            let [val] = take_args("array.push", args)?;
+            rt.allocate(mem::size_of::<Value>())?;
+            array.borrow_mut().push(val);
+            Value::Null
+        }
+        "pop" => {
+            let [] = take_args("array.pop", args)?;
+            return array.borrow_mut().pop().ok_or_else(|| {
+                Error::runtime(
+                    RuntimeErrorKind::IndexOutOfBounds,
+                    "Can't pop from an empty array",
+                )
+            });
+        }
+        "insert" => {
+            let [index, val] = take_args("array.insert", args)?;
+            //inserting can also go right after the last element
+            let index = match index {
+                Value::Int(i) if i == len as i64 => len,
+                other => index_arg("array.insert", other, len)?,
+            };
+            rt.allocate(mem::size_of::<Value>())?;
+            array.borrow_mut().insert(index, val);
+            Value::Null
+        }
+        "remove" => {
+            let [index] = take_args("array.remove", args)?;
+            let index = index_arg("array.remove", index, len)?;
+            return Ok(array.borrow_mut().remove(index));
+        }
+        "slice" => {
+            let (start, end) = slice_range("array.slice", args, len)?;
+            Value::array(array.borrow()[start..end].to_vec())
+        }
+        "concat" => {
+            let [other] = take_args("array.concat", args)?;
+            match other {
+                Value::EmArray(other) => {
+                    let mut items = array.borrow().clone();
+                    items.extend(other.borrow().iter().cloned());
+                    Value::array(items)
+                }
+                other => return Err(wrong_type("array.concat", "an array", &other)),
+            }
+        }
+        "reverse" => {
+            let [] = take_args("array.reverse", args)?;
+            array.borrow_mut().reverse();
+            Value::Null
+        }
+        "sort" => {
+            let comparator = match args.len() {
+                0 => None,
+                _ => {
+                    let [f] = take_args("array.sort", args)?;
+                    Some(f)
+                }
+            };
+            let items = array.borrow().clone();
+            *array.borrow_mut() = sort(rt, items, comparator.as_ref())?;
+            Value::Null
+        }
+        "contains" => {
+            let [val] = take_args("array.contains", args)?;
+            Value::EmBool(array.borrow().iter().any(|v| equal(v, &val)))
+        }
+        "index_of" => {
+            //gives -1 if the value isn't in the array, like `string.find`
+            let [val] = take_args("array.index_of", args)?;
+            let found = array.borrow().iter().position(|v| equal(v, &val));
+            Value::Int(found.map_or(-1, |i| i as i64))
+        }
+        "map" => {
+            let [f] = take_args("array.map", args)?;
+            let items = array.borrow().clone();
+            let mapped = items
+                .into_iter()
+                .map(|v| rt.call_function(&f, vec![v]))
+                .collect::<Result<Vec<_>, _>>()?;
+            Value::array(mapped)
+        }
+        "filter" => {
+            let [f] = take_args("array.filter", args)?;
+            let items = array.borrow().clone();
+            let mut kept = vec![];
+            for v in items {
+                //like conditions, anything but `true` leaves the element out
+                if rt.call_function(&f, vec![v.clone()])? == Value::EmBool(true) {
+                    kept.push(v);
+                }
+            }
+            Value::array(kept)
+        }
+        "reduce" => {
+            //without a starting value the first element is used, so there has to be one
+            let mut items = array.borrow().clone().into_iter();
+            let (f, mut acc) = match args.len() {
+                1 => {
+                    let [f] = take_args("array.reduce", args)?;
+                    let first = items.next().ok_or_else(|| {
+                        Error::runtime(
+                            RuntimeErrorKind::InvalidOperation,
+                            "Can't reduce an empty array without a starting value",
+                        )
+                        .with_note("pass one as the second argument, like reduce(f, 0)")
+                    })?;
+                    (f, first)
+                }
+                _ => {
+                    let [f, init] = take_args("array.reduce", args)?;
+                    (f, init)
+                }
+            };
+            for v in items {
+                acc = rt.call_function(&f, vec![acc, v])?;
+            }
+            return Ok(acc);
+        }
+        _ => return Err(no_method("array", name)),
+    };
+    rt.allocate(size_of(&res))?;
+    Ok(res)
+}
+
+///Sorts `items`, either in their natural order or by calling `comparator` with two of them, which gives back a
+///number that's negative, zero, or positive when the first goes before, with, or after the second
+fn sort(
+    rt: &mut Runtime,
+    items: Vec<Value>,
+    comparator: Option<&Value>,
+) -> Result<Vec<Value>, Error> {
+    merge_sort(items, &mut |a, b| match comparator {
+        Some(f) => match rt.call_function(f, vec![a.clone(), b.clone()])? {
+            Value::Int(i) => Ok(i.cmp(&0)),
+            Value::Float(f) => Ok(f.partial_cmp(&0.0).unwrap_or(Ordering::Equal)),
+            other => Err(Error::runtime(
+                RuntimeErrorKind::TypeMismatch,
+                format!(
+                    "array.sort comparator has to return a number, returned {}",
+                    other
+                ),
+            )),
+        },
+        None => natural_order(a, b).ok_or_else(|| {
+            Error::runtime(
+                RuntimeErrorKind::TypeMismatch,
+                format!("Can't sort {} and {}", a, b),
+            )
+            .with_note("pass a function to sort() to say how they go in order")
+        }),
+    })
+}
+
+///A stable merge sort that stops at the first error. The standard library's sorts can panic when the order
+///they're given isn't consistent, which a script's comparator doesn't have to be.
+fn merge_sort<F>(mut items: Vec<Value>, order: &mut F) -> Result<Vec<Value>, Error>
+where
+    F: FnMut(&Value, &Value) -> Result<Ordering, Error>,
+{
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let right = items.split_off(items.len() / 2);
+    let left = merge_sort(items, order)?;
+    let mut right = merge_sort(right, order)?.into_iter().peekable();
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    for l in left {
+        //elements on the right only go first if they're strictly less, which keeps equal ones in order
+        while let Some(r) = right.peek() {
+            if order(r, &l)? != Ordering::Less {
+                break;
+            }
+            merged.extend(right.next());
+        }
+        merged.push(l);
+    }
+    merged.extend(right);
+    Ok(merged)
+}
+
+///The order values are sorted in without a comparator, which only puts numbers and strings in order with
+///others of their kind
+fn natural_order(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::EmString(x), Value::EmString(y)) => Some(x.cmp(y)),
+        (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => compare(a, b),
+        _ => None,
+    }
+}
+
+///Reads an index argument, which has to point at one of the `len` elements of an array
+fn index_arg(name: &str, val: Value, len: usize) -> Result<usize, Error> {
+    let i = int_arg(name, val)?;
+    if i < 0 || i >= len as i64 {
+        return Err(Error::runtime(
+            RuntimeErrorKind::IndexOutOfBounds,
+            format!("Index {} out of bounds", i),
+        )
+        .with_note(format!("the array has {} elements", len)));
+    }
+    Ok(i as usize)
+}
+
+///Reads the arguments of a `slice` method, which are the start and optionally the end of the range. The end
+///defaults to `len`, and the range has to fit inside it.
+fn slice_range(name: &str, args: Vec<Value>, len: usize) -> Result<(usize, usize), Error> {
+    let (start, end) = match args.len() {
+        1 => {
+            let [start] = take_args(name, args)?;
+            (int_arg(name, start)?, len as i64)
+        }
+        _ => {
+            let [start, end] = take_args(name, args)?;
+            (int_arg(name, start)?, int_arg(name, end)?)
+        }
+    };
+    if start < 0 || end < start || end > len as i64 {
+        return Err(Error::runtime(
+            RuntimeErrorKind::IndexOutOfBounds,
+            format!("Range {}..{} out of bounds", start, end),
+        )
+        .with_note(format!("the length is {}", len)));
+    }
+    Ok((start as usize, end as usize))
+}
+
+///Roughly how many bytes a method's result takes up, to count against the allocation limit. Only what got
+///copied counts, so an array is its elements and the strings among them, since any arrays in it are shared.
+fn size_of(val: &Value) -> usize {
+    let string_len = |v: &Value| match v {
+        Value::EmString(s) => s.len(),
+        _ => 0,
+    };
+    match val {
+        Value::EmArray(v) => v
+            .borrow()
+            .iter()
+            .map(|v| mem::size_of::<Value>() + string_len(v))
+            .sum(),
+        other => string_len(other),
+    }
+}
+
+fn string_arg(name: &str, val: Value) -> Result<String, Error> {
+    match val {
+        Value::EmString(s) => Ok(s),
+        other => Err(wrong_type(name, "a string", &other)),
+    }
+}
+
+fn int_arg(name: &str, val: Value) -> Result<i64, Error> {
+    match val {
+        Value::Int(i) => Ok(i),
+        other => Err(wrong_type(name, "an integer", &other)),
+    }
+}
+
+fn wrong_type(name: &str, expected: &str, found: &Value) -> Error {
+    Error::runtime(
+        RuntimeErrorKind::TypeMismatch,
+        format!("{} expects {}, found {}", name, expected, found.type_name()),
+    )
+}
+
+///Makes sure a method got exactly `N` arguments and hands them back so they can be destructured
+fn take_args<const N: usize>(name: &str, args: Vec<Value>) -> Result<[Value; N], Error> {
+    let found = args.len();
+    args.try_into().map_err(|_| wrong_arg_count(name, N, found))
+}
+
+fn no_method(ty: &str, name: &str) -> Error {
+    Error::runtime(
+        RuntimeErrorKind::MissingProperty,
+        format!("{} has no method {}", ty, name),
+    )
+}
@@ -1,212 +1,713 @@
+mod builtins;
+mod capabilities;
+mod convert;
+mod env;
+mod iter;
+mod json;
+mod limits;
+mod methods;
+mod resolver;
+#[cfg(feature = "serde")]
+mod serialize;
+mod streams;
 #[cfg(test)]
 mod tests;
 mod types;
-mod builtins;
+mod vm;
+
+pub use capabilities::{Capabilities, FsAccess};
+pub use convert::{FromArgs, FromValue, IntoArgs, IntoValue};
+pub use limits::{Limits, DEFAULT_CALL_DEPTH};
+#[cfg(feature = "serde")]
+pub use serialize::{from_value, to_value};
+pub use streams::OutputBuffer;
+pub use vm::{Backend, Closure};
 
+use crate::interpreter::env::Env;
+use crate::interpreter::iter::Items;
+use crate::interpreter::limits::Budget;
+use crate::interpreter::streams::Streams;
+use crate::interpreter::types::EmMap;
 use crate::interpreter::types::EmObject;
 use crate::interpreter::types::Indexable;
+use crate::interpreter::types::MapKey;
 
 use super::lexer::Expression;
-use super::parser::ExprNode;
+use super::parser::{ExprNode, Node, Slot};
+use crate::error::{Error, RuntimeErrorKind};
 
+use std::cmp::Ordering;
+use std::convert::TryFrom;
 use std::fmt;
+use std::io::{Read, Write};
+use std::mem;
+use std::rc::Rc;
 use std::{cell::RefCell, collections::HashMap};
 
 ///Represents everything that exists in the language currently
-#[derive(Debug, Clone, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, PartialOrd)]
 pub enum Value {
     Null,
-    Float(f32),
+    Int(i64),
+    Float(f64),
     EmString(String),
     EmBool(bool),
-    EmArray(Vec<Box<Value>>),
+    EmArray(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<EmMap>>),
     //Char(u8),
     Name(String),
-    Function(Expression, Vec<Value>, ExprNode),
-    Object(EmObject),
+    Function(Rc<Function>),
+    Closure(Rc<Closure>), //a function compiled for the VM
+    Object(Rc<RefCell<EmObject>>),
 }
 
+///A function run by the tree walker, along with the scope it was defined in
+#[derive(Debug, PartialEq, PartialOrd)]
+pub struct Function {
+    pub name: Expression,
+    pub params: Vec<Value>,
+    pub body: Node,
+    pub(crate) locals: usize, //how many slots its frame needs, including the parameters
+    pub(crate) env: Env,
+}
+
+//Arrays and objects are reference types, so copying the value only copies the reference
+impl Value {
+    ///Wraps `items` in a new array
+    pub fn array(items: Vec<Value>) -> Value {
+        Value::EmArray(Rc::new(RefCell::new(items)))
+    }
+
+    ///Wraps `map` so it can be shared between variables
+    pub fn map(map: EmMap) -> Value {
+        Value::Map(Rc::new(RefCell::new(map)))
+    }
+
+    ///Wraps `obj` so it can be shared between variables
+    pub fn object(obj: EmObject) -> Value {
+        Value::Object(Rc::new(RefCell::new(obj)))
+    }
+
+    ///Wraps a function defined in `env` so it can be shared between variables
+    pub(crate) fn function(
+        name: Expression,
+        params: Vec<Value>,
+        body: Node,
+        locals: usize,
+        env: &Env,
+    ) -> Value {
+        Value::Function(Rc::new(Function {
+            name,
+            params,
+            body,
+            locals,
+            env: env.clone(),
+        }))
+    }
+
+    ///How many arguments a function takes, or `None` if the value isn't a function
+    pub(crate) fn arity(&self) -> Option<usize> {
+        match self {
+            Value::Function(func) => Some(func.params.len()),
+            Value::Closure(c) => Some(c.arity()),
+            _ => None,
+        }
+    }
+    ///The name scripts know this kind of value by, used when complaining about types
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::EmString(_) => "string",
+            Value::EmBool(_) => "bool",
+            Value::EmArray(_) => "array",
+            Value::Map(_) => "map",
+            Value::Name(_) => "name",
+            Value::Function(..) | Value::Closure(_) => "function",
+            Value::Object(_) => "object",
+        }
+    }
+}
+
+//Arrays, maps, and objects can end up holding themselves, so comparing and showing them keeps track of the ones it's
+//already in the middle of rather than following them around forever
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Null, Value::Null) => true,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::EmString(a), Value::EmString(b)) | (Value::Name(a), Value::Name(b)) => a == b,
+            (Value::EmBool(a), Value::EmBool(b)) => a == b,
+            (Value::EmArray(a), Value::EmArray(b)) => compare_shared(a, b, true, |a, b| a == b),
+            (Value::Map(a), Value::Map(b)) => compare_shared(a, b, true, |a, b| a == b),
+            (Value::Object(a), Value::Object(b)) => compare_shared(a, b, true, |a, b| a == b),
+            (Value::Function(a), Value::Function(b)) => a == b,
+            (Value::Closure(a), Value::Closure(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+thread_local! {
+    //the pairs of values being compared right now
+    static COMPARING: RefCell<Vec<(*const (), *const ())>> = const { RefCell::new(Vec::new()) };
+}
+
+///Compares what two shared values hold with `f`, giving `same` if they're the same value. A pair that comes up
+///again while it's still being compared can only be part of a cycle, so it's taken to be the same there too.
+fn compare_shared<T, R>(
+    a: &Rc<RefCell<T>>,
+    b: &Rc<RefCell<T>>,
+    same: R,
+    f: impl FnOnce(&T, &T) -> R,
+) -> R {
+    if Rc::ptr_eq(a, b) {
+        return same;
+    }
+    let pair = (Rc::as_ptr(a) as *const (), Rc::as_ptr(b) as *const ());
+    if COMPARING.with(|c| c.borrow().contains(&pair)) {
+        return same;
+    }
+    COMPARING.with(|c| c.borrow_mut().push(pair));
+    let res = f(&a.borrow(), &b.borrow());
+    COMPARING.with(|c| c.borrow_mut().pop());
+    res
+}
+
+//Without a runtime there's nothing to run ~display with, so this shows objects by their members. Scripts see
+//values through `Runtime::display` instead.
 impl std::fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Value::Float(s) => write!(f, "{}", s),
-            Value::EmString(s) => write!(f, "{}", s),
-            // Value::Char(c) => write!(f, "{}", c),
-            Value::Name(n) => write!(f, "{}", n),
-            Value::Null => write!(f, "null"),
-            Value::Function(n, p, _) => write!(f, "{:?}({:?})", n, p),
-            Value::EmBool(b) => write!(f, "{}", b),
-            Value::EmArray(v) => {
-                let mut tmp = String::new();
-                for val in v.iter() {
-                    if let Value::EmString(_) = **val{
-                        tmp = format!("{}\"{}\", ", tmp, val);
-                    }else {
-                        tmp = format!("{}{}, ", tmp, val);
+        let mut out = String::new();
+        write_value(&mut out, self, &mut vec![], &mut |out, obj, _| {
+            write_members(out, obj);
+            Ok::<(), fmt::Error>(())
+        })?;
+        f.write_str(&out)
+    }
+}
 
-                    }
+///The arrays and maps being written, by address
+type Seen = Vec<*const ()>;
+type WriteObject<'a, E> = dyn FnMut(&mut String, &Value, &mut Seen) -> Result<(), E> + 'a;
+
+///Writes `val` the way scripts see it, with `object` writing any objects. `seen` holds the arrays and maps being
+///written further out, and one that turns up inside of itself is written as `[...]` or `{...}`.
+fn write_value<E>(
+    out: &mut String,
+    val: &Value,
+    seen: &mut Seen,
+    object: &mut WriteObject<'_, E>,
+) -> Result<(), E> {
+    match val {
+        Value::Int(i) => out.push_str(&i.to_string()),
+        //whole floats keep their decimal point, so they don't look like integers
+        Value::Float(n) if n.fract() == 0.0 && n.is_finite() => out.push_str(&format!("{:.1}", n)),
+        Value::Float(n) => out.push_str(&n.to_string()),
+        Value::EmString(s) | Value::Name(s) => out.push_str(s),
+        Value::Null => out.push_str("null"),
+        Value::EmBool(b) => out.push_str(&b.to_string()),
+        Value::Function(func) => {
+            let params: Vec<String> = func.params.iter().map(|p| p.to_string()).collect();
+            match &func.name {
+                Expression::Ident(name) => {
+                    out.push_str(&format!("fn {}({})", name, params.join(", ")))
                 }
-                tmp.pop();
-                tmp.pop();
-                write!(f, "[{}]", tmp)
+                _ => out.push_str(&format!("fn ({})", params.join(", "))),
             }
-            Value::Object(e) => {
-                if let Some(Value::Function(_, _, t)) = e.get_prop("~display") {
-                    let mut rt = Runtime::new();
-                    let mut gf = StackFrame::new();
-                    gf.set_var(String::from("self"), self.clone());
-                    let res = repl_run(t.clone(), &mut rt, &mut gf).unwrap_or_default();
-                    write!(f, "{}", res)
-                } else {
-                    write!(f, "{:?}", e.members)
+        }
+        Value::Closure(c) => out.push_str(&c.to_string()),
+        Value::EmArray(v) => {
+            let id = Rc::as_ptr(v) as *const ();
+            if seen.contains(&id) {
+                out.push_str("[...]");
+                return Ok(());
+            }
+            seen.push(id);
+            out.push('[');
+            //showing an object can run code that changes the array, so it can't stay borrowed
+            let items = v.borrow().clone();
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_element(out, item, seen, object)?;
+            }
+            out.push(']');
+            seen.pop();
+        }
+        Value::Map(m) => {
+            let id = Rc::as_ptr(m) as *const ();
+            if seen.contains(&id) {
+                out.push_str("{...}");
+                return Ok(());
+            }
+            seen.push(id);
+            out.push('{');
+            let entries: Vec<(MapKey, Value)> = m
+                .borrow()
+                .entries
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            for (i, (key, item)) in entries.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
                 }
+                out.push_str(&key.to_string());
+                out.push_str(": ");
+                write_element(out, item, seen, object)?;
             }
+            out.push('}');
+            seen.pop();
         }
+        Value::Object(_) => object(out, val, seen)?,
+    }
+    Ok(())
+}
+
+///Writes an object that doesn't have a `~display` method
+fn write_members(out: &mut String, obj: &Value) {
+    if let Value::Object(e) = obj {
+        out.push_str(&format!("{:?}", e.borrow().members));
+    }
+}
+
+///Writes something held by an array or map, where strings are quoted
+fn write_element<E>(
+    out: &mut String,
+    val: &Value,
+    seen: &mut Seen,
+    object: &mut WriteObject<'_, E>,
+) -> Result<(), E> {
+    match val {
+        Value::EmString(s) => {
+            out.push('"');
+            out.push_str(s);
+            out.push('"');
+            Ok(())
+        }
+        _ => write_value(out, val, seen, object),
     }
 }
 
 impl types::Indexable<Value> for Value {
-    fn index<'a>(&'a self, index: usize) -> Result<&'a Value, String> {
+    fn index(&self, index: usize) -> Result<Value, Error> {
         match self {
             Value::EmArray(v) => {
-                if let Some(val) = v.get(index) {
-                    Ok(val)
-                } else {
-                    Err(format!(
-                        "Index {} out of bounds (I hope I can include line numbers some day)",
-                        index
-                    ))
-                }
+                let v = v.borrow();
+                v.get(index)
+                    .cloned()
+                    .ok_or_else(|| out_of_bounds(index, v.len()))
             }
-            _ => Err(format!("Type {} isn't indexable", self)),
+            //strings are indexed by character, and give back a string with just that one
+            Value::EmString(s) => match s.chars().nth(index) {
+                Some(c) => Ok(Value::EmString(c.to_string())),
+                None => Err(Error::runtime(
+                    RuntimeErrorKind::IndexOutOfBounds,
+                    format!("Index {} out of bounds", index),
+                )
+                .with_note(format!("the string has {} characters", s.chars().count()))),
+            },
+            _ => Err(not_indexable(self)),
         }
     }
 
-    fn index_mut<'a>(&'a mut self, index: usize) -> Result<&'a mut Value, String> {
+    fn set_index(&self, index: usize, val: Value) -> Result<(), Error> {
         match self {
             Value::EmArray(v) => {
-                if let Some(val) = v.get_mut(index) {
-                    Ok(val)
-                } else {
-                    Err(format!(
-                        "Index {} out of bounds (I hope I can include line numbers some day)",
-                        index
-                    ))
+                let mut v = v.borrow_mut();
+                let len = v.len();
+                match v.get_mut(index) {
+                    Some(slot) => {
+                        *slot = val;
+                        Ok(())
+                    }
+                    None => Err(out_of_bounds(index, len)),
                 }
             }
-            _ => Err(format!("Type {} isn't indexable", self)),
+            Value::EmString(_) => Err(Error::runtime(
+                RuntimeErrorKind::InvalidOperation,
+                "Can't assign to a character of a string",
+            )
+            .with_note("strings can't be changed, build a new one with slice() and +")),
+            _ => Err(not_indexable(self)),
         }
     }
 }
 
-///Stores variables in a hashmap for a given function block. Only created on function call, with the exception of the global frame
+fn out_of_bounds(index: usize, len: usize) -> Error {
+    Error::runtime(
+        RuntimeErrorKind::IndexOutOfBounds,
+        format!("Index {} out of bounds", index),
+    )
+    .with_note(format!("the array has {} elements", len))
+}
+
+fn not_indexable(val: &Value) -> Error {
+    Error::runtime(
+        RuntimeErrorKind::TypeMismatch,
+        format!("Type {} isn't indexable", val),
+    )
+}
+
+///Turns the value of an index expression into something that can actually index an array
+fn to_index(val: Value) -> Result<usize, Error> {
+    match val {
+        Value::Int(i) if i >= 0 => Ok(i as usize),
+        Value::Int(i) => Err(Error::runtime(
+            RuntimeErrorKind::IndexOutOfBounds,
+            format!("Index {} out of bounds", i),
+        )),
+        Value::Float(f) => Err(Error::runtime(
+            RuntimeErrorKind::TypeMismatch,
+            format!("Index must be an integer, found {}", f),
+        )),
+        other => Err(Error::runtime(
+            RuntimeErrorKind::TypeMismatch,
+            format!("Index was not a number, found {}", other),
+        )),
+    }
+}
+
+///Returns the value at an array index or map key
+fn index_value(target: &Value, key: Value) -> Result<Value, Error> {
+    match target {
+        Value::Map(m) => m.borrow().get(&key),
+        _ => to_index(key).and_then(|i| target.index(i)),
+    }
+}
+
+///Assigns to an array index or map key. Arrays and maps are shared, so the change is seen by every variable
+///that refers to them
+fn set_index_value(target: &Value, key: Value, val: Value) -> Result<(), Error> {
+    match target {
+        Value::Map(m) => m.borrow_mut().insert(&key, val),
+        _ => to_index(key).and_then(|i| target.set_index(i, val)),
+    }
+}
+
+pub(crate) fn undefined(name: &str) -> Error {
+    Error::runtime(
+        RuntimeErrorKind::UndefinedName,
+        format!("Couldn't find identifier {}", name),
+    )
+}
+
+///A handle to a scope of global variables, which are looked up by name so the host and any number of scripts
+///can share them. Looking up a variable walks out through the enclosing scopes, and cloning the handle shares
+///the scope. Local variables don't live here, the resolver gives them slots in a frame instead.
+#[derive(Clone)]
 pub struct StackFrame {
+    scope: Rc<RefCell<Scope>>,
+}
+
+struct Scope {
     stack: HashMap<String, Value>,
+    parent: Option<StackFrame>,
 }
 
 ///Handles all of the interpretation, and keeps track of things like function definitions
 pub struct Runtime {
     // tree: ExprNode,
     // stack: Vec<StackFrame>,
-    heap: HashMap<String, RefCell<Value>>,
-    functions: HashMap<String, Box<dyn Fn(Vec<Value>) -> Value>>,
+    functions: HashMap<String, builtins::Builtin>,
     returning: bool,
+    budget: Budget,
+    capabilities: Capabilities,
+    streams: Streams,
+    backend: Backend,
+    stack: Vec<Option<Value>>, //the VM's stack, with the locals that haven't been assigned yet empty
 }
 
 ///A run function that accepts a runtime and global frame, mostly for use with the REPL
 pub fn repl_run(
-    tree: ExprNode,
+    mut tree: Node,
     runtime: &mut Runtime,
-    glob_frame: &mut StackFrame,
-) -> Result<String, String> {
-    match runtime.walk_tree(&tree, glob_frame) {
-        Ok(val) => Ok(format!("{}", val)),
-        Err(e) => Err(e),
-    }
+    glob_frame: &StackFrame,
+) -> Result<String, Error> {
+    runtime.reset_usage();
+    let val = runtime.run_script(&mut tree, glob_frame)?;
+    runtime.display(&val)
 }
 
-///Walks through the provided tree and executes all the nodes
-pub fn run(tree: ExprNode, args: ExprNode) {
-    let mut r = Runtime::new();
-    // r.find_global_vars();
-    let mut glob_frame = StackFrame::new();
+///Walks through the provided tree and executes all the nodes, then calls the script's main function
+pub fn run(tree: Node, args: Node) -> Result<Value, Error> {
+    run_with(tree, args, Backend::default())
+}
+
+///Same as `run`, but the script is run by `backend`
+pub fn run_with(mut tree: Node, args: Node, backend: Backend) -> Result<Value, Error> {
+    //scripts run from the command line are trusted as much as whoever ran them
+    let mut r = Runtime::with_capabilities(Capabilities::all());
+    r.set_backend(backend);
+    let glob_frame = StackFrame::new();
 
     //define all functions and any global variables
-    if let Err(e) = r.walk_tree(&tree, &mut glob_frame) {
-        println!("Interpreter crashed because: {}", e);
-    }
+    r.run_script(&mut tree, &glob_frame)?;
 
-    if let Err(e) = r.do_call(&Expression::Ident("main".to_owned()), &[args], &mut glob_frame) {
-        println!("Interpreter crashed because: {}", e);
-    }
-    // println!("{:?}", glob_frame.stack);
+    let main = Expression::Ident("main".to_owned());
+    r.do_call(&main, None, &[args], &Env::global(&glob_frame))
 }
 
 // Basically *is* the interpreter, walks through the AST and executes the nodes as needed
 impl Runtime {
     //TODO: Reduce the number of copies ins this code
 
-    ///Creates a new Runtime with an empty heap
+    ///Creates a new Runtime with the builtin functions loaded and the default capabilities, which only allow
+    ///terminal I/O
     pub fn new() -> Runtime {
+        Runtime::with_capabilities(Capabilities::default())
+    }
+
+    ///Creates a Runtime whose scripts can only use the builtins `capabilities` allows
+    pub fn with_capabilities(capabilities: Capabilities) -> Runtime {
         Runtime {
-            heap: HashMap::new(),
             returning: false,
             functions: builtins::get_functions(),
+            budget: Budget::default(),
+            capabilities,
+            streams: Streams::default(),
+            backend: Backend::default(),
+            stack: Vec::new(),
+        }
+    }
+
+    ///Picks what runs scripts from now on. Both backends give the same results, the VM is just faster.
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    ///Resolves the variables in a whole script, then runs it in `frame` with the selected backend. Nothing
+    ///runs if the script uses a name that isn't defined anywhere.
+    pub(crate) fn run_script(
+        &mut self,
+        tree: &mut Node,
+        frame: &StackFrame,
+    ) -> Result<Value, Error> {
+        resolver::resolve(tree, frame, &self.functions)?;
+        match self.backend {
+            Backend::TreeWalker => self.run_body(tree, &Env::global(frame)),
+            Backend::Vm => self.run_vm(tree, frame),
         }
     }
 
+    ///Makes `readln` and `read` take their input from `input` instead of the terminal
+    pub fn set_stdin(&mut self, input: impl Read + 'static) {
+        self.streams.set_stdin(input);
+    }
+
+    ///Sends everything scripts print to `output`. An `OutputBuffer` can be used to capture it.
+    pub fn set_stdout(&mut self, output: impl Write + 'static) {
+        self.streams.stdout = Box::new(output);
+    }
+
+    pub fn set_stderr(&mut self, output: impl Write + 'static) {
+        self.streams.stderr = Box::new(output);
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities;
+    }
+
+    ///Sets the limits scripts run with from now on
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.budget.limits = limits;
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.budget.limits
+    }
+
+    ///Forgets the steps, time, and allocations used so far, so the next script gets the full limits. The
+    ///`Engine` does this every time it's asked to run something.
+    pub fn reset_usage(&mut self) {
+        self.budget.reset();
+    }
+
+    ///Counts `bytes` against the allocation limit. Native functions that build large values can call this so
+    ///scripts can't get around the limit through them.
+    pub fn allocate(&mut self, bytes: usize) -> Result<(), Error> {
+        self.budget.allocate(bytes)
+    }
+
+    ///Turns `val` into the string scripts see, which is what printing it or adding it to a string gives. Objects
+    ///are shown with their `~display` method if they have one, which runs with this runtime's limits,
+    ///capabilities, and streams like any other script code, so anything it does wrong is an error here.
+    pub fn display(&mut self, val: &Value) -> Result<String, Error> {
+        let mut out = String::new();
+        self.write_shown(&mut out, val, &mut vec![])?;
+        Ok(out)
+    }
+
+    fn write_shown(&mut self, out: &mut String, val: &Value, seen: &mut Seen) -> Result<(), Error> {
+        write_value(out, val, seen, &mut |out, obj, seen| {
+            self.write_object(out, obj, seen)
+        })
+    }
+
+    ///Shows an object with its `~display` method. If what that gives back shows the object again, it's shown by
+    ///its members there instead of going around forever.
+    fn write_object(
+        &mut self,
+        out: &mut String,
+        obj: &Value,
+        seen: &mut Seen,
+    ) -> Result<(), Error> {
+        let e = match obj {
+            Value::Object(e) => e,
+            _ => return Ok(()),
+        };
+        let id = Rc::as_ptr(e) as *const ();
+        //don't hold on to the object while ~display runs, since it can look at self
+        let display = e.borrow().get_prop("~display").cloned();
+        match display {
+            Some(display @ (Value::Function(_) | Value::Closure(_))) if !seen.contains(&id) => {
+                let shown = self.call_function(&display, vec![obj.clone()])?;
+                seen.push(id);
+                let res = self.write_shown(out, &shown, seen);
+                seen.pop();
+                res
+            }
+            _ => {
+                write_members(out, obj);
+                Ok(())
+            }
+        }
+    }
+
+    ///Does arithmetic on two values, where adding anything to a string joins it on the way it's shown
+    pub(crate) fn arithmetic(
+        &mut self,
+        op: Arithmetic,
+        left: Value,
+        right: Value,
+    ) -> Result<Value, Error> {
+        match (left, right) {
+            (Value::EmString(s), r) if op == Arithmetic::Add => {
+                let joined = s + &self.display(&r)?;
+                self.allocate(joined.len())?;
+                Ok(Value::EmString(joined))
+            }
+            (l, r) => arithmetic(op, l, r),
+        }
+    }
+
+    ///Makes a rust function callable from scripts as `name`, replacing any builtin with the same name. The
+    ///function is handed the runtime so it can call back into functions it's passed with `call_function`, and
+    ///any error it returns is reported at the call in the script. Host state can be shared with the function
+    ///by capturing something like an `Rc<RefCell<_>>`.
+    pub fn register_fn<F>(&mut self, name: &str, func: F)
+    where
+        F: Fn(&mut Runtime, Vec<Value>) -> Result<Value, Error> + 'static,
+    {
+        self.functions
+            .insert(name.to_owned(), builtins::Builtin::new(func, None));
+    }
+
+    ///Like `register_fn`, but calls that don't pass exactly `arity` arguments are an error before the function runs
+    pub fn register_fn_with_arity<F>(&mut self, name: &str, arity: usize, func: F)
+    where
+        F: Fn(&mut Runtime, Vec<Value>) -> Result<Value, Error> + 'static,
+    {
+        self.functions
+            .insert(name.to_owned(), builtins::Builtin::new(func, Some(arity)));
+    }
+
     ///Matches the provided node and dispatches functions to handle it
-    fn walk_tree(&mut self, node: &ExprNode, frame: &mut StackFrame) -> Result<Value, String> {
-        // println!(
-        //     "Walking tree: \n    Current node: {:?}\n     Current stack: {:?}",
-        //     node, frame.stack
-        // );
-        let res: Value;
-        match node {
-            ExprNode::Block(v) => {
-                let mut ret = Value::Null;
-                for e in v.iter() {
-                    match e {
-                        /*When we run into a ReturnVal, it needs special treatment so we know to stop executing the
-                         *current block once we get whatever the value is
-                         **/
-                        ExprNode::ReturnVal(v) => {
-                            ret = self.walk_tree(v, frame)?;
-                            break;
-                        }
-                        _ => {
-                            self.walk_tree(e, frame)?;
-                        }
-                    }
-                    if self.returning {
-                        //if the returning flag has been set, then break out of the loop and stop executing this block
-                        //This is for return statements that don't return anything
-                        break;
-                    }
-                }
-                return Ok(ret);
-            }
-            ExprNode::Operation(o, l, r) => res = self.do_operation(&**o, &**l, &**r, frame)?,
-            ExprNode::Call(ex, n) => res = self.do_call(&**ex, &*n, frame)?,
-            ExprNode::MethodCall(n, args) => res = self.do_method(n, args, frame)?,
-            ExprNode::StrLiteral(s) => res = Value::EmString(*s.clone()),
-            ExprNode::NumLiteral(n) => res = Value::Float(**n),
-            ExprNode::BoolLiteral(b) => res = Value::EmBool(*b),
-            ExprNode::Name(n) => res = frame.get_var_copy(n),
-            ExprNode::Func(n, p, b) => res = self.def_func(n, p, b)?, //don't need the stackframe here because functions are stored on the heap
-            ExprNode::Statement(e) => res = self.walk_tree(&**e, frame)?,
-            ExprNode::Loop(ty, con, block) => res = self.do_loop(&**ty, &**con, &**block, frame)?,
-            ExprNode::IfStatement(con, body, branch) => {
-                res = self.do_if(con, body, branch, frame)?
-            }
-            ExprNode::Array(v) => res = self.create_array(v, frame)?,
-            ExprNode::Index(ident, index) => res = self.index_array(ident, index, frame)?,
-            ExprNode::New(name, args) => res = self.do_init(name, args, frame)?,
-            ExprNode::Class(name, body) => res = self.define_class(&**name, &**body, frame)?,
-            _ => res = Value::Null,
-        }
-        //Reset the returning flag, since we're returning whatever value we got anyways
+    fn walk_tree(&mut self, node: &Node, env: &Env) -> Result<Value, Error> {
+        //anything that goes wrong without a more specific location happened at this node
+        self.budget
+            .step()
+            .and_then(|_| self.eval(node, env))
+            .map_err(|e| e.at(&node.span))
+    }
+
+    ///Runs a function body or a whole script directly in `env`, rather than in a new scope like other blocks
+    pub(crate) fn run_body(&mut self, body: &Node, env: &Env) -> Result<Value, Error> {
+        let res = match &body.kind {
+            ExprNode::Block(v) => self.run_block(v, env).map_err(|e| e.at(&body.span)),
+            _ => self.walk_tree(body, env),
+        };
+        //the return only applies to this body, so whatever ran it should carry on
         self.returning = false;
+        res
+    }
+
+    ///Runs each statement in a block, stopping early if one of them returns
+    fn run_block(&mut self, statements: &[Node], env: &Env) -> Result<Value, Error> {
+        for e in statements {
+            let val = self.walk_tree(e, env)?;
+            if self.returning {
+                //if the returning flag has been set, then stop executing this block and pass the value up
+                return Ok(val);
+            }
+        }
+        Ok(Value::Null)
+    }
+
+    fn eval(&mut self, node: &Node, env: &Env) -> Result<Value, Error> {
+        let res = match &node.kind {
+            ExprNode::Block(v) => self.run_block(v, &env.scope(node.locals))?,
+            ExprNode::ReturnVal(v) => {
+                let val = self.walk_tree(v, env)?;
+                self.returning = true;
+                val
+            }
+            ExprNode::Operation(o, l, r) => self.do_operation(o, l, r, env)?,
+            ExprNode::Call(ex, n) => self.do_call(ex, node.var, n, env)?,
+            ExprNode::MethodCall(n, args) => self.do_method(n, args, env)?,
+            ExprNode::StrLiteral(s) => {
+                self.allocate(s.len())?;
+                Value::EmString(*s.clone())
+            }
+            ExprNode::IntLiteral(n) => Value::Int(*n),
+            ExprNode::FloatLiteral(n) => Value::Float(*n),
+            ExprNode::BoolLiteral(b) => Value::EmBool(*b),
+            ExprNode::Name(n) => env.get(node.var, n).ok_or_else(|| undefined(n))?,
+            ExprNode::Func(n, p, b) => self.def_func(node, n, p, b, env)?,
+            ExprNode::Lambda(p, b) => {
+                //anonymous functions don't have a name to show, so they get a keyword instead
+                let name = Expression::Key("fn".to_owned());
+                Value::function(name, param_names(p), (**b).clone(), node.locals, env)
+            }
+            ExprNode::Invoke(callee, args) => {
+                let func = self.walk_tree(callee, env)?;
+                let args = self.eval_args(args, env)?;
+                self.call_function(&func, args)?
+            }
+            ExprNode::Group(e) => self.walk_tree(e, env)?,
+            ExprNode::Unary(op, operand) => {
+                let op = unary_op(op).ok_or_else(|| invalid_operator(op))?;
+                let val = self.walk_tree(operand, env)?;
+                unary(op, val)?
+            }
+            //for loops get a scope for the loop variable, which only exists for the duration of the loop
+            ExprNode::Loop(_, con, block) if matches!(con.kind, ExprNode::ForIn(..)) => {
+                self.do_for_in(node.locals, con, block, env)?
+            }
+            ExprNode::Loop(ty, con, block) => {
+                self.do_loop(ty, con, block, &env.scope(node.locals))?
+            }
+            ExprNode::IfStatement(con, body, branch) => self.do_if(con, body, branch, env)?,
+            ExprNode::Array(v) => self.create_array(v, env)?,
+            ExprNode::Map(v) => self.create_map(v, env)?,
+            ExprNode::Index(ident, index) => self.index_array(ident, index, env)?,
+            ExprNode::New(name, args) => self.do_init(name, node.var, args, env)?,
+            ExprNode::Class(name, body) => self.define_class(node, name, body, env)?,
+            _ => Value::Null,
+        };
         Ok(res)
     }
 
@@ -214,20 +715,15 @@ impl Runtime {
     fn do_loop(
         &mut self,
         ty: &str,
-        condition: &ExprNode,
-        block: &ExprNode,
-        frame: &mut StackFrame,
-    ) -> Result<Value, String> {
+        condition: &Node,
+        block: &Node,
+        env: &Env,
+    ) -> Result<Value, Error> {
         match ty {
             "while" => {
                 let mut ret = Value::Null;
-                // println!("Loop condition: {:?}\nBlock to run: {:?}", condition, block);
-                // println!(
-                //     "Condition is currently: {:?}",
-                //     self.walk_tree(&condition, frame)
-                // );
-                while self.walk_tree(&condition, frame)? == Value::EmBool(true) {
-                    ret = self.walk_tree(&block, frame)?;
+                while self.walk_tree(condition, env)? == Value::EmBool(true) {
+                    ret = self.walk_tree(block, env)?;
                     if self.returning {
                         break;
                     }
@@ -236,27 +732,27 @@ impl Runtime {
             }
             "for" => {
                 let mut ret = Value::Null;
-                if let ExprNode::ForLoopDec(dec, con, inc) = condition {
-                    if let ExprNode::Illegal(_) = **dec {
-                        while self.walk_tree(&con, frame)? == Value::EmBool(true) {
+                if let ExprNode::ForLoopDec(dec, con, inc) = &condition.kind {
+                    if let ExprNode::Illegal(_) = dec.kind {
+                        while self.walk_tree(con, env)? == Value::EmBool(true) {
                             //walk the tree to execute the loop body
-                            ret = self.walk_tree(&block, frame)?;
+                            ret = self.walk_tree(block, env)?;
                             if self.returning {
                                 break;
                             }
                             //perform the incrementation
-                            self.walk_tree(&inc, frame)?;
+                            self.walk_tree(inc, env)?;
                         }
                     } else {
-                        self.walk_tree(&dec, frame)?;
-                        while self.walk_tree(&con, frame)? == Value::EmBool(true) {
+                        self.walk_tree(dec, env)?;
+                        while self.walk_tree(con, env)? == Value::EmBool(true) {
                             //walk the tree to execute the loop body
-                            ret = self.walk_tree(&block, frame)?;
+                            ret = self.walk_tree(block, env)?;
                             if self.returning {
                                 break;
                             }
                             //perform the incrementation
-                            self.walk_tree(&inc, frame)?;
+                            self.walk_tree(inc, env)?;
                         }
                     }
                 }
@@ -267,25 +763,72 @@ impl Runtime {
         }
     }
 
-    ///Defines a function and saves it as a variable in the heap
+    ///Runs a for-in loop. What it goes through is worked out first, then each time round gets a new scope with
+    ///`locals` slots, so closures made in the loop keep the loop variables from that time. The item goes in the
+    ///last loop variable, and its position in the first if there are two.
+    fn do_for_in(
+        &mut self,
+        locals: usize,
+        header: &Node,
+        block: &Node,
+        env: &Env,
+    ) -> Result<Value, Error> {
+        let (vars, over) = match &header.kind {
+            ExprNode::ForIn(vars, over) => (vars, over),
+            _ => return Ok(Value::Null),
+        };
+        let items = match &over.kind {
+            ExprNode::Range(start, end, step, inclusive) => {
+                let start = self.walk_tree(start, env)?;
+                let end = self.walk_tree(end, env)?;
+                let step = self.walk_tree(step, env)?;
+                Items::range(start, end, step, *inclusive)
+            }
+            _ => Items::new(self.walk_tree(over, env)?),
+        }
+        .map_err(|e| e.at(&over.span))?;
+
+        let mut ret = Value::Null;
+        for (i, item) in items {
+            let env = env.scope(locals);
+            let set = |var: &Node, val| {
+                if let ExprNode::Name(n) = &var.kind {
+                    env.set(var.var, n, val);
+                }
+            };
+            if let [counter, _] = vars.as_slice() {
+                set(counter, Value::Int(i));
+            }
+            if let Some(var) = vars.last() {
+                set(var, item);
+            }
+            ret = self.walk_tree(block, &env)?;
+            if self.returning {
+                break;
+            }
+        }
+        Ok(ret)
+    }
+
+    ///Defines a function in the current scope, which it keeps hold of so it can see its surroundings when called
     fn def_func(
         &mut self,
+        node: &Node,
         name: &Expression,
-        params: &[ExprNode],
-        body: &ExprNode,
-    ) -> Result<Value, String> {
+        params: &[Node],
+        body: &Node,
+        env: &Env,
+    ) -> Result<Value, Error> {
         if let Expression::Ident(n) = name {
-            let mut args = vec![];
-            params.iter().for_each(|e| {
-                if let ExprNode::Name(n) = e {
-                    args.push(Value::Name(n.to_string()));
-                }
-            });
-            let f = Value::Function(name.clone(), args, body.clone());
-            self.heap.insert(n.to_owned(), RefCell::new(f.clone()));
+            let args = param_names(params);
+            let f = Value::function(name.clone(), args, body.clone(), node.locals, env);
+            env.define(node.var, n, f.clone());
             Ok(f)
         } else {
-            Err(format!("Expected identifier, found {:?}", name))
+            Err(Error::runtime(
+                RuntimeErrorKind::InvalidOperation,
+                format!("Expected identifier, found {}", name),
+            ))
             //If we don't get a name for the funciton, we should exit since things will break
         }
     }
@@ -294,167 +837,121 @@ impl Runtime {
     fn do_operation(
         &mut self,
         opr: &Expression,
-        left: &ExprNode,
-        right: &ExprNode,
-        frame: &mut StackFrame,
-    ) -> Result<Value, String> {
+        left: &Node,
+        right: &Node,
+        env: &Env,
+    ) -> Result<Value, Error> {
         match opr {
-            Expression::Equal => match left {
+            Expression::Equal => match &left.kind {
                 ExprNode::Name(n) => {
-                    let v = self.walk_tree(&right, frame)?;
-                    // println!("Assigning variable: {:?}", v);
-                    frame.set_var(n.to_string(), v.clone());
+                    let v = self.walk_tree(right, env)?;
+                    env.set(left.var, n, v.clone());
                     Ok(v)
                 }
-                ExprNode::Index(n, i) => {
-                    let name = if let ExprNode::Name(s) = *n.clone() {
-                        *s
-                    } else {
-                        return Err(format!("Error getting name {:?}", n));
-                    };
-                    let index = self.walk_tree(i, frame)?;
-                    let val = self.walk_tree(right, frame)?;
-                    frame.update_array_index(&name, index, val.clone());
-
+                ExprNode::Index(..) => {
+                    let val = self.walk_tree(right, env)?;
+                    self.assign_index(left, val.clone(), env)?;
                     Ok(val)
                 }
-                ExprNode::Operation(o, l, r) => {
-                    match **o {
-                        Expression::Lbracket => {
-                            let val = self.walk_tree(right, frame)?;
-                            frame.update_nested_array(l, r, Some(val.clone()), true);
-                            Ok(val)
-                        }
-                        Expression::Operator(op) => {
-                            match op {
-                                '.' => {
-                                    let name = if let ExprNode::Name(n) = &**l {
-                                        *n.clone()
-                                    }else{
-                                        return Err(format!("Expected name, got {:?}", l));
-                                    };
-                                    let val = self.walk_tree(right, frame)?;
-
-                                    if let Some(Value::Object(e)) = frame.get_var_mut(&name.to_string()){
-                                        let prop = if let ExprNode::Name(n) = &**r {
-                                            n
-                                        }else {
-                                            return Err(format!("Unexpected symbol {:?}", r));
-                                        };
-
-                                        e.set_prop(*prop.clone(), Box::new(val.clone()));
-                                        Ok(val)
-                                    }else {
-                                        Err(format!("Unexpected {:?}", name))
-                                    }
-                                }
-                                _ => Err(format!("Unexpected operator {}", op))
+                ExprNode::Operation(o, l, r) => match **o {
+                    Expression::Operator('.') => {
+                        let prop = if let ExprNode::Name(n) = &r.kind {
+                            n
+                        } else {
+                            return Err(Error::runtime(
+                                RuntimeErrorKind::InvalidOperation,
+                                format!("Unexpected symbol {:?}", r.kind),
+                            )
+                            .at(&r.span));
+                        };
+                        //the object is shared, so setting the property is seen everywhere it's referenced
+                        match self.walk_tree(l, env)? {
+                            Value::Object(e) => {
+                                let val = self.walk_tree(right, env)?;
+                                e.borrow_mut()
+                                    .set_prop(prop.to_string(), Box::new(val.clone()));
+                                Ok(val)
                             }
+                            other => Err(Error::runtime(
+                                RuntimeErrorKind::TypeMismatch,
+                                format!("{} is not an object", other),
+                            )
+                            .at(&l.span)),
                         }
-                        _ => Err(format!("Unexpected symbol {:?}", o))
                     }
-                }
-                _ => Err(format!("Error assigning to variable {:?}", left)),
+                    _ => Err(Error::runtime(
+                        RuntimeErrorKind::InvalidOperation,
+                        format!("Can't assign to the result of {}", o),
+                    )),
+                },
+                _ => Err(Error::runtime(
+                    RuntimeErrorKind::InvalidOperation,
+                    format!("Error assigning to variable {:?}", left.kind),
+                )),
             },
 
-            Expression::Operator(o) => {
-                if *o == '.' {
-                    // let val = self.walk_tree(&left, frame)?;
-                    return if let Value::Object(obj) = self.walk_tree(&left, frame)? {
-                        if let Some(v) = obj.get_prop(&right.inner()) {
-                            Ok(v.clone())
-                        }else {
-                            Err(format!("{} has no property {}", obj, right.inner()))
-                        }
-                    } else {
-                        Err(format!("{:?} is not an object", left))
-                    }
-
-                }
-                let l_p = self.walk_tree(&left, frame)?;
-                let r_p = self.walk_tree(&right, frame)?;
-
-               
-
-                let f = match l_p {
-                    Value::Float(f) => f,
-                    Value::Name(n) => {
-                        if let Value::Float(f) = frame.get_var(&n) {
-                            *f
-                        } else {
-                            0.0 as f32
-                        }
-                    }
-                    Value::EmString(s) => {
-                        return Ok(Value::EmString(format!("{}{}", s, r_p)))
-                    },
-                    _ => 0.0 as f32,
-                };
-
-                let r = match r_p {
-                    Value::Float(f) => f,
-                    Value::Name(n) => {
-                        if let Value::Float(f) = frame.get_var(&n) {
-                            *f
-                        } else {
-                            0.0 as f32
-                        }
+            Expression::Operator('.') => {
+                let prop = match right.kind.inner() {
+                    Some(p) => p,
+                    None => {
+                        return Err(Error::runtime(
+                            RuntimeErrorKind::InvalidOperation,
+                            format!("Expected property name, found {:?}", right.kind),
+                        )
+                        .at(&right.span))
                     }
-                    _ => 0.0 as f32,
                 };
+                match self.walk_tree(left, env)? {
+                    Value::Object(obj) => match obj.borrow().get_prop(&prop) {
+                        Some(v) => Ok(v.clone()),
+                        None => Err(Error::runtime(
+                            RuntimeErrorKind::MissingProperty,
+                            format!("{} has no property {}", obj.borrow(), prop),
+                        )
+                        .at(&right.span)),
+                    },
+                    other => Err(Error::runtime(
+                        RuntimeErrorKind::TypeMismatch,
+                        format!("{} is not an object", other),
+                    )
+                    .at(&left.span)),
+                }
+            }
+            Expression::Operator(_) | Expression::Power | Expression::Key(_) => {
+                let op = Arithmetic::from_op(opr).ok_or_else(|| invalid_operator(opr))?;
+                let l_p = self.walk_tree(left, env)?;
+                let r_p = self.walk_tree(right, env)?;
 
-                if *o == '+' {
-                    Ok(Value::Float(f + r))
-                } else if *o == '-' {
-                    Ok(Value::Float(f - r))
-                } else if *o == '*' {
-                    Ok(Value::Float(f * r))
-                } else if *o == '/' {
-                    Ok(Value::Float(f / r))
-                } else {
-                    Err(format!("Invalid Operator: {}", o))
+                self.arithmetic(op, l_p, r_p)
+            }
+            //the right side only runs if it can change the result
+            Expression::BoolOp(op) if op == "&&" || op == "||" => {
+                let l_p = self.walk_tree(left, env)? == Value::EmBool(true);
+                if l_p == (op == "||") {
+                    return Ok(Value::EmBool(l_p));
                 }
+                let r_p = self.walk_tree(right, env)?;
+                Ok(Value::EmBool(r_p == Value::EmBool(true)))
             }
             Expression::BoolOp(op) => {
-                let l_p = self.walk_tree(&left, frame)?;
-                let r_p = self.walk_tree(&right, frame)?;
-                match op.as_str() {
-                    "==" => Ok(Value::EmBool(l_p == r_p)),
-                    "!=" => Ok(Value::EmBool(l_p != r_p)),
-                    ">=" => Ok(Value::EmBool(l_p >= r_p)),
-                    "<=" => Ok(Value::EmBool(l_p <= r_p)),
-                    "<" => Ok(Value::EmBool(l_p < r_p)),
-                    ">" => Ok(Value::EmBool(l_p > r_p)),
-                    _ => Err(format!("Invalid Operator: {}", op)),
-                }
+                let cmp = Comparison::from_op(op).ok_or_else(|| invalid_operator(opr))?;
+                let l_p = self.walk_tree(left, env)?;
+                let r_p = self.walk_tree(right, env)?;
+                Ok(Value::EmBool(cmp.test(&l_p, &r_p)))
             }
-
-            Expression::Lbracket => Ok(self.index_array(left, right, frame)?),
             _ => Ok(Value::Null),
         }
     }
 
-    fn keyword(
-        &mut self,
-        name: &Expression,
-        value: &ExprNode,
-        frame: &mut StackFrame,
-    ) -> Result<Value, String> {
-        if let Expression::Key(s) = name { 
-            let tmp = match value {
-                        ExprNode::Call(n, args) => 
-                            self.do_call(n, args, frame)?,
-                        
-                        _ => self.walk_tree(&value, frame)?,
-                        };
-            match s.as_str() {
-                "return" => {
-                    self.returning = true;
-                    return Ok(tmp);
-                }
-                _ => {
-                   
-                }
+    fn keyword(&mut self, name: &Expression, value: &Node, env: &Env) -> Result<Value, Error> {
+        if let Expression::Key(s) = name {
+            let tmp = match &value.kind {
+                ExprNode::Call(n, args) => self.do_call(n, value.var, args, env)?,
+                _ => self.walk_tree(value, env)?,
+            };
+            if s == "return" {
+                self.returning = true;
+                return Ok(tmp);
             }
         }
 
@@ -465,251 +962,600 @@ impl Runtime {
     fn do_call(
         &mut self,
         name: &Expression,
-        args: &[ExprNode],
-        frame: &mut StackFrame,
-    ) -> Result<Value, String> {
+        var: Option<Slot>,
+        args: &[Node],
+        env: &Env,
+    ) -> Result<Value, Error> {
         match name {
-            Expression::Key(_) => self.keyword(name, &args[0], frame),
+            Expression::Key(_) => match args.first() {
+                Some(arg) => self.keyword(name, arg, env),
+                None => Err(Error::runtime(
+                    RuntimeErrorKind::WrongArgumentCount,
+                    format!("Expected a value after {}", name),
+                )),
+            },
             Expression::Ident(n) => {
-                //check if there is a built-in function to use
-                if self.functions.contains_key(n) {
-                    let tmp = args.iter()
-                    .map(|e| self.walk_tree(e, frame).unwrap())
-                    .collect();
-                    let func = self.functions.get(n).unwrap();
-                    return Ok(func(tmp))
-                }
-
-                if let Some(func) = self.heap.get(n) {
-                    //I'd really like to not have to borrow here
-                    match &*func.clone().borrow() {
-                        Value::Function(_, params, body) => {
-                            if params.len() != args.len() {
-                                Err(format!(
-                                    "Expected {} arguments for {}, got {}",
-                                    params.len(),
-                                    n,
-                                    args.len()
-                                ))
-                            } else {
-                                let mut func_frame = StackFrame::new();
-                                for (i, e) in args.iter().enumerate() {
-                                    if let Value::Name(arg) = &params[i] {
-                                        let val = self.walk_tree(&e, frame)?;
-                                        match val {
-                                            Value::Name(n) => {
-                                                let tmp = frame.get_var(&n).clone();
-                                                func_frame.set_var(arg.to_string(), tmp);
-                                                //I'd really like to not have to copy here
-                                            }
-                                            _ => func_frame.set_var(arg.to_string(), val),
-                                        }
-                                    }
-                                }
-                                self.walk_tree(&body, &mut func_frame)
-                                //this shouldn't be necessary since Rust will destroy the old
-                                //stack frame anyways when it goes out of  scope
-                                // params.iter().for_each(|e| {
-                                //     if let Value::Name(n) = e {
-                                //         frame.free_var(n)
-                                //     }
-                                // });
-                            }
-                        }
-                        _ => Err(format!("Expected function, found {}", func.borrow())),
+                let func = match env.get(var, n) {
+                    Some(func) => func,
+                    //check if there is a built-in function to use
+                    None if self.functions.contains_key(n) => {
+                        let args = self.eval_args(args, env)?;
+                        return self.call_builtin(n, args);
                     }
-                } else {
-                    Err(format!("Couldn't find identifier {}", n))
+                    None => return Err(undefined(n)),
+                };
+                let args = self.eval_args(args, env)?;
+                self.call_function(&func, args)
+            }
+            _ => Err(Error::runtime(
+                RuntimeErrorKind::InvalidOperation,
+                format!("Expected keyword or identifier, found {:?}", name),
+            )),
+        }
+    }
+
+    ///Calls the builtin or registered function `name`, which has to exist
+    fn call_builtin(&mut self, name: &str, args: Vec<Value>) -> Result<Value, Error> {
+        let builtin = self.functions[name].clone();
+        if let Some(cap) = builtin.capability {
+            if !self.capabilities.allows(cap) {
+                return Err(capabilities::permission_denied(format!(
+                    "{} isn't allowed in this runtime",
+                    name
+                ))
+                .with_note(format!("it needs the {} capability", cap)));
+            }
+        }
+        match builtin.arity {
+            Some(arity) if arity != args.len() => Err(wrong_arg_count(name, arity, args.len())),
+            _ => (builtin.func)(self, args),
+        }
+    }
+
+    ///Evaluates the arguments of a call in order
+    fn eval_args(&mut self, args: &[Node], env: &Env) -> Result<Vec<Value>, Error> {
+        args.iter().map(|e| self.walk_tree(e, env)).collect()
+    }
+
+    ///Calls a function value with arguments that have already been evaluated. This is also how builtins
+    ///call back into functions that were passed to them.
+    pub fn call_function(&mut self, func: &Value, args: Vec<Value>) -> Result<Value, Error> {
+        match func {
+            Value::Function(f) => {
+                if f.params.len() != args.len() {
+                    return Err(wrong_arg_count(
+                        &func.to_string(),
+                        f.params.len(),
+                        args.len(),
+                    ));
                 }
+                let env = f.env.call(f.locals, args);
+                self.budget.enter_call()?;
+                let res = self.run_body(&f.body, &env);
+                self.budget.exit_call();
+                res
             }
-            _ => Err(format!("Expected keyword or identifier, found {:?}", name)),
+            Value::Closure(c) => self.call_closure(c, args),
+            other => Err(Error::runtime(
+                RuntimeErrorKind::TypeMismatch,
+                format!("Expected function, found {}", other),
+            )),
         }
     }
 
-    fn do_method(&mut self, method: &ExprNode, args: &Vec<ExprNode>, frame: &mut StackFrame) -> Result<Value, String> {
-        if let ExprNode::Operation(_, name, member) = method {
-            if let Value::Object(e) = self.walk_tree(&**name, frame)?{
-                let func = e.get_prop(&*member.inner());
-                match func {
-                    Some(Value::Function(n, p, body)) => {
-                        if args.len() != p.len() - 1 {
-                            Err(format!(
-                                "Method {} for {} takes {} arguments, found {}",
-                                n,
-                                e.get_prop("~name").unwrap(),
-                                p.len(),
-                                args.len()
-                            ))
-                        } else {
-                            let mut func_frame = StackFrame::new();
-                            func_frame.set_var(String::from("self"), Value::Object(e.clone()));
-                            for (i, e) in args.iter().enumerate() {
-                                if let Value::Name(arg) = &p[i+1] {
-                                    let val = self.walk_tree(&e, frame)?;
-                                    match val {
-                                        Value::Name(n) => {
-                                            let tmp = frame.get_var(&n).clone();
-                                            func_frame.set_var(arg.to_string(), tmp);
-                                            //I'd really like to not have to copy here
-                                        }
-                                        _ => func_frame.set_var(arg.to_string(), val),
-                                    }
-                                }
-                            }
-                            self.walk_tree(body, &mut func_frame)
-                        }
-                    }
-                    _ => {
-                        Err(format!("Expected function, got {:?}", func))
-                    }
+    fn do_method(&mut self, method: &Node, args: &[Node], env: &Env) -> Result<Value, Error> {
+        if let ExprNode::Operation(_, name, member) = &method.kind {
+            let prop = member.kind.inner().unwrap_or_default();
+            //builtins can be grouped under a name like `json.parse`, as long as a variable isn't hiding them
+            if let ExprNode::Name(n) = &name.kind {
+                let qualified = format!("{}.{}", n, prop);
+                if env.get(name.var, n).is_none() && self.functions.contains_key(&qualified) {
+                    let args = self.eval_args(args, env)?;
+                    return self.call_builtin(&qualified, args);
                 }
-            }else {
-                Err(format!("Expected object, got {:?}", self.walk_tree(name, frame)?))
             }
+            let receiver = self.walk_tree(name, env)?;
+            if !matches!(
+                receiver,
+                Value::Object(_) | Value::Map(_) | Value::EmString(_) | Value::EmArray(_)
+            ) {
+                return Err(not_a_receiver(&receiver).at(&name.span));
+            }
+            let args = self.eval_args(args, env)?;
+            self.call_method(receiver, &prop, args)
+                .map_err(|e| e.at(&member.span))
         } else {
-            Err(format!("Unexpected expression {:?}", method))
+            Err(Error::runtime(
+                RuntimeErrorKind::InvalidOperation,
+                format!("Unexpected expression {:?}", method.kind),
+            ))
         }
     }
+    ///Calls the method `prop` of an object or map with arguments that have already been evaluated
+    fn call_method(
+        &mut self,
+        receiver: Value,
+        prop: &str,
+        args: Vec<Value>,
+    ) -> Result<Value, Error> {
+        match receiver {
+            Value::Object(e) => {
+                //copy the method out so the object isn't borrowed while it runs
+                let found = e.borrow().get_prop(prop).cloned();
+                match found {
+                    Some(method) => match method.arity() {
+                        Some(arity) if args.len() + 1 != arity => Err(wrong_arg_count(
+                            &format!("{}.{}", e.borrow(), prop),
+                            arity.saturating_sub(1),
+                            args.len(),
+                        )),
+                        Some(_) => {
+                            //methods get the object they were called on as their first argument, so they can change it
+                            let mut vals = vec![Value::Object(e.clone())];
+                            vals.extend(args);
+                            self.call_function(&method, vals)
+                        }
+                        None => Err(Error::runtime(
+                            RuntimeErrorKind::TypeMismatch,
+                            format!("Expected function, got {}", method),
+                        )),
+                    },
+                    None => Err(Error::runtime(
+                        RuntimeErrorKind::MissingProperty,
+                        format!("{} has no method {}", e.borrow(), prop),
+                    )),
+                }
+            }
+            Value::Map(m) => methods::map_method(self, &m, prop, args),
+            Value::EmString(s) => methods::string_method(self, &s, prop, args),
+            Value::EmArray(v) => methods::array_method(self, &v, prop, args),
+            other => Err(not_a_receiver(&other)),
+        }
+    }
+
     ///Performs an if statement and any of its relevant branches
     fn do_if(
         &mut self,
-        condition: &ExprNode,
-        body: &ExprNode,
-        branches: &ExprNode,
-        frame: &mut StackFrame,
-    ) -> Result<Value, String> {
-        if self.walk_tree(condition, frame)? == Value::EmBool(true) {
-            self.walk_tree(body, frame)
-        } else if let ExprNode::IfStatement(con, body, branch) = branches {
-            self.do_if(con, body, branch, frame)
+        condition: &Node,
+        body: &Node,
+        branches: &Node,
+        env: &Env,
+    ) -> Result<Value, Error> {
+        if self.walk_tree(condition, env)? == Value::EmBool(true) {
+            self.walk_tree(body, env)
+        } else if let ExprNode::IfStatement(con, body, branch) = &branches.kind {
+            self.do_if(con, body, branch, env)
         } else {
-            self.walk_tree(branches, frame)
+            self.walk_tree(branches, env)
         }
     }
 
-
-
     fn do_init(
         &mut self,
         name: &Expression,
-        init_args: &Vec<ExprNode>,
-        frame: &mut StackFrame,
-    ) -> Result<Value, String> {
-        if let Expression::Ident(n) = name{
-            let class = match self.heap.get(n) {
-                Some(val) => {
-                    if let Value::Object(e) = val.borrow().clone(){
-                        e
-                    }else {
-                        return Err(format!("Expected class, got {}", val.borrow()));
-                    }
-                },
-                None => return Err(format!("Class {} is not defined", name)),
-
-            };
-        if let Some(Value::Function(_, params, body)) = class.get_prop("~init") {
-            if init_args.len() != params.len() - 1 {
-                Err(format!(
-                    "Contrsuctor for {} takes {} arguments, found {}",
-                    class.get_prop("~name").unwrap(),
-                    params.len(),
-                    init_args.len()
-                ))
-            } else {
-                let mut func_frame = StackFrame::new();
-                func_frame.set_var(String::from("self"), Value::Object(class.clone()));
-                for (i, e) in init_args.iter().enumerate() {
-                    if let Value::Name(arg) = &params[i+1] {
-                        let val = self.walk_tree(&e, frame)?;
-                        match val {
-                            Value::Name(n) => {
-                                let tmp = frame.get_var(&n).clone();
-                                func_frame.set_var(arg.to_string(), tmp);
-                                //I'd really like to not have to copy here
-                            }
-                            _ => func_frame.set_var(arg.to_string(), val),
-                        }
-                    }
-                }
-                self.walk_tree(body, &mut func_frame)?;
-                
-                //should figure out a way to get ownership from a stackframe
-                Ok(func_frame.get_var("self").clone())
-            }
+        var: Option<Slot>,
+        init_args: &[Node],
+        env: &Env,
+    ) -> Result<Value, Error> {
+        if let Expression::Ident(n) = name {
+            let class = find_class(n, env.get(var, n))?;
+            let args = self.eval_args(init_args, env)?;
+            self.instantiate(&class, args)
         } else {
-            Ok(Value::Object(class))
+            Err(Error::runtime(
+                RuntimeErrorKind::InvalidOperation,
+                format!("Expected object, found {:?}", name),
+            ))
         }
-    }else {
-        Err(format!("Expected object, found {:?}", name))
     }
+
+    ///Makes a new instance of `class`, running its constructor if it has one
+    fn instantiate(
+        &mut self,
+        class: &Rc<RefCell<EmObject>>,
+        args: Vec<Value>,
+    ) -> Result<Value, Error> {
+        //every instance gets its own copy of the class's members, so changing one doesn't change the others
+        let members = class.borrow().members.len();
+        self.allocate(members * mem::size_of::<Value>())?;
+        let obj = Value::object(class.borrow().clone());
+        let init = class.borrow().get_prop("~init").cloned();
+        match init {
+            Some(init) => match init.arity() {
+                Some(arity) if args.len() + 1 != arity => Err(wrong_arg_count(
+                    &format!("Constructor for {}", class.borrow()),
+                    arity.saturating_sub(1),
+                    args.len(),
+                )),
+                Some(_) => {
+                    let mut vals = vec![obj.clone()];
+                    vals.extend(args);
+                    self.call_function(&init, vals)?;
+                    Ok(obj)
+                }
+                None => Ok(obj),
+            },
+            None => Ok(obj),
+        }
     }
 
     ///Defines an array and saves it to the current stackframe
-    fn create_array(
-        &mut self,
-        raw: &Vec<ExprNode>,
-        frame: &mut StackFrame,
-    ) -> Result<Value, String> {
+    fn create_array(&mut self, raw: &[Node], env: &Env) -> Result<Value, Error> {
         let mut tmp = vec![];
         for val in raw.iter() {
-            tmp.push(Box::new(self.walk_tree(val, frame)?));
+            tmp.push(self.walk_tree(val, env)?);
         }
+        self.allocate(tmp.len() * mem::size_of::<Value>())?;
 
-        Ok(Value::EmArray(tmp))
+        Ok(Value::array(tmp))
     }
 
-    ///Returns the value at a given array index
-    fn index_array(
-        &mut self,
-        ident: &ExprNode,
-        index: &ExprNode,
-        frame: &mut StackFrame,
-    ) -> Result<Value, String> {
-        let array = self.walk_tree(ident, frame)?;
-        if let Value::Float(f) = self.walk_tree(index, frame)? {
-            Ok(array.index(f as usize)?.clone())
+    ///Evaluates the entries of a map literal in order
+    fn create_map(&mut self, raw: &[(Node, Node)], env: &Env) -> Result<Value, Error> {
+        let mut map = EmMap::default();
+        for (key, val) in raw.iter() {
+            let k = self.walk_tree(key, env)?;
+            let v = self.walk_tree(val, env)?;
+            map.insert(&k, v).map_err(|e| e.at(&key.span))?;
+        }
+        self.allocate(raw.len() * mem::size_of::<(MapKey, Value)>())?;
+
+        Ok(Value::map(map))
+    }
+
+    ///Returns the value at a given array index or map key
+    fn index_array(&mut self, ident: &Node, index: &Node, env: &Env) -> Result<Value, Error> {
+        let array = self.walk_tree(ident, env)?;
+        let key = self.walk_tree(index, env)?;
+        index_value(&array, key).map_err(|e| e.at(&index.span))
+    }
+
+    ///Assigns `val` to the array element or map entry described by `target`
+    fn assign_index(&mut self, target: &Node, val: Value, env: &Env) -> Result<(), Error> {
+        if let ExprNode::Index(l, r) = &target.kind {
+            let array = self.walk_tree(l, env)?;
+            let key = self.walk_tree(r, env)?;
+            set_index_value(&array, key, val).map_err(|e| e.at(&r.span))
         } else {
-            Err(format!("Index was not a numeber"))
+            Err(Error::runtime(
+                RuntimeErrorKind::InvalidOperation,
+                format!("Can't assign to an index of {:?}", target.kind),
+            )
+            .at(&target.span))
         }
     }
 
-    fn define_class(&mut self, name: &Expression, body: &ExprNode, frame: &mut StackFrame) -> Result<Value, String> {
+    fn define_class(
+        &mut self,
+        node: &Node,
+        name: &Expression,
+        body: &Node,
+        env: &Env,
+    ) -> Result<Value, Error> {
         let mut members = HashMap::new();
-        let class = if let Expression::Ident(s) = name{
+        let class = if let Expression::Ident(s) = name {
             s
-        }else {
-            return Err("Expected an identifier".to_string());
-        }; 
+        } else {
+            return Err(Error::runtime(
+                RuntimeErrorKind::InvalidOperation,
+                format!("Expected an identifier, found {}", name),
+            ));
+        };
 
         //the name property will be the name of the class for now, this might change in the future
-        members.insert("~name".to_string(), Box::new(Value::EmString(class.clone())));
+        members.insert(
+            "~name".to_string(),
+            Box::new(Value::EmString(class.clone())),
+        );
 
-        if let ExprNode::Block(v) = body {
+        if let ExprNode::Block(v) = &body.kind {
+            //methods get a scope of their own so they don't end up defined next to the class
+            let class_env = env.scope(node.locals);
             for node in v {
-                let val = self.walk_tree(node, frame)?;
-                match &val {
-                    Value::Function(n, _, _) => {
-                        let fn_name = if let Expression::Ident(s) =  n{
-                            s
-                        }else {
-                            return Err("Expected identifier".to_owned());
-                        };
-                        members.insert(fn_name.clone(), Box::new(val.clone()));
+                let val = self.walk_tree(node, &class_env)?;
+                let fn_name = match &val {
+                    Value::Function(f) => match &f.name {
+                        Expression::Ident(n) => Some(n.clone()),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                match fn_name {
+                    Some(fn_name) => {
+                        members.insert(fn_name, Box::new(val));
                     }
-                    er => {
-                        return Err(format!("Unexpected {:?} in class definition", er));
+                    None => {
+                        return Err(Error::runtime(
+                            RuntimeErrorKind::InvalidOperation,
+                            format!("Unexpected {} in class definition", val),
+                        )
+                        .at(&node.span)
+                        .with_note("classes can only contain function definitions"));
                     }
                 }
             }
         }
 
-        let tmp = Value::Object(EmObject {members: members});
-        self.heap.insert(class.clone(), RefCell::new(tmp.clone()));
+        let tmp = Value::object(EmObject { members });
+        env.define(node.var, class, tmp.clone());
 
         Ok(tmp)
     }
 }
 
+///Turns the parameter nodes of a function definition into the names they get bound to
+fn param_names(params: &[Node]) -> Vec<Value> {
+    params
+        .iter()
+        .filter_map(|e| match &e.kind {
+            ExprNode::Name(n) => Some(Value::Name(n.to_string())),
+            _ => None,
+        })
+        .collect()
+}
+
+///Finds the class `name`, given what the variable with that name holds
+fn find_class(name: &str, found: Option<Value>) -> Result<Rc<RefCell<EmObject>>, Error> {
+    match found {
+        Some(Value::Object(e)) => Ok(e),
+        Some(other) => Err(Error::runtime(
+            RuntimeErrorKind::TypeMismatch,
+            format!("Expected class, got {}", other),
+        )),
+        None => Err(Error::runtime(
+            RuntimeErrorKind::UndefinedName,
+            format!("Class {} is not defined", name),
+        )),
+    }
+}
+
+fn not_a_receiver(val: &Value) -> Error {
+    Error::runtime(
+        RuntimeErrorKind::TypeMismatch,
+        format!("Expected object, got {}", val),
+    )
+}
+
+fn invalid_operator(op: &Expression) -> Error {
+    Error::runtime(
+        RuntimeErrorKind::InvalidOperation,
+        format!("Invalid Operator: {}", op),
+    )
+}
+
+///The operators that do arithmetic on two values
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Arithmetic {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Power,
+    FloorDivide,
+}
+
+impl Arithmetic {
+    pub(crate) fn from_op(op: &Expression) -> Option<Arithmetic> {
+        match op {
+            Expression::Operator('+') => Some(Arithmetic::Add),
+            Expression::Operator('-') => Some(Arithmetic::Subtract),
+            Expression::Operator('*') => Some(Arithmetic::Multiply),
+            Expression::Operator('/') => Some(Arithmetic::Divide),
+            Expression::Operator('%') => Some(Arithmetic::Remainder),
+            Expression::Power => Some(Arithmetic::Power),
+            Expression::Key(k) if k == "div" => Some(Arithmetic::FloorDivide),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Arithmetic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            Arithmetic::Add => "+",
+            Arithmetic::Subtract => "-",
+            Arithmetic::Multiply => "*",
+            Arithmetic::Divide => "/",
+            Arithmetic::Remainder => "%",
+            Arithmetic::Power => "**",
+            Arithmetic::FloorDivide => "div",
+        };
+        f.write_str(op)
+    }
+}
+
+///Does arithmetic on two numbers. Two integers give an integer, and mixing an integer with a float turns the
+///integer into a float first.
+fn arithmetic(op: Arithmetic, left: Value, right: Value) -> Result<Value, Error> {
+    match (left, right) {
+        (Value::Int(a), Value::Int(b)) => int_arithmetic(op, a, b),
+        (Value::Int(a), Value::Float(b)) => Ok(Value::Float(float_arithmetic(op, a as f64, b))),
+        (Value::Float(a), Value::Int(b)) => Ok(Value::Float(float_arithmetic(op, a, b as f64))),
+        (Value::Float(a), Value::Float(b)) => Ok(Value::Float(float_arithmetic(op, a, b))),
+        (l, r) => Err(Error::runtime(
+            RuntimeErrorKind::TypeMismatch,
+            format!("Can't use {} on {} and {}", op, l, r),
+        )),
+    }
+}
+
+///Integer arithmetic never wraps around, anything that doesn't fit in 64 bits is an error. Division rounds
+///towards zero, while `div` rounds down and `%` takes the sign of the divisor to match it. A negative power
+///can't be an integer, so it gives a float.
+fn int_arithmetic(op: Arithmetic, a: i64, b: i64) -> Result<Value, Error> {
+    let res = match op {
+        Arithmetic::Add => a.checked_add(b),
+        Arithmetic::Subtract => a.checked_sub(b),
+        Arithmetic::Multiply => a.checked_mul(b),
+        Arithmetic::Divide | Arithmetic::Remainder | Arithmetic::FloorDivide if b == 0 => {
+            return Err(Error::runtime(
+                RuntimeErrorKind::DivisionByZero,
+                format!("Tried to divide {} by zero", a),
+            ))
+        }
+        Arithmetic::Divide => a.checked_div(b),
+        Arithmetic::Remainder => {
+            let r = a.wrapping_rem(b);
+            Some(if r != 0 && (r < 0) != (b < 0) {
+                r + b
+            } else {
+                r
+            })
+        }
+        Arithmetic::FloorDivide => a.checked_div(b).map(|q| {
+            if a % b != 0 && (a < 0) != (b < 0) {
+                q - 1
+            } else {
+                q
+            }
+        }),
+        Arithmetic::Power if b < 0 => return Ok(Value::Float((a as f64).powf(b as f64))),
+        Arithmetic::Power => u32::try_from(b).ok().and_then(|b| a.checked_pow(b)),
+    };
+    res.map(Value::Int).ok_or_else(|| {
+        Error::runtime(
+            RuntimeErrorKind::Overflow,
+            format!("Integer overflow in {} {} {}", a, op, b),
+        )
+        .with_note("integers are 64 bits, use a float like 1.0 for numbers that don't fit")
+    })
+}
+
+///The character for a prefix operator, which is how the VM stores it
+fn unary_op(op: &Expression) -> Option<char> {
+    match op {
+        Expression::Operator(c @ ('-' | '+')) => Some(*c),
+        Expression::BoolOp(o) if o == "!" => Some('!'),
+        _ => None,
+    }
+}
+
+///Applies a prefix operator. Like conditions, `!` treats anything but `true` as false.
+fn unary(op: char, val: Value) -> Result<Value, Error> {
+    match (op, val) {
+        ('!', v) => Ok(Value::EmBool(v != Value::EmBool(true))),
+        ('-', Value::Int(i)) => i.checked_neg().map(Value::Int).ok_or_else(|| {
+            Error::runtime(
+                RuntimeErrorKind::Overflow,
+                format!("Integer overflow in -{}", i),
+            )
+            .with_note("integers are 64 bits, use a float like 1.0 for numbers that don't fit")
+        }),
+        ('-', Value::Float(f)) => Ok(Value::Float(-f)),
+        ('+', v @ (Value::Int(_) | Value::Float(_))) => Ok(v),
+        (op, v) => Err(Error::runtime(
+            RuntimeErrorKind::TypeMismatch,
+            format!("Can't use {} on {}", op, v),
+        )),
+    }
+}
+
+fn float_arithmetic(op: Arithmetic, a: f64, b: f64) -> f64 {
+    match op {
+        Arithmetic::Add => a + b,
+        Arithmetic::Subtract => a - b,
+        Arithmetic::Multiply => a * b,
+        Arithmetic::Divide => a / b,
+        Arithmetic::Remainder => a - b * (a / b).floor(),
+        Arithmetic::Power => a.powf(b),
+        Arithmetic::FloorDivide => (a / b).floor(),
+    }
+}
+
+///The operators that compare two values
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl Comparison {
+    fn from_op(op: &str) -> Option<Comparison> {
+        match op {
+            "==" => Some(Comparison::Equal),
+            "!=" => Some(Comparison::NotEqual),
+            "<" => Some(Comparison::Less),
+            "<=" => Some(Comparison::LessEqual),
+            ">" => Some(Comparison::Greater),
+            ">=" => Some(Comparison::GreaterEqual),
+            _ => None,
+        }
+    }
+
+    ///Values that can't be ordered, like a string and a number, are never less or greater than each other
+    fn test(self, left: &Value, right: &Value) -> bool {
+        match self {
+            Comparison::Equal => equal(left, right),
+            Comparison::NotEqual => !equal(left, right),
+            _ => compare(left, right).is_some_and(|o| self.orders(o)),
+        }
+    }
+
+    ///Whether two values that are ordered like `ord` pass the comparison
+    fn orders(self, ord: Ordering) -> bool {
+        match self {
+            Comparison::Equal => ord == Ordering::Equal,
+            Comparison::NotEqual => ord != Ordering::Equal,
+            Comparison::Less => ord == Ordering::Less,
+            Comparison::LessEqual => ord != Ordering::Greater,
+            Comparison::Greater => ord == Ordering::Greater,
+            Comparison::GreaterEqual => ord != Ordering::Less,
+        }
+    }
+}
+
+///Checks if two values are equal, where an integer equals a float with the same value
+fn equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Int(a), Value::Float(b)) | (Value::Float(b), Value::Int(a)) => *a as f64 == *b,
+        _ => left == right,
+    }
+}
+
+///Orders two values of the same type, comparing integers and floats by their values. Values of different types
+///don't have an order, and neither do maps, objects, or functions.
+fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+        (Value::Int(a), Value::Float(b)) => (*a as f64).partial_cmp(b),
+        (Value::Float(a), Value::Int(b)) => a.partial_cmp(&(*b as f64)),
+        (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+        (Value::EmString(a), Value::EmString(b)) => Some(a.cmp(b)),
+        (Value::EmBool(a), Value::EmBool(b)) => Some(a.cmp(b)),
+        (Value::Null, Value::Null) => Some(Ordering::Equal),
+        (Value::EmArray(a), Value::EmArray(b)) => {
+            compare_shared(a, b, Some(Ordering::Equal), |a, b| compare_items(a, b))
+        }
+        _ => None,
+    }
+}
+
+///Orders arrays by their first elements that differ, or by their lengths if one starts with the other
+fn compare_items(a: &[Value], b: &[Value]) -> Option<Ordering> {
+    for (x, y) in a.iter().zip(b) {
+        match compare(x, y)? {
+            Ordering::Equal => {}
+            other => return Some(other),
+        }
+    }
+    Some(a.len().cmp(&b.len()))
+}
+
+fn wrong_arg_count(name: &str, expected: usize, found: usize) -> Error {
+    Error::runtime(
+        RuntimeErrorKind::WrongArgumentCount,
+        format!(
+            "Expected {} arguments for {}, got {}",
+            expected, name, found
+        ),
+    )
+}
+
 ///Keeps track of local variables for functions. Currently only created when a function is called
 impl Default for Runtime {
     fn default() -> Self {
@@ -724,111 +1570,77 @@ impl Default for StackFrame {
 }
 
 impl StackFrame {
+    ///Creates a new global scope
     pub fn new() -> StackFrame {
         StackFrame {
-            stack: HashMap::new(),
+            scope: Rc::new(RefCell::new(Scope {
+                stack: HashMap::new(),
+                parent: None,
+            })),
         }
     }
 
-    fn set_var(&mut self, name: String, v: Value) {
-        self.stack.insert(name, v);
+    ///Creates a new scope inside of this one
+    pub fn child(&self) -> StackFrame {
+        StackFrame {
+            scope: Rc::new(RefCell::new(Scope {
+                stack: HashMap::new(),
+                parent: Some(self.clone()),
+            })),
+        }
     }
 
-    fn get_var(&self, name: &str) -> &Value {
-        if self.stack.contains_key(name) {
-            &self.stack[name]
-        } else {
-            &Value::Null
-        }
+    ///Creates a variable in this scope, hiding any with the same name further out
+    pub(crate) fn define(&self, name: String, v: Value) {
+        self.scope.borrow_mut().stack.insert(name, v);
     }
 
-    fn get_var_mut(&mut self, name: &str) -> Option<&mut Value>{
-        if self.stack.contains_key(name){
-            self.stack.get_mut(name)
-        }else{
-            None
+    ///Assigns to the closest variable called `name`, or creates it in this scope if there isn't one
+    fn set_var(&self, name: String, v: Value) {
+        match self.find(&name) {
+            Some(owner) => owner.define(name, v),
+            None => self.define(name, v),
         }
     }
 
-    fn update_array_index(&mut self, name: &str, index: Value, val: Value) {
-        let var = self
-            .stack
-            .get_mut(name)
-            .expect(format!("Unable to find variable {}", name).as_str());
-
-        if let Value::Float(f) = index {
-            match var {
-                Value::EmArray(v) => {
-                    v[f as usize] = Box::new(val);
-                }
-                _ => panic!("Expected array, found {}", var),
+    ///Finds the closest scope that has a variable called `name`
+    fn find(&self, name: &str) -> Option<StackFrame> {
+        let mut cur = Some(self.clone());
+        while let Some(frame) = cur {
+            if frame.scope.borrow().stack.contains_key(name) {
+                return Some(frame);
             }
+            cur = frame.scope.borrow().parent.clone();
         }
+        None
     }
 
-    fn update_nested_array(
-        &mut self,
-        ident: &ExprNode,
-        index: &ExprNode,
-        val: Option<Value>,
-        first: bool,
-    ) -> Option<&mut Box<Value>> {
-        match ident {
-            ExprNode::Operation(o, l, r) => {
-                if **o != Expression::Lbracket {
-                    panic!("Found operation when assigning to array: {:?}", ident);
-                } else {
-                    let var = self.update_nested_array(l, r, None, false)?;
-                    let i = match index {
-                        ExprNode::NumLiteral(f) => **f as usize,
-                        _ => panic!("Expected number literal, found {:?}", index),
-                    };
-                    match &mut **var {
-                        Value::EmArray(v) => {
-                            if first {
-                                v[i] = Box::new(val.unwrap());
-                                None
-                            } else {
-                                v.get_mut(i)
-                            }
-                        }
-                        n => panic!("Expected array, found {}", n),
-                    }
-                }
-            }
-            ExprNode::Name(n) => {
-                let i = match index {
-                    ExprNode::NumLiteral(f) => **f as usize,
-                    _ => panic!("Expected number literal, found {:?}", index),
-                };
-
-                let var = self
-                    .stack
-                    .get_mut(&**n)
-                    .expect(format!("Unable to find variable {}", n).as_str());
+    pub(crate) fn get_var(&self, name: &str) -> Option<Value> {
+        let owner = self.find(name)?;
+        let scope = owner.scope.borrow();
+        scope.stack.get(name).cloned()
+    }
+}
 
-                match var {
-                    Value::EmArray(v) => v.get_mut(i),
-                    _ => panic!("Expected array, found {}", var),
-                }
-            }
+//Scopes can hold functions that hold the scope, so these only look at which scope it is rather than what's in it
+impl fmt::Debug for StackFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "StackFrame({:p})", Rc::as_ptr(&self.scope))
+    }
+}
 
-            _ => panic!("Unexpected node: {:?}", ident),
-        }
+impl PartialEq for StackFrame {
+    fn eq(&self, other: &StackFrame) -> bool {
+        Rc::ptr_eq(&self.scope, &other.scope)
     }
+}
 
-    fn get_var_copy(&self, name: &str) -> Value {
-        if self.stack.contains_key(name) {
-            self.stack[name].clone()
+impl PartialOrd for StackFrame {
+    fn partial_cmp(&self, other: &StackFrame) -> Option<std::cmp::Ordering> {
+        if self == other {
+            Some(std::cmp::Ordering::Equal)
         } else {
-            Value::Null
+            None
         }
     }
-
-    //leaving this here for now in case I need it in the future
-    // fn free_var(&mut self, name: &str) {
-    //     if self.stack.contains_key(name) {
-    //         self.stack.remove(name);
-    //     }
-    // }
 }
@@ -0,0 +1,349 @@
+use crate::error::Error;
+use crate::interpreter::builtins::Builtin;
+use crate::interpreter::{param_names, undefined, StackFrame};
+use crate::lexer::{Expression, Span};
+use crate::parser::{ExprNode, Node, Slot};
+use std::collections::HashMap;
+
+//Works out where every variable lives before a script runs, and stores it in the tree. A scope declares its
+//parameters, the functions and classes defined in it, and any name assigned in it that can't already be seen
+//from there, and each of those gets a slot in the scope's frame. Everything declared at the top level is a
+//global, which is still looked up by name so scripts can share them with the host and with each other.
+
+///Resolves every variable in `tree`, failing on the first name outside of a function that isn't defined
+///anywhere. Names that are already in `globals`, and builtins that are called by name, count as defined.
+pub(crate) fn resolve(
+    tree: &mut Node,
+    globals: &StackFrame,
+    builtins: &HashMap<String, Builtin>,
+) -> Result<(), Error> {
+    let mut r = Resolver {
+        scopes: vec![],
+        functions: 0,
+        globals,
+        builtins,
+    };
+    let region: Vec<&Node> = match &tree.kind {
+        ExprNode::Block(v) => v.iter().collect(),
+        _ => vec![&*tree],
+    };
+    let mut names = vec![];
+    for node in region {
+        assigned_names(node, &mut names);
+    }
+    r.scopes.push(Scope {
+        vars: names.into_iter().map(|(n, _)| (n, 0)).collect(),
+        len: 0,
+    });
+    r.body(tree)
+}
+
+struct Scope {
+    vars: HashMap<String, usize>, //name, slot
+    len: usize,                   //how many slots the scope's frame has
+}
+
+struct Resolver<'a> {
+    scopes: Vec<Scope>, //the global scope first, innermost last
+    functions: usize,   //how many function bodies the resolver is inside of
+    globals: &'a StackFrame,
+    builtins: &'a HashMap<String, Builtin>,
+}
+
+impl Resolver<'_> {
+    ///Finds the variable `name` can see, where `Some(None)` is a global. Scopes without a frame aren't counted
+    ///in the depth, since nothing makes one for them when the code runs.
+    fn find(&self, name: &str) -> Option<Option<Slot>> {
+        let mut depth = 0;
+        for scope in self.scopes[1..].iter().rev() {
+            if let Some(&index) = scope.vars.get(name) {
+                return Some(Some(Slot { depth, index }));
+            }
+            if scope.len > 0 {
+                depth += 1;
+            }
+        }
+        if self.scopes[0].vars.contains_key(name) || self.globals.get_var(name).is_some() {
+            Some(None)
+        } else {
+            None
+        }
+    }
+
+    ///Finds the variable `name` can see. Code that runs straight away can only use names that are already
+    ///defined, but a function might not be called until a script loaded later has defined what it uses, so
+    ///there any name that isn't found is a global that's checked for when the function runs.
+    fn var(&self, name: &str, span: &Span) -> Result<Option<Slot>, Error> {
+        match self.find(name) {
+            Some(var) => Ok(var),
+            None if self.functions > 0 => Ok(None),
+            None => Err(undefined(name).at(span)),
+        }
+    }
+
+    ///Where a function or class called `name` is defined, which is always the innermost scope
+    fn definition(&self, name: &Expression) -> Option<Slot> {
+        match (self.scopes.len(), name) {
+            (1, _) => None,
+            (_, Expression::Ident(n)) => {
+                let scope = self.scopes.last()?;
+                scope.vars.get(n).map(|&index| Slot { depth: 0, index })
+            }
+            _ => None,
+        }
+    }
+
+    ///Opens a scope for `params` and the variables `region` declares, returning how many slots it needs.
+    ///Parameters always come first so calls can put the arguments straight into their slots.
+    fn enter(&mut self, params: &[Node], region: &[&Node]) -> usize {
+        let mut vars = HashMap::new();
+        for (i, p) in param_names(params).iter().enumerate() {
+            vars.insert(p.to_string(), i);
+        }
+        let mut len = params.len();
+        let mut names = vec![];
+        for node in region {
+            assigned_names(node, &mut names);
+        }
+        for (name, always) in names {
+            if vars.contains_key(&name) || (!always && self.find(&name).is_some()) {
+                continue;
+            }
+            vars.insert(name, len);
+            len += 1;
+        }
+        self.scopes.push(Scope { vars, len });
+        len
+    }
+
+    fn exit(&mut self) {
+        self.scopes.pop();
+    }
+
+    ///Resolves a function body or a whole script, whose block runs directly in the scope that's already open
+    fn body(&mut self, body: &mut Node) -> Result<(), Error> {
+        match &mut body.kind {
+            ExprNode::Block(v) => self.nodes(v),
+            _ => self.node(body),
+        }
+    }
+
+    fn nodes(&mut self, nodes: &mut [Node]) -> Result<(), Error> {
+        nodes.iter_mut().try_for_each(|n| self.node(n))
+    }
+
+    ///Resolves a function's parameters and body, returning how many slots its frame needs
+    fn function(&mut self, params: &[Node], body: &mut Node) -> Result<usize, Error> {
+        let locals = match &body.kind {
+            ExprNode::Block(v) => self.enter(params, &v.iter().collect::<Vec<_>>()),
+            _ => self.enter(params, &[&*body]),
+        };
+        self.functions += 1;
+        let res = self.body(body);
+        self.functions -= 1;
+        self.exit();
+        res.map(|_| locals)
+    }
+
+    fn node(&mut self, node: &mut Node) -> Result<(), Error> {
+        match &mut node.kind {
+            ExprNode::Name(n) => node.var = self.var(n, &node.span)?,
+            ExprNode::Operation(o, l, r) => {
+                self.node(l)?;
+                //the right side of a member access is a property, not a variable
+                if **o != Expression::Operator('.') {
+                    self.node(r)?;
+                }
+            }
+            ExprNode::Call(name, args) => {
+                if let Expression::Ident(n) = &**name {
+                    node.var = match self.find(n) {
+                        Some(var) => var,
+                        None if self.builtins.contains_key(n) => None,
+                        None => self.var(n, &node.span)?,
+                    };
+                }
+                self.nodes(args)?;
+            }
+            ExprNode::MethodCall(method, args) => {
+                if let ExprNode::Operation(_, target, member) = &mut method.kind {
+                    let qualified = match (&target.kind, member.kind.inner()) {
+                        (ExprNode::Name(n), Some(prop)) => format!("{}.{}", n, prop),
+                        _ => String::new(),
+                    };
+                    //builtins like `json.parse` are only used when there isn't a variable hiding them
+                    let builtin = match &target.kind {
+                        ExprNode::Name(n) => {
+                            self.find(n).is_none() && self.builtins.contains_key(&qualified)
+                        }
+                        _ => false,
+                    };
+                    if !builtin {
+                        self.node(target)?;
+                    }
+                }
+                self.nodes(args)?;
+            }
+            ExprNode::Invoke(callee, args) => {
+                self.node(callee)?;
+                self.nodes(args)?;
+            }
+            ExprNode::Block(v) => {
+                node.locals = self.enter(&[], &v.iter().collect::<Vec<_>>());
+                let res = self.nodes(v);
+                self.exit();
+                res?;
+            }
+            ExprNode::Func(name, params, body) => {
+                node.var = self.definition(name);
+                node.locals = self.function(params, body)?;
+            }
+            ExprNode::Lambda(params, body) => node.locals = self.function(params, body)?,
+            ExprNode::Class(name, body) => {
+                node.var = self.definition(name);
+                //methods get a scope of their own so they don't end up defined next to the class
+                if let ExprNode::Block(v) = &mut body.kind {
+                    node.locals = self.enter(&[], &v.iter().collect::<Vec<_>>());
+                    let res = self.nodes(v);
+                    self.exit();
+                    res?;
+                }
+            }
+            ExprNode::New(name, args) => {
+                if let Expression::Ident(n) = &**name {
+                    node.var = self.var(n, &node.span)?;
+                }
+                self.nodes(args)?;
+            }
+            ExprNode::Loop(ty, cond, body) => match &mut cond.kind {
+                //the loop variable only exists for the duration of the loop
+                ExprNode::ForLoopDec(dec, con, inc) if **ty == "for" => {
+                    node.locals = self.enter(&[], &[&**dec, &**con, &**inc, &**body]);
+                    let res = self
+                        .node(dec)
+                        .and_then(|_| self.node(con))
+                        .and_then(|_| self.node(inc))
+                        .and_then(|_| self.node(body));
+                    self.exit();
+                    res?;
+                }
+                //what's looped over is worked out before the loop's scope starts, and the loop variables are
+                //always new ones, like parameters
+                ExprNode::ForIn(vars, over) => {
+                    self.node(over)?;
+                    node.locals = self.enter(vars, &[&**body]);
+                    let res = self.nodes(vars).and_then(|_| self.node(body));
+                    self.exit();
+                    res?;
+                }
+                _ => {
+                    self.node(cond)?;
+                    self.node(body)?;
+                }
+            },
+            ExprNode::ForLoopDec(a, b, c) | ExprNode::IfStatement(a, b, c) => {
+                self.node(a)?;
+                self.node(b)?;
+                self.node(c)?;
+            }
+            ExprNode::Index(a, b) => {
+                self.node(a)?;
+                self.node(b)?;
+            }
+            ExprNode::Range(a, b, c, _) => {
+                self.node(a)?;
+                self.node(b)?;
+                self.node(c)?;
+            }
+            ExprNode::Group(e)
+            | ExprNode::ReturnVal(e)
+            | ExprNode::ElseStatement(e)
+            | ExprNode::Unary(_, e) => self.node(e)?,
+            ExprNode::Array(v) => self.nodes(v)?,
+            ExprNode::Map(v) => {
+                for (k, v) in v {
+                    self.node(k)?;
+                    self.node(v)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+///The nodes that are run in the same scope as `node`. Blocks, functions, classes, and for loops make scopes
+///of their own, so what's inside of them isn't included, apart from what a for-in loop goes through.
+fn same_scope(node: &Node) -> Vec<&Node> {
+    match &node.kind {
+        ExprNode::Block(_) | ExprNode::Func(..) | ExprNode::Lambda(..) | ExprNode::Class(..) => {
+            vec![]
+        }
+        ExprNode::Loop(ty, cond, _) if **ty == "for" => match &cond.kind {
+            ExprNode::ForIn(_, over) => vec![over],
+            _ => vec![],
+        },
+        _ => children(node),
+    }
+}
+
+pub(crate) fn children(node: &Node) -> Vec<&Node> {
+    match &node.kind {
+        ExprNode::Operation(_, l, r) => vec![l, r],
+        ExprNode::Call(_, args) | ExprNode::New(_, args) | ExprNode::Array(args) => {
+            args.iter().collect()
+        }
+        ExprNode::Invoke(callee, args) | ExprNode::MethodCall(callee, args) => {
+            let mut nodes = vec![&**callee];
+            nodes.extend(args);
+            nodes
+        }
+        ExprNode::Block(v) => v.iter().collect(),
+        ExprNode::Func(_, params, body) | ExprNode::Lambda(params, body) => {
+            let mut nodes: Vec<&Node> = params.iter().collect();
+            nodes.push(body);
+            nodes
+        }
+        ExprNode::Class(_, body) => vec![body],
+        ExprNode::Loop(_, a, b) | ExprNode::Index(a, b) => vec![a, b],
+        ExprNode::ForLoopDec(a, b, c)
+        | ExprNode::IfStatement(a, b, c)
+        | ExprNode::Range(a, b, c, _) => vec![a, b, c],
+        ExprNode::ForIn(vars, over) => {
+            let mut nodes: Vec<&Node> = vars.iter().collect();
+            nodes.push(over);
+            nodes
+        }
+        ExprNode::Group(e)
+        | ExprNode::ReturnVal(e)
+        | ExprNode::ElseStatement(e)
+        | ExprNode::Unary(_, e) => vec![e],
+        ExprNode::Map(v) => v.iter().flat_map(|(k, v)| vec![k, v]).collect(),
+        _ => vec![],
+    }
+}
+
+///Collects the variables that running `node` could declare in the current scope, and whether they're always
+///declared there, like definitions are
+fn assigned_names(node: &Node, names: &mut Vec<(String, bool)>) {
+    let found = match &node.kind {
+        ExprNode::Operation(o, l, _) if **o == Expression::Equal => match &l.kind {
+            ExprNode::Name(n) => Some((n.to_string(), false)),
+            _ => None,
+        },
+        ExprNode::Func(n, ..) | ExprNode::Class(n, _) => match &**n {
+            Expression::Ident(n) => Some((n.clone(), true)),
+            _ => None,
+        },
+        _ => None,
+    };
+    if let Some((name, always)) = found {
+        match names.iter_mut().find(|(n, _)| *n == name) {
+            Some(existing) => existing.1 |= always,
+            None => names.push((name, always)),
+        }
+    }
+    for child in same_scope(node) {
+        assigned_names(child, names);
+    }
+}
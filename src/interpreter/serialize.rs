@@ -0,0 +1,592 @@
+use crate::error::{Error, RuntimeErrorKind};
+use crate::interpreter::types::{EmMap, EmObject, MapKey};
+use crate::interpreter::Value;
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+use serde::ser::{self, Serialize, Serializer};
+use serde::{forward_to_deserialize_any, Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+//Bridges script values and serde, so anything serde understands can be handed to a script and read back out.
+//Structs become objects, maps become maps, sequences and tuples become arrays, and enums are written the way
+//serde_json does, as a string for unit variants and a map with one entry for everything else.
+
+///Turns anything serde can serialize into a script value
+pub fn to_value<T: Serialize + ?Sized>(val: &T) -> Result<Value, Error> {
+    val.serialize(ValueSerializer)
+}
+
+///Reads a script value back out as anything serde can deserialize
+pub fn from_value<T: DeserializeOwned>(val: Value) -> Result<T, Error> {
+    T::deserialize(val)
+}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error::runtime(RuntimeErrorKind::TypeMismatch, msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error::runtime(RuntimeErrorKind::TypeMismatch, msg.to_string())
+    }
+}
+
+///A map with a single entry, which is how enum variants that hold something are written
+fn variant(name: &str, val: Value) -> Value {
+    let mut map = EmMap::default();
+    map.entries.insert(MapKey::Str(name.to_owned()), val);
+    Value::map(map)
+}
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::Null => s.serialize_unit(),
+            Value::Int(i) => s.serialize_i64(*i),
+            Value::Float(f) => s.serialize_f64(*f),
+            Value::EmString(st) | Value::Name(st) => s.serialize_str(st),
+            Value::EmBool(b) => s.serialize_bool(*b),
+            Value::EmArray(items) => s.collect_seq(items.borrow().iter()),
+            Value::Map(m) => {
+                s.collect_map(m.borrow().entries.iter().map(|(k, v)| (k.to_value(), v)))
+            }
+            Value::Object(o) => s.collect_map(o.borrow().data_members()),
+            Value::Function(..) | Value::Closure(_) => {
+                Err(<S::Error as ser::Error>::custom(format!(
+                    "Can't serialize {}, functions only exist inside scripts",
+                    self
+                )))
+            }
+        }
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a null, bool, number, string, sequence, or map")
+    }
+
+    fn visit_bool<E>(self, b: bool) -> Result<Value, E> {
+        Ok(Value::EmBool(b))
+    }
+
+    fn visit_i64<E>(self, i: i64) -> Result<Value, E> {
+        Ok(Value::Int(i))
+    }
+
+    fn visit_u64<E: de::Error>(self, u: u64) -> Result<Value, E> {
+        i64::try_from(u)
+            .map(Value::Int)
+            .map_err(|_| E::custom(format!("{} doesn't fit in a script integer", u)))
+    }
+
+    fn visit_f64<E>(self, f: f64) -> Result<Value, E> {
+        Ok(Value::Float(f))
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Value, E> {
+        Ok(Value::EmString(s.to_owned()))
+    }
+
+    fn visit_string<E>(self, s: String) -> Result<Value, E> {
+        Ok(Value::EmString(s))
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, d: D) -> Result<Value, D::Error> {
+        Value::deserialize(d)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut items = vec![];
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(Value::array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Value, A::Error> {
+        let mut map = EmMap::default();
+        while let Some((k, v)) = access.next_entry::<Value, Value>()? {
+            map.insert(&k, v)
+                .map_err(|e| <A::Error as de::Error>::custom(e.message()))?;
+        }
+        Ok(Value::map(map))
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Value, D::Error> {
+        d.deserialize_any(ValueVisitor)
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Value {
+    type Deserializer = Value;
+
+    fn into_deserializer(self) -> Value {
+        self
+    }
+}
+
+impl<'de> Deserializer<'de> for Value {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Value::Null => visitor.visit_unit(),
+            Value::Int(i) => visitor.visit_i64(i),
+            Value::Float(f) => visitor.visit_f64(f),
+            Value::EmString(s) | Value::Name(s) => visitor.visit_string(s),
+            Value::EmBool(b) => visitor.visit_bool(b),
+            Value::EmArray(items) => {
+                let items = items.borrow().clone();
+                let mut seq = SeqDeserializer::new(items.into_iter());
+                let res = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(res)
+            }
+            Value::Map(m) => {
+                let entries: Vec<(Value, Value)> = m
+                    .borrow()
+                    .entries
+                    .iter()
+                    .map(|(k, v)| (k.to_value(), v.clone()))
+                    .collect();
+                let mut map = MapDeserializer::new(entries.into_iter());
+                let res = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(res)
+            }
+            Value::Object(o) => {
+                let members: Vec<(Value, Value)> = o
+                    .borrow()
+                    .data_members()
+                    .into_iter()
+                    .map(|(k, v)| (Value::EmString(k.clone()), v.clone()))
+                    .collect();
+                let mut map = MapDeserializer::new(members.into_iter());
+                let res = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(res)
+            }
+            func @ (Value::Function(..) | Value::Closure(_)) => {
+                Err(<Error as de::Error>::custom(format!(
+                    "Can't deserialize {}, functions only exist inside scripts",
+                    func
+                )))
+            }
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Value::Null => visitor.visit_none(),
+            other => visitor.visit_some(other),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self {
+            Value::EmString(s) => {
+                visitor.visit_enum(IntoDeserializer::<Error>::into_deserializer(s))
+            }
+            Value::Map(m) if m.borrow().entries.len() == 1 => {
+                let (k, v) = m
+                    .borrow()
+                    .entries
+                    .iter()
+                    .next()
+                    .map(|(k, v)| (k.to_value(), v.clone()))
+                    .unwrap();
+                visitor.visit_enum(EnumDeserializer {
+                    variant: k,
+                    value: v,
+                })
+            }
+            other => Err(<Error as de::Error>::custom(format!(
+                "Expected a string or a map with one entry for {}, found {}",
+                name,
+                other.type_name()
+            ))),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+///Reads an enum variant written as a map with one entry
+struct EnumDeserializer {
+    variant: Value,
+    value: Value,
+}
+
+impl<'de> EnumAccess<'de> for EnumDeserializer {
+    type Error = Error;
+    type Variant = VariantDeserializer;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, VariantDeserializer), Error> {
+        let variant = seed.deserialize(self.variant)?;
+        Ok((variant, VariantDeserializer(self.value)))
+    }
+}
+
+struct VariantDeserializer(Value);
+
+impl<'de> VariantAccess<'de> for VariantDeserializer {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        <()>::deserialize(self.0)
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self.0)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.0.deserialize_seq(visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.0.deserialize_map(visitor)
+    }
+}
+
+///Builds script values out of whatever serde hands it
+struct ValueSerializer;
+
+impl Serializer for ValueSerializer {
+    type Ok = Value;
+    type Error = Error;
+    type SerializeSeq = ArrayBuilder;
+    type SerializeTuple = ArrayBuilder;
+    type SerializeTupleStruct = ArrayBuilder;
+    type SerializeTupleVariant = VariantBuilder<ArrayBuilder>;
+    type SerializeMap = MapBuilder;
+    type SerializeStruct = ObjectBuilder;
+    type SerializeStructVariant = VariantBuilder<ObjectBuilder>;
+
+    fn serialize_bool(self, v: bool) -> Result<Value, Error> {
+        Ok(Value::EmBool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value, Error> {
+        Ok(Value::Int(v as i64))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value, Error> {
+        Ok(Value::Int(v as i64))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value, Error> {
+        Ok(Value::Int(v as i64))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value, Error> {
+        Ok(Value::Int(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value, Error> {
+        Ok(Value::Int(v as i64))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value, Error> {
+        Ok(Value::Int(v as i64))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value, Error> {
+        Ok(Value::Int(v as i64))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value, Error> {
+        i64::try_from(v).map(Value::Int).map_err(|_| {
+            Error::runtime(
+                RuntimeErrorKind::Overflow,
+                format!("{} doesn't fit in a script integer", v),
+            )
+        })
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value, Error> {
+        Ok(Value::Float(v as f64))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value, Error> {
+        Ok(Value::Float(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value, Error> {
+        Ok(Value::EmString(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value, Error> {
+        Ok(Value::EmString(v.to_owned()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value, Error> {
+        Ok(Value::array(
+            v.iter().map(|b| Value::Int(*b as i64)).collect(),
+        ))
+    }
+
+    fn serialize_none(self) -> Result<Value, Error> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, v: &T) -> Result<Value, Error> {
+        v.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value, Error> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value, Error> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Value, Error> {
+        Ok(Value::EmString(variant.to_owned()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        v: &T,
+    ) -> Result<Value, Error> {
+        v.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        name: &'static str,
+        v: &T,
+    ) -> Result<Value, Error> {
+        Ok(variant(name, to_value(v)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<ArrayBuilder, Error> {
+        Ok(ArrayBuilder(Vec::with_capacity(len.unwrap_or(0))))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<ArrayBuilder, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<ArrayBuilder, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        name: &'static str,
+        len: usize,
+    ) -> Result<VariantBuilder<ArrayBuilder>, Error> {
+        Ok(VariantBuilder(name, self.serialize_seq(Some(len))?))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<MapBuilder, Error> {
+        Ok(MapBuilder {
+            map: EmMap::default(),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, name: &'static str, _len: usize) -> Result<ObjectBuilder, Error> {
+        let mut members = HashMap::new();
+        //the same name member classes get, so the object shows up as the struct's name
+        members.insert(
+            "~name".to_owned(),
+            Box::new(Value::EmString(name.to_owned())),
+        );
+        Ok(ObjectBuilder(EmObject { members }))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<VariantBuilder<ObjectBuilder>, Error> {
+        Ok(VariantBuilder(variant, self.serialize_struct(name, len)?))
+    }
+}
+
+struct ArrayBuilder(Vec<Value>);
+
+impl ser::SerializeSeq for ArrayBuilder {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, v: &T) -> Result<(), Error> {
+        self.0.push(to_value(v)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(Value::array(self.0))
+    }
+}
+
+impl ser::SerializeTuple for ArrayBuilder {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, v: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, v)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for ArrayBuilder {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, v: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, v)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+///Wraps whatever an enum variant holds in a map with the variant's name as the key
+struct VariantBuilder<T>(&'static str, T);
+
+impl ser::SerializeTupleVariant for VariantBuilder<ArrayBuilder> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, v: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(&mut self.1, v)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(variant(self.0, ser::SerializeSeq::end(self.1)?))
+    }
+}
+
+impl ser::SerializeStructVariant for VariantBuilder<ObjectBuilder> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        v: &T,
+    ) -> Result<(), Error> {
+        ser::SerializeStruct::serialize_field(&mut self.1, key, v)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(variant(self.0, ser::SerializeStruct::end(self.1)?))
+    }
+}
+
+struct MapBuilder {
+    map: EmMap,
+    ///The key that's waiting for its value
+    key: Option<Value>,
+}
+
+impl ser::SerializeMap for MapBuilder {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        let key = to_value(key)?;
+        //check the key now so the error is about the key rather than its value
+        MapKey::from_value(&key)?;
+        self.key = Some(key);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, v: &T) -> Result<(), Error> {
+        let key = self.key.take().unwrap_or(Value::Null);
+        self.map.insert(&key, to_value(v)?)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(Value::map(self.map))
+    }
+}
+
+struct ObjectBuilder(EmObject);
+
+impl ser::SerializeStruct for ObjectBuilder {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        v: &T,
+    ) -> Result<(), Error> {
+        self.0.set_prop(key.to_owned(), Box::new(to_value(v)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(Value::object(self.0))
+    }
+}
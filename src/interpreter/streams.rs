@@ -0,0 +1,66 @@
+use std::cell::RefCell;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::rc::Rc;
+
+///Where scripts read input from and write output to. These are the process's own streams unless the host
+///replaces them.
+pub(crate) struct Streams {
+    pub stdin: Box<dyn BufRead>,
+    pub stdout: Box<dyn Write>,
+    pub stderr: Box<dyn Write>,
+    ///Set while stdin is the process's own, so `read` can take single keys straight from the terminal
+    pub terminal: bool,
+}
+
+impl Default for Streams {
+    fn default() -> Self {
+        Streams {
+            stdin: Box::new(BufReader::new(io::stdin())),
+            stdout: Box::new(io::stdout()),
+            stderr: Box::new(io::stderr()),
+            terminal: true,
+        }
+    }
+}
+
+impl Streams {
+    pub fn set_stdin(&mut self, input: impl Read + 'static) {
+        self.stdin = Box::new(BufReader::new(input));
+        self.terminal = false;
+    }
+}
+
+///An output stream that collects everything written to it, for hosts that want to see what a script printed.
+///Clones share the same contents, so keep one and give the runtime another.
+#[derive(Debug, Clone, Default)]
+pub struct OutputBuffer {
+    buf: Rc<RefCell<Vec<u8>>>,
+}
+
+impl OutputBuffer {
+    pub fn new() -> OutputBuffer {
+        OutputBuffer::default()
+    }
+
+    ///Everything written so far, with anything that isn't valid UTF-8 replaced
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.buf.borrow()).into_owned()
+    }
+
+    ///Like `contents`, but empties the buffer too
+    pub fn take(&self) -> String {
+        let bytes = self.buf.replace(vec![]);
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+impl Write for OutputBuffer {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.borrow_mut().extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
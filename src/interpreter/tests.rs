@@ -1,113 +1,1581 @@
+use crate::error::{Error, RuntimeErrorKind};
 use crate::interpreter::*;
 use crate::lexer;
 use crate::lexer::Expression;
 use crate::parser;
-use crate::parser::ExprNode;
-use std::collections::HashMap;
+use crate::parser::{ExprNode, Node, Slot};
 
 #[test]
 fn generate_literals() {
-    let dummy_string = ExprNode::StrLiteral(Box::new("Test".to_owned()));
-    let dummy_number = ExprNode::NumLiteral(Box::new(69.0 as f32));
+    let dummy_string: Node = ExprNode::StrLiteral(Box::new("Test".to_owned())).into();
+    let dummy_number: Node = ExprNode::IntLiteral(69).into();
 
     let expected_string = Value::EmString("Test".to_owned());
-    let expected_number = Value::Float(69.0);
+    let expected_number = Value::Int(69);
 
     let mut r = Runtime::new();
-    let mut stack = StackFrame {
-        stack: HashMap::new(),
-    };
+    let stack = StackFrame::new();
     assert_eq!(
-        r.walk_tree(&dummy_string, &mut stack).unwrap(),
+        r.walk_tree(&dummy_string, &Env::global(&stack)).unwrap(),
         expected_string
     );
 
     assert_eq!(
-        r.walk_tree(&dummy_number, &mut stack).unwrap(),
+        r.walk_tree(&dummy_number, &Env::global(&stack)).unwrap(),
         expected_number
     );
 }
 
 #[test]
 fn assign_vars() {
-    let op = ExprNode::Operation(
+    let op: Node = ExprNode::Operation(
         Box::new(Expression::Equal),
-        Box::new(ExprNode::Name(Box::new("test".to_owned()))),
-        Box::new(ExprNode::StrLiteral(Box::new("this is a test".to_owned()))),
-    );
+        Box::new(ExprNode::Name(Box::new("test".to_owned())).into()),
+        Box::new(ExprNode::StrLiteral(Box::new("this is a test".to_owned())).into()),
+    )
+    .into();
 
     let expected = Value::EmString("this is a test".to_owned());
 
     let mut r = Runtime::new();
-    let mut stack = StackFrame {
-        stack: HashMap::new(),
-    };
-    r.walk_tree(&op, &mut stack).expect("Unable to walk tree");
-    assert_eq!(stack.get_var(&"test"), &expected);
+    let stack = StackFrame::new();
+    r.walk_tree(&op, &Env::global(&stack))
+        .expect("Unable to walk tree");
+    assert_eq!(stack.get_var("test"), Some(expected));
 }
 
 #[test]
 fn looping() {
     let ty = String::from("while");
-    let condition = ExprNode::Operation(
+    let condition: Node = ExprNode::Operation(
         Box::new(Expression::BoolOp("<".to_owned())),
-        Box::new(ExprNode::Name(Box::new("i".to_owned()))),
-        Box::new(ExprNode::NumLiteral(Box::new(10 as f32))),
-    );
-    let block = ExprNode::Block(vec![ExprNode::Operation(
+        Box::new(ExprNode::Name(Box::new("i".to_owned())).into()),
+        Box::new(ExprNode::IntLiteral(10).into()),
+    )
+    .into();
+    let block: Node = ExprNode::Block(vec![ExprNode::Operation(
         Box::new(Expression::Equal),
-        Box::new(ExprNode::Name(Box::new("i".to_owned()))),
-        Box::new(ExprNode::Operation(
-            Box::new(Expression::Operator('+')),
-            Box::new(ExprNode::Name(Box::new("i".to_owned()))),
-            Box::new(ExprNode::NumLiteral(Box::new(1.0 as f32))),
-        )),
-    )]);
+        Box::new(ExprNode::Name(Box::new("i".to_owned())).into()),
+        Box::new(
+            ExprNode::Operation(
+                Box::new(Expression::Operator('+')),
+                Box::new(ExprNode::Name(Box::new("i".to_owned())).into()),
+                Box::new(ExprNode::IntLiteral(1).into()),
+            )
+            .into(),
+        ),
+    )
+    .into()])
+    .into();
     // let loop_test = ExprNode::Loop(Box::new(ty), Box::new(condition), Box::new(block));
     let mut r = Runtime::new();
-    let mut stack = StackFrame::new();
-    stack.set_var(String::from("i"), Value::Float(0.0 as f32));
-    r.do_loop(&ty, &condition, &block, &mut stack)
+    let stack = StackFrame::new();
+    stack.set_var(String::from("i"), Value::Int(0));
+    r.do_loop(&ty, &condition, &block, &Env::global(&stack))
         .expect("Error executing loop");
 
-    assert_eq!(*stack.get_var("i"), Value::Float(10.0));
+    assert_eq!(stack.get_var("i"), Some(Value::Int(10)));
 
     //test for loops here
 
     //include a file with the code to test in it so it can be updated more easily
-    if let Ok(dummy) = parser::parse(lexer::run(include_str!("test_files/for_test.em"))) {
+    if let Ok(dummy) = parser::parse(lexer::run(include_str!("test_files/for_test.em")).unwrap()) {
         let mut runtime = Runtime::new();
-        let mut frame = StackFrame::new();
+        let frame = StackFrame::new();
 
-        repl_run(dummy, &mut runtime, &mut frame).expect("Unable to perform run");
+        repl_run(dummy, &mut runtime, &frame).expect("Unable to perform run");
 
-        return assert_eq!(Value::Float(10.0), *frame.get_var("result"));
+        return assert_eq!(Some(Value::Int(10)), frame.get_var("result"));
     }
 
-    assert!(false);
+    panic!("Unable to parse for loop test");
 }
 
 //this effectively also tests if arrays is working correctly due to the way the test file is written
 #[test]
 fn if_elif_else() {
-    if let Ok(dummy) = parser::parse(lexer::run(include_str!("test_files/if_elif_else_test.em"))) {
+    if let Ok(dummy) =
+        parser::parse(lexer::run(include_str!("test_files/if_elif_else_test.em")).unwrap())
+    {
         let mut runtime = Runtime::new();
-        let mut frame = StackFrame::new();
+        let frame = StackFrame::new();
 
-        match repl_run(dummy, &mut runtime, &mut frame) {
+        match repl_run(dummy, &mut runtime, &frame) {
             Ok(_) => {}
             Err(e) => println!("Got this error running the if test: {}", e),
         }
 
-        if let Value::EmArray(v) = frame.get_var("res") {
-            for val in v {
-                match **val {
-                    Value::EmBool(b) => return assert!(b),
-                    _ => {}
+        if let Some(Value::EmArray(v)) = frame.get_var("res") {
+            for val in v.borrow().iter() {
+                if let Value::EmBool(b) = *val {
+                    return assert!(b);
                 }
             }
         }
 
-        assert!(false);
+        panic!("Didn't find any results from the if test");
+    }
+}
+
+#[test]
+fn error_locations() {
+    let dummy = parser::parse(lexer::run_file("test.em", "x = 1;\n\nfoo(x);").unwrap()).unwrap();
+    let mut runtime = Runtime::new();
+    let frame = StackFrame::new();
+
+    let err = repl_run(dummy, &mut runtime, &frame).unwrap_err();
+    assert!(err.to_string().starts_with("test.em:3:1:"), "{}", err);
+}
+
+#[test]
+fn runtime_errors() {
+    let dummy =
+        parser::parse(lexer::run_file("test.em", "x = [true, false];\nx[5];").unwrap()).unwrap();
+    let mut runtime = Runtime::new();
+    let frame = StackFrame::new();
+
+    match repl_run(dummy, &mut runtime, &frame) {
+        Err(Error::RuntimeError { kind, span, .. }) => {
+            assert_eq!(kind, RuntimeErrorKind::IndexOutOfBounds);
+            assert_eq!(span.unwrap().line, 2);
+        }
+        other => panic!("Expected an index error, got {:?}", other),
+    }
+
+    //builtins shouldn't panic when they don't get enough arguments
+    let dummy = parser::parse(lexer::run("println();").unwrap()).unwrap();
+    let err = repl_run(dummy, &mut runtime, &frame).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::RuntimeError {
+            kind: RuntimeErrorKind::WrongArgumentCount,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn operator_precedence() {
+    let dummy =
+        parser::parse(lexer::run("a = 2 * 3 + 4;\nb = 10 - 3 - 2;\nc = 2 * (3 + 4);").unwrap())
+            .unwrap();
+    let mut runtime = Runtime::new();
+    let frame = StackFrame::new();
+    repl_run(dummy, &mut runtime, &frame).unwrap();
+
+    assert_eq!(frame.get_var("a"), Some(Value::Int(10)));
+    assert_eq!(frame.get_var("b"), Some(Value::Int(5)));
+    assert_eq!(frame.get_var("c"), Some(Value::Int(14)));
+}
+
+#[test]
+fn scoping() {
+    let source = "
+        total = 10;
+        fn add(n) {
+            total = total + n;
+            return total;
+        }
+        fn counter() {
+            count = 0;
+            fn inc() {
+                count = count + 1;
+                return count;
+            }
+            return inc;
+        }
+        add(5);
+        if true {
+            hidden = 1;
+        }
+        next = counter();
+        next();
+        last = next();
+    ";
+    let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+    let mut runtime = Runtime::new();
+    let frame = StackFrame::new();
+    repl_run(dummy, &mut runtime, &frame).unwrap();
+
+    //functions can see and update globals
+    assert_eq!(frame.get_var("total"), Some(Value::Int(15)));
+    //variables made inside a block stay there
+    assert_eq!(frame.get_var("hidden"), None);
+    assert_eq!(frame.get_var("count"), None);
+    //closures keep the scope they were made in alive between calls
+    assert_eq!(frame.get_var("last"), Some(Value::Int(2)));
+
+    let dummy = parser::parse(lexer::run("if true { inner = 1; }\ninner;").unwrap()).unwrap();
+    let err = repl_run(dummy, &mut runtime, &frame).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::RuntimeError {
+            kind: RuntimeErrorKind::UndefinedName,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn nested_returns() {
+    let source = "
+        fn pick(x) {
+            if x > 1 {
+                while true {
+                    return \"big\";
+                }
+            }
+            return \"small\";
+        }
+        a = pick(5);
+        b = pick(0);
+    ";
+    let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+    let mut runtime = Runtime::new();
+    let frame = StackFrame::new();
+    repl_run(dummy, &mut runtime, &frame).unwrap();
+
+    assert_eq!(frame.get_var("a"), Some(Value::EmString("big".to_owned())));
+    assert_eq!(
+        frame.get_var("b"),
+        Some(Value::EmString("small".to_owned()))
+    );
+}
+
+#[test]
+fn first_class_functions() {
+    let source = "
+        fn apply(f, x) {
+            return f(x);
+        }
+        fn adder(n) {
+            return x => x + n;
+        }
+        double = fn (x) { return x * 2; };
+        a = apply(double, 4);
+        b = apply((x) => x - 1, 4);
+        c = adder(10)(5);
+        fns = [double, adder(1)];
+        d = fns[1](1);
+    ";
+    let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+    let mut runtime = Runtime::new();
+    let frame = StackFrame::new();
+    repl_run(dummy, &mut runtime, &frame).unwrap();
+
+    assert_eq!(frame.get_var("a"), Some(Value::Int(8)));
+    assert_eq!(frame.get_var("b"), Some(Value::Int(3)));
+    assert_eq!(frame.get_var("c"), Some(Value::Int(15)));
+    assert_eq!(frame.get_var("d"), Some(Value::Int(2)));
+
+    //builtins call back into scripts the same way
+    let double = frame.get_var("double").unwrap();
+    assert_eq!(
+        runtime.call_function(&double, vec![Value::Int(21)]),
+        Ok(Value::Int(42))
+    );
+    assert_eq!(double.to_string(), "fn (x)");
+
+    let err = runtime.call_function(&Value::Int(1), vec![]).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::RuntimeError {
+            kind: RuntimeErrorKind::TypeMismatch,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn reference_semantics() {
+    let source = "
+        class Counter {
+            fn ~init(self) {
+                self.count = 0;
+            }
+            fn bump(self) {
+                self.count = self.count + 1;
+            }
+        }
+        fn fill(arr) {
+            arr[0] = 10;
+        }
+        c = new Counter();
+        c.bump();
+        c.bump();
+        alias = c;
+        alias.bump();
+        other = new Counter();
+
+        xs = [1, 2];
+        ys = xs;
+        ys[1] = 5;
+        fill(xs);
+        grid = [[0], [0]];
+        grid[1][0] = 3;
+    ";
+    let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+    let mut runtime = Runtime::new();
+    let frame = StackFrame::new();
+    repl_run(dummy, &mut runtime, &frame).unwrap();
+
+    let count = |name: &str| match frame.get_var(name) {
+        Some(Value::Object(o)) => o.borrow().get_prop("count").cloned(),
+        _ => None,
+    };
+    assert_eq!(count("c"), Some(Value::Int(3)));
+    assert_eq!(count("alias"), Some(Value::Int(3)));
+    assert_eq!(count("other"), Some(Value::Int(0)));
+
+    let expected = Value::array(vec![Value::Int(10), Value::Int(5)]);
+    assert_eq!(frame.get_var("xs"), Some(expected.clone()));
+    assert_eq!(frame.get_var("ys"), Some(expected));
+    assert_eq!(
+        frame.get_var("grid").map(|g| g.to_string()),
+        Some("[[0], [3]]".to_owned())
+    );
+}
+
+#[test]
+fn maps() {
+    let source = "
+        ages = { \"ann\": 31, \"bob\": 27 };
+        ages[\"cy\"] = 40;
+        ages[\"bob\"] = ages[\"bob\"] + 1;
+        mixed = { 1: \"one\", true: \"yes\" };
+        a = ages[\"bob\"];
+        b = mixed[1];
+        c = mixed[true];
+        d = ages.has(\"ann\");
+        e = ages.remove(\"ann\");
+        f = ages.has(\"ann\");
+        keys = ages.keys();
+        vals = ages.values();
+        shared = ages;
+        shared[\"dee\"] = 1;
+        g = ages.len();
+    ";
+    let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+    let mut runtime = Runtime::new();
+    let frame = StackFrame::new();
+    repl_run(dummy, &mut runtime, &frame).unwrap();
+
+    assert_eq!(frame.get_var("a"), Some(Value::Int(28)));
+    assert_eq!(frame.get_var("b"), Some(Value::EmString("one".to_owned())));
+    assert_eq!(frame.get_var("c"), Some(Value::EmString("yes".to_owned())));
+    assert_eq!(frame.get_var("d"), Some(Value::EmBool(true)));
+    assert_eq!(frame.get_var("e"), Some(Value::Int(31)));
+    assert_eq!(frame.get_var("f"), Some(Value::EmBool(false)));
+    assert_eq!(
+        frame.get_var("keys").map(|k| k.to_string()),
+        Some("[\"bob\", \"cy\"]".to_owned())
+    );
+    assert_eq!(
+        frame.get_var("vals").map(|k| k.to_string()),
+        Some("[28, 40]".to_owned())
+    );
+    assert_eq!(frame.get_var("g"), Some(Value::Int(3)));
+    assert_eq!(
+        frame.get_var("mixed").map(|m| m.to_string()),
+        Some("{true: \"yes\", 1: \"one\"}".to_owned())
+    );
+
+    let run_err = |source: &str| {
+        let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+        repl_run(dummy, &mut Runtime::new(), &StackFrame::new()).unwrap_err()
+    };
+    assert!(matches!(
+        run_err("m = {}; m[\"nope\"];"),
+        Error::RuntimeError {
+            kind: RuntimeErrorKind::MissingProperty,
+            ..
+        }
+    ));
+    assert!(matches!(
+        run_err("m = { [1]: 2 };"),
+        Error::RuntimeError {
+            kind: RuntimeErrorKind::TypeMismatch,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn integers_and_floats() {
+    let source = "
+        id = 9007199254740993;
+        next = id + 2;
+        a = 7 / 2;
+        b = 7 / 2.0;
+        c = 1.5 * 2;
+        d = 1 == 1.0;
+        e = 2 < 2.5;
+        xs = [10, 20];
+        f = xs[1];
+        m = { 1: \"one\" };
+        g = m[1.0];
+        shown = \"\" + [2.0, -0.5, 3, 10.0 ** 20] + \" \" + 4.0;
+    ";
+    let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+    let mut runtime = Runtime::new();
+    let frame = StackFrame::new();
+    repl_run(dummy, &mut runtime, &frame).unwrap();
+
+    assert_eq!(
+        frame.get_var("next"),
+        Some(Value::Int(9_007_199_254_740_995))
+    );
+    assert_eq!(frame.get_var("a"), Some(Value::Int(3)));
+    assert_eq!(frame.get_var("b"), Some(Value::Float(3.5)));
+    assert_eq!(frame.get_var("c"), Some(Value::Float(3.0)));
+    assert_eq!(frame.get_var("d"), Some(Value::EmBool(true)));
+    assert_eq!(frame.get_var("e"), Some(Value::EmBool(true)));
+    assert_eq!(frame.get_var("f"), Some(Value::Int(20)));
+    assert_eq!(frame.get_var("g"), Some(Value::EmString("one".to_owned())));
+    //whole floats are still shown as floats
+    assert_eq!(
+        frame.get_var("shown"),
+        Some(Value::EmString(
+            "[2.0, -0.5, 3, 100000000000000000000.0] 4.0".to_owned()
+        ))
+    );
+
+    let run_err = |source: &str| {
+        let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+        match repl_run(dummy, &mut Runtime::new(), &StackFrame::new()) {
+            Err(Error::RuntimeError { kind, .. }) => kind,
+            other => panic!("Expected a runtime error, got {:?}", other),
+        }
+    };
+    assert_eq!(
+        run_err("x = 9223372036854775807 + 1;"),
+        RuntimeErrorKind::Overflow
+    );
+    assert_eq!(run_err("x = 1 / 0;"), RuntimeErrorKind::DivisionByZero);
+    assert_eq!(run_err("x = [1]; x[0.5];"), RuntimeErrorKind::TypeMismatch);
+    assert_eq!(run_err("x = true * 2;"), RuntimeErrorKind::TypeMismatch);
+}
+
+#[test]
+fn native_functions() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let calls = Rc::new(Cell::new(0));
+    let mut runtime = Runtime::new();
+    let counter = calls.clone();
+    runtime.register_fn("tick", move |_, _| {
+        counter.set(counter.get() + 1);
+        Ok(Value::Int(counter.get()))
+    });
+    runtime.register_fn_with_arity("twice", 2, |rt, args| {
+        let once = rt.call_function(&args[0], vec![args[1].clone()])?;
+        rt.call_function(&args[0], vec![once])
+    });
+    runtime.register_fn("fail", |_, _| {
+        Err(Error::runtime(
+            RuntimeErrorKind::InvalidOperation,
+            "the host said no",
+        ))
+    });
+
+    let source = "
+        tick();
+        a = tick();
+        b = twice(x => x * 3, 2);
+    ";
+    let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+    let frame = StackFrame::new();
+    repl_run(dummy, &mut runtime, &frame).unwrap();
+    assert_eq!(frame.get_var("a"), Some(Value::Int(2)));
+    assert_eq!(frame.get_var("b"), Some(Value::Int(18)));
+    assert_eq!(calls.get(), 2);
+
+    let run_err = |runtime: &mut Runtime, source: &str| {
+        let dummy = parser::parse(lexer::run_file("host.em", source).unwrap()).unwrap();
+        repl_run(dummy, runtime, &StackFrame::new()).unwrap_err()
+    };
+    let err = run_err(&mut runtime, "x = 1;\ntwice(1);");
+    assert!(matches!(
+        err,
+        Error::RuntimeError {
+            kind: RuntimeErrorKind::WrongArgumentCount,
+            ..
+        }
+    ));
+    //errors from the host point at the call in the script
+    let err = run_err(&mut runtime, "\n  fail();");
+    assert_eq!(err.message(), "the host said no");
+    assert_eq!(err.span().map(|s| (s.line, s.col)), Some((2, 3)));
+}
+
+#[test]
+fn value_conversions() {
+    use std::collections::HashMap;
+
+    assert_eq!(5u8.into_value(), Value::Int(5));
+    assert_eq!(Some("hi").into_value(), Value::EmString("hi".to_owned()));
+    assert_eq!(None::<i64>.into_value(), Value::Null);
+    assert_eq!(
+        vec![1, 2].into_value(),
+        Value::array(vec![Value::Int(1), Value::Int(2)])
+    );
+    assert_eq!((1, "a", true).into_value().to_string(), "[1, \"a\", true]");
+
+    let nested = vec![vec![1.5], vec![]].into_value();
+    assert_eq!(
+        Vec::<Vec<f64>>::from_value(nested),
+        Ok(vec![vec![1.5], vec![]])
+    );
+    assert_eq!(Option::<String>::from_value(Value::Null), Ok(None));
+    assert_eq!(f64::from_value(Value::Int(2)), Ok(2.0));
+    assert_eq!(
+        <(i64, String)>::from_value((7, "x").into_value()),
+        Ok((7, "x".to_owned()))
+    );
+
+    let mut scores = HashMap::new();
+    scores.insert("ann".to_owned(), 3);
+    let map = scores.clone().into_value();
+    assert_eq!(map.to_string(), "{\"ann\": 3}");
+    assert_eq!(HashMap::<String, i64>::from_value(map), Ok(scores));
+
+    //errors say what was wanted and what was there instead
+    let err = i64::from_value(Value::EmString("12".to_owned())).unwrap_err();
+    assert_eq!(err.message(), "Expected int, found string");
+    let err =
+        Vec::<i64>::from_value(vec![Value::Int(1), Value::EmBool(true)].into_value()).unwrap_err();
+    assert_eq!(err.message(), "Expected int, found bool");
+    assert!(err.notes().iter().any(|n| n == "at index 1 of the array"));
+    let err = u8::from_value(Value::Int(300)).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::RuntimeError {
+            kind: RuntimeErrorKind::Overflow,
+            ..
+        }
+    ));
+    let err = <(i64, i64)>::from_value(vec![1].into_value()).unwrap_err();
+    assert_eq!(err.message(), "Expected an array of 2 elements, found 1");
+
+    //native functions can unpack their arguments in one go
+    let mut runtime = Runtime::new();
+    runtime.register_fn("repeat", |_, args| {
+        let (word, times): (String, usize) = FromArgs::from_args(args)?;
+        Ok(vec![word; times].into_value())
+    });
+    let dummy = parser::parse(lexer::run("a = repeat(\"ab\", 2);").unwrap()).unwrap();
+    let frame = StackFrame::new();
+    repl_run(dummy, &mut runtime, &frame).unwrap();
+    assert_eq!(
+        frame.get_var("a").map(|a| a.to_string()),
+        Some("[\"ab\", \"ab\"]".to_owned())
+    );
+    let dummy = parser::parse(lexer::run("repeat(2);").unwrap()).unwrap();
+    assert!(matches!(
+        repl_run(dummy, &mut runtime, &frame),
+        Err(Error::RuntimeError {
+            kind: RuntimeErrorKind::WrongArgumentCount,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn json() {
+    let mut runtime = Runtime::new();
+    let frame = StackFrame::new();
+    frame.define(
+        "src".to_owned(),
+        Value::EmString(r#" {"name": "gem", "tags": ["a\n", "é😀"], "n": 3, "x": 1.5e2, "ok": true, "none": null} "#.to_owned()),
+    );
+    let source = "
+        data = json.parse(src);
+        name = data[\"name\"];
+        tag = data[\"tags\"][1];
+        sum = data[\"n\"] + data[\"x\"];
+        compact = json.stringify(data);
+        pretty = json.stringify([1, {}], 2);
+        again = json.stringify(json.parse(compact)) == compact;
+        class Point { fn ~init(self) { self.x = 1; self.y = 2; } fn len(self) { return 0; } }
+        point = json.stringify(new Point());
+    ";
+    let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+    repl_run(dummy, &mut runtime, &frame).unwrap();
+    let string = |s: &str| Some(Value::EmString(s.to_owned()));
+    assert_eq!(frame.get_var("name"), string("gem"));
+    assert_eq!(frame.get_var("tag"), string("é😀"));
+    assert_eq!(frame.get_var("sum"), Some(Value::Float(153.0)));
+    assert_eq!(
+        frame.get_var("compact"),
+        string(r#"{"n":3,"name":"gem","none":null,"ok":true,"tags":["a\n","é😀"],"x":150.0}"#)
+    );
+    assert_eq!(frame.get_var("pretty"), string("[\n  1,\n  {}\n]"));
+    assert_eq!(frame.get_var("again"), Some(Value::EmBool(true)));
+    assert_eq!(frame.get_var("point"), string(r#"{"x":1,"y":2}"#));
+
+    let run_err = |runtime: &mut Runtime, source: &str| {
+        let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+        repl_run(dummy, runtime, &frame).unwrap_err()
+    };
+    for bad in &["{\"a\" 1}", "[1, 2", "01", "{\"a\": tru}", "[1] x"] {
+        frame.define("src".to_owned(), Value::EmString((*bad).to_owned()));
+        let err = run_err(&mut runtime, "json.parse(src);");
+        assert!(
+            matches!(
+                err,
+                Error::RuntimeError {
+                    kind: RuntimeErrorKind::InvalidJson,
+                    ..
+                }
+            ),
+            "{} parsed",
+            bad
+        );
+    }
+    let err = run_err(&mut runtime, "json.stringify([1, x => x]);");
+    assert!(matches!(
+        err,
+        Error::RuntimeError {
+            kind: RuntimeErrorKind::TypeMismatch,
+            ..
+        }
+    ));
+    //indents are limited like in JavaScript, so a huge one can't take all the memory there is
+    let err = run_err(&mut runtime, "json.stringify([1], 9223372036854775807);");
+    assert!(matches!(
+        err,
+        Error::RuntimeError {
+            kind: RuntimeErrorKind::InvalidOperation,
+            ..
+        }
+    ));
+    //a value that holds the same array many times is counted every time it's written out
+    runtime.set_limits(Limits {
+        max_allocation: Some(100_000),
+        ..Limits::default()
+    });
+    let err = run_err(
+        &mut runtime,
+        "x = [\"abc\"]; for i in 0..30 { x = [x, x]; } json.stringify(x, 10);",
+    );
+    assert!(matches!(
+        err,
+        Error::RuntimeError {
+            kind: RuntimeErrorKind::LimitExceeded,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn resource_limits() {
+    use std::time::Duration;
+
+    let run_limited = |limits: Limits, source: &str| {
+        let mut runtime = Runtime::new();
+        runtime.set_limits(limits);
+        let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+        repl_run(dummy, &mut runtime, &StackFrame::new())
+    };
+    let assert_exceeded = |res: Result<String, Error>| {
+        assert!(
+            matches!(
+                res,
+                Err(Error::RuntimeError {
+                    kind: RuntimeErrorKind::LimitExceeded,
+                    span: Some(_),
+                    ..
+                })
+            ),
+            "{:?}",
+            res
+        );
+    };
+
+    let forever = "while true {}";
+    assert_exceeded(run_limited(
+        Limits {
+            max_steps: Some(1000),
+            ..Limits::default()
+        },
+        forever,
+    ));
+    assert_exceeded(run_limited(
+        Limits {
+            max_duration: Some(Duration::from_millis(20)),
+            ..Limits::default()
+        },
+        forever,
+    ));
+    assert_exceeded(run_limited(
+        Limits {
+            max_call_depth: Some(50),
+            ..Limits::default()
+        },
+        "fn f(n) { return f(n + 1); } f(0);",
+    ));
+    //runaway recursion stops with the default limits too, whether the host or the command line runs it
+    assert_eq!(Limits::default().max_call_depth, Some(DEFAULT_CALL_DEPTH));
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        let mut runtime = Runtime::new();
+        runtime.set_backend(backend);
+        let dummy =
+            parser::parse(lexer::run("fn f(n) { return f(n + 1); } f(0);").unwrap()).unwrap();
+        assert_exceeded(repl_run(dummy, &mut runtime, &StackFrame::new()));
+
+        let dummy = parser::parse(lexer::run("fn main(a) { main(a); }").unwrap()).unwrap();
+        let res = run_with(dummy, ExprNode::Array(vec![]).into(), backend);
+        assert_exceeded(res.map(|v| v.to_string()));
+    }
+    assert_exceeded(run_limited(
+        Limits {
+            max_allocation: Some(10_000),
+            ..Limits::default()
+        },
+        "s = \"x\"; while true { s = s + s; }",
+    ));
+    assert_exceeded(run_limited(
+        Limits {
+            max_allocation: Some(10_000),
+            ..Limits::default()
+        },
+        "a = []; while true { a = [a, a, a, a]; }",
+    ));
+    assert_exceeded(run_limited(
+        Limits {
+            max_allocation: Some(10_000),
+            ..Limits::default()
+        },
+        "m = {}; for i in 0..50 { m[i] = \"v\"; } for i in 0..1000 { k = m.keys(); v = m.values(); }",
+    ));
+    //~display runs under the same limits as the rest of the script, however the object ends up shown
+    for show in [
+        "s = \"\" + o;",
+        "s = f\"{o}\";",
+        "println(o);",
+        "s = \", \".join([o]);",
+    ] {
+        assert_exceeded(run_limited(
+            Limits {
+                max_steps: Some(1000),
+                ..Limits::default()
+            },
+            &format!(
+                "class L {{ fn ~display(self) {{ while true {{}} }} }} o = new L(); {}",
+                show
+            ),
+        ));
+    }
+    //and anything that goes wrong in it is an error where the object was shown
+    let res = run_limited(
+        Limits::default(),
+        "class B { fn ~display(self) { return [][0]; } } s = \"\" + new B();",
+    );
+    assert!(
+        matches!(
+            res,
+            Err(Error::RuntimeError {
+                kind: RuntimeErrorKind::IndexOutOfBounds,
+                ..
+            })
+        ),
+        "{:?}",
+        res
+    );
+
+    //scripts that stay inside the limits run normally, and each run gets the full budget again
+    let limits = Limits {
+        max_steps: Some(500),
+        max_call_depth: Some(50),
+        ..Limits::default()
+    };
+    let mut runtime = Runtime::new();
+    runtime.set_limits(limits);
+    let frame = StackFrame::new();
+    let source = "fn f(n) { if n == 0 { return 0; } return f(n - 1); } x = f(20);";
+    for _ in 0..3 {
+        let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+        repl_run(dummy, &mut runtime, &frame).unwrap();
+    }
+    assert_eq!(frame.get_var("x"), Some(Value::Int(0)));
+    assert_eq!(runtime.limits(), limits);
+}
+
+#[test]
+fn capabilities() {
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("gem-capabilities-{}", std::process::id()));
+    fs::create_dir_all(dir.join("data")).unwrap();
+    fs::write(dir.join("data/in.txt"), "hello").unwrap();
+    let run_with = |caps: Capabilities, source: &str| {
+        let mut runtime = Runtime::with_capabilities(caps);
+        let frame = StackFrame::new();
+        let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+        repl_run(dummy, &mut runtime, &frame).map(|_| frame)
+    };
+    let denied = |res: Result<StackFrame, Error>| {
+        matches!(
+            res,
+            Err(Error::RuntimeError {
+                kind: RuntimeErrorKind::PermissionDenied,
+                ..
+            })
+        )
+    };
+
+    let read_only = Capabilities {
+        io: false,
+        fs: FsAccess::ReadOnly(dir.join("data")),
+        env: false,
+    };
+    let frame = run_with(
+        read_only.clone(),
+        "a = fs.read(\"in.txt\"); b = fs.exists(\"out.txt\"); c = fs.list(\".\");",
+    )
+    .unwrap();
+    assert_eq!(
+        frame.get_var("a"),
+        Some(Value::EmString("hello".to_owned()))
+    );
+    assert_eq!(frame.get_var("b"), Some(Value::EmBool(false)));
+    assert_eq!(
+        frame.get_var("c"),
+        Some(Value::array(vec![Value::EmString("in.txt".to_owned())]))
+    );
+    assert!(denied(run_with(
+        read_only.clone(),
+        "fs.write(\"out.txt\", 1);"
+    )));
+    assert!(denied(run_with(read_only.clone(), "println(1);")));
+    //~display is held to the same capabilities as the code that shows the object
+    assert!(denied(run_with(
+        read_only.clone(),
+        "class L { fn ~display(self) { println(1); return \"l\"; } } s = \"\" + new L();",
+    )));
+    assert!(denied(run_with(read_only.clone(), "env.get(\"PATH\");")));
+    //paths can't leave the directory they're limited to
+    fs::write(dir.join("secret.txt"), "hidden").unwrap();
+    assert!(denied(run_with(read_only, "fs.read(\"../secret.txt\");")));
+
+    let read_write = Capabilities {
+        fs: FsAccess::ReadWrite(dir.join("data")),
+        ..Capabilities::none()
+    };
+    run_with(read_write.clone(), "fs.write(\"out.txt\", [1, 2]);").unwrap();
+    assert_eq!(
+        fs::read_to_string(dir.join("data/out.txt")).unwrap(),
+        "[1, 2]"
+    );
+    assert!(denied(run_with(
+        read_write.clone(),
+        "fs.write(\"../out.txt\", 1);"
+    )));
+    //a link to a file that doesn't exist yet can't be used to create it outside of the directory
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(dir.join("escaped.txt"), dir.join("data/link")).unwrap();
+        assert!(denied(run_with(read_write, "fs.write(\"link\", 1);")));
+        assert!(!dir.join("escaped.txt").exists());
+    }
+
+    assert!(denied(run_with(
+        Capabilities::none(),
+        "fs.read(\"in.txt\");"
+    )));
+    let frame = run_with(
+        Capabilities {
+            env: true,
+            ..Capabilities::none()
+        },
+        "x = env.get(\"GEM_SURELY_NOT_SET\");",
+    )
+    .unwrap();
+    assert_eq!(frame.get_var("x"), Some(Value::Null));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn streams() {
+    let mut runtime = Runtime::new();
+    let out = OutputBuffer::new();
+    let err = OutputBuffer::new();
+    runtime.set_stdin("alice\r\nbob\né!".as_bytes());
+    runtime.set_stdout(out.clone());
+    runtime.set_stderr(err.clone());
+
+    let source = "
+        a = readln(\"name? \");
+        b = readln();
+        c = read();
+        d = read();
+        e = read();
+        f = readln();
+        println(a + \" and \" + b);
+        print([1, 2]);
+        eprintln(\"oops\");
+        class Loud { fn ~display(self) { print(\"<\"); return \"loud>\"; } }
+        println(new Loud());
+    ";
+    let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+    let frame = StackFrame::new();
+    repl_run(dummy, &mut runtime, &frame).unwrap();
+    let string = |s: &str| Some(Value::EmString(s.to_owned()));
+    assert_eq!(frame.get_var("a"), string("alice"));
+    assert_eq!(frame.get_var("b"), string("bob"));
+    assert_eq!(frame.get_var("c"), string("é"));
+    assert_eq!(frame.get_var("d"), string("!"));
+    //running out of input gives null
+    assert_eq!(frame.get_var("e"), Some(Value::Null));
+    assert_eq!(frame.get_var("f"), Some(Value::Null));
+    //what ~display prints goes to the same place as everything else
+    assert_eq!(out.take(), "name? alice and bob\n[1, 2]<loud>\n");
+    assert_eq!(out.contents(), "");
+    assert_eq!(err.contents(), "oops\n");
+}
+
+#[test]
+fn bytecode_vm() {
+    //both backends should print the same things and fail the same way
+    let run_on = |backend: Backend, source: &str| {
+        let mut runtime = Runtime::new();
+        runtime.set_backend(backend);
+        let out = OutputBuffer::new();
+        runtime.set_stdout(out.clone());
+        let dummy = parser::parse(lexer::run_file("test.em", source).unwrap()).unwrap();
+        repl_run(dummy, &mut runtime, &StackFrame::new()).map(|_| out.take())
+    };
+    let scripts = [
+        "
+        fn fib(n) { if n < 2 { return n; } return fib(n - 1) + fib(n - 2); }
+        class Point {
+            fn ~init(self, x, y) { self.x = x; self.y = y; }
+            fn add(self, o) { return new Point(self.x + o.x, self.y + o.y); }
+            fn twice(self) { return add(self, self); }
+            fn ~display(self) { return \"(\" + self.x + \", \" + self.y + \")\"; }
+        }
+        p = new Point(1, 2);
+        println(fib(15) + \" \" + p.add(new Point(3, 4)) + \" \" + p.twice());
+        m = { \"a\": [1, 2.5], 3: null };
+        m[\"b\"] = m.len();
+        println(json.stringify(m) + [1, \"two\"][1] + m[3]);
+        ",
+        "
+        total = 10;
+        i = 7;
+        fn add(n) { total += n; local = n; return total; }
+        fn counter() {
+            count = 0;
+            return () => { count += 1; return count; };
+        }
+        add(5);
+        next = counter();
+        next();
+        adders = [null, null, null];
+        for (i = 0; i < 3; i++) {
+            k = i * 10;
+            adders[i] = x => x + k;
+        }
+        j = 0;
+        while j < 3 { if j == 1 { seen = j; } elif j > 1 { println(\"late\"); } else { println(\"early\"); } j++; }
+        println(\"\" + total + next() + i + adders[0](1) + adders[2](1));
+        println(fn (a, b) { return a - b; }(5, 3));
+        ",
+        "x = 1;\nfn f() { return y; }\nf();",
+        "if true { hidden = 1; }\nhidden;",
+        "fn f(a) { return a; }\nf(1, 2);",
+        "a = [1];\na[3] = 2;",
+        "m = {};\nm.nope();",
+        "json.nope(1);",
+        "println(1 / 0);",
+    ];
+    for source in scripts.iter() {
+        let expected = run_on(Backend::TreeWalker, source);
+        assert_eq!(run_on(Backend::Vm, source), expected, "{}", source);
+    }
+    assert_eq!(
+        run_on(Backend::Vm, scripts[1]).unwrap(),
+        "early\nlate\n1523121\n2\n"
+    );
+
+    //functions compiled for the VM can be called by the host, and they share the globals
+    let mut engine = crate::Engine::new();
+    engine.runtime().set_backend(Backend::Vm);
+    engine
+        .load("count = 1;\nfn bump(n) { count = count + n; return count; }")
+        .unwrap();
+    assert_eq!(engine.call::<i64>("bump", (2,)), Ok(3));
+    assert_eq!(engine.get::<i64>("count"), Ok(3));
+
+    //script calls push a VM frame instead of recursing on the native stack
+    let deep =
+        "fn down(n) { if n == 0 { return 0; } return down(n - 1) + 1; }\nprintln(down(9000));";
+    assert_eq!(run_on(Backend::Vm, deep).unwrap(), "9000\n");
+
+    //every instruction counts as a step
+    let mut runtime = Runtime::new();
+    runtime.set_backend(Backend::Vm);
+    runtime.set_limits(Limits {
+        max_steps: Some(1000),
+        ..Limits::default()
+    });
+    let dummy = parser::parse(lexer::run("while true {}").unwrap()).unwrap();
+    let err = repl_run(dummy, &mut runtime, &StackFrame::new()).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::RuntimeError {
+            kind: RuntimeErrorKind::LimitExceeded,
+            span: Some(_),
+            ..
+        }
+    ));
+}
+
+#[test]
+fn resolver() {
+    let source = "
+        x = 1;
+        fn outer(a) {
+            b = a + x;
+            fn inner() { return b; }
+            return inner;
+        }
+    ";
+    let mut tree = parser::parse(lexer::run(source).unwrap()).unwrap();
+    let functions = builtins::get_functions();
+    resolver::resolve(&mut tree, &StackFrame::new(), &functions).unwrap();
+
+    let outer = match &tree.kind {
+        ExprNode::Block(v) => v[1].clone(),
+        other => panic!("Unexpected tree {:?}", other),
+    };
+    //a, b, and inner each get a slot, with the parameter first
+    assert_eq!(outer.locals, 3);
+    assert_eq!(outer.var, None);
+    let (b, inner) = match &outer.kind {
+        ExprNode::Func(_, _, body) => match &body.kind {
+            ExprNode::Block(v) => (v[0].clone(), v[1].clone()),
+            other => panic!("Unexpected body {:?}", other),
+        },
+        other => panic!("Unexpected function {:?}", other),
+    };
+    assert_eq!(inner.var, Some(Slot { depth: 0, index: 2 }));
+    if let ExprNode::Operation(_, target, val) = &b.kind {
+        assert_eq!(target.var, Some(Slot { depth: 0, index: 1 }));
+        //globals are still looked up by name
+        assert!(matches!(&val.kind, ExprNode::Operation(_, a, x)
+            if a.var == Some(Slot { depth: 0, index: 0 }) && x.var.is_none()));
+    } else {
+        panic!("Unexpected assignment {:?}", b);
+    }
+    //inner has no variables of its own, so outer's frame is the closest one
+    if let ExprNode::Func(_, _, body) = &inner.kind {
+        assert!(matches!(&body.kind, ExprNode::Block(v)
+            if matches!(&v[0].kind, ExprNode::ReturnVal(b) if b.var == Some(Slot { depth: 0, index: 1 }))));
+    }
+
+    //undefined names in code that runs straight away are found before anything runs, on both backends
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        let mut runtime = Runtime::new();
+        runtime.set_backend(backend);
+        let out = OutputBuffer::new();
+        runtime.set_stdout(out.clone());
+        let source = "println(\"ran\");\nif true { x = missing; }";
+        let dummy = parser::parse(lexer::run_file("test.em", source).unwrap()).unwrap();
+        let err = repl_run(dummy, &mut runtime, &StackFrame::new()).unwrap_err();
+        assert!(err.to_string().starts_with("test.em:2:15:"), "{}", err);
+        assert_eq!(out.take(), "");
+
+        //functions can use names that get defined later, so those are only missing once they're used
+        let frame = StackFrame::new();
+        let source = "fn f() { return missing; }\nfn g() { return absent(1); }";
+        let dummy = parser::parse(lexer::run_file("test.em", source).unwrap()).unwrap();
+        repl_run(dummy, &mut runtime, &frame).unwrap();
+        for call in ["f();", "g();"] {
+            let dummy = parser::parse(lexer::run_file("test.em", call).unwrap()).unwrap();
+            let err = repl_run(dummy, &mut runtime, &frame).unwrap_err();
+            assert!(
+                matches!(
+                    err,
+                    Error::RuntimeError {
+                        kind: RuntimeErrorKind::UndefinedName,
+                        ..
+                    }
+                ),
+                "{}",
+                err
+            );
+        }
+    }
+
+    //globals the host or an earlier script defined can be used, and assigning to one from a function changes it
+    let frame = StackFrame::new();
+    frame.define("host".to_owned(), Value::Int(2));
+    let source = "fn bump() { host = host + later; } later = 3; bump();";
+    let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+    repl_run(dummy, &mut Runtime::new(), &frame).unwrap();
+    assert_eq!(frame.get_var("host"), Some(Value::Int(5)));
+}
+
+#[test]
+fn logical_operators() {
+    let source = "
+        fn side(v) { calls = calls + 1; return v; }
+        calls = 0;
+        a = true && side(false);
+        b = false && side(true);
+        c = true || side(false);
+        d = false or side(true);
+        e = not (1 < 2) and true;
+        f = !null;
+        g = -calls * 2 + +1;
+        h = -(2.5);
+    ";
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        let mut runtime = Runtime::new();
+        runtime.set_backend(backend);
+        let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+        let frame = StackFrame::new();
+        repl_run(dummy, &mut runtime, &frame).unwrap();
+        //the right side only runs when the left side doesn't decide the result
+        assert_eq!(frame.get_var("calls"), Some(Value::Int(2)));
+        assert_eq!(frame.get_var("a"), Some(Value::EmBool(false)));
+        assert_eq!(frame.get_var("b"), Some(Value::EmBool(false)));
+        assert_eq!(frame.get_var("c"), Some(Value::EmBool(true)));
+        assert_eq!(frame.get_var("d"), Some(Value::EmBool(true)));
+        assert_eq!(frame.get_var("e"), Some(Value::EmBool(false)));
+        assert_eq!(frame.get_var("f"), Some(Value::EmBool(true)));
+        assert_eq!(frame.get_var("g"), Some(Value::Int(-3)));
+        assert_eq!(frame.get_var("h"), Some(Value::Float(-2.5)));
+
+        let mut fails = |source: &str| {
+            let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+            match repl_run(dummy, &mut runtime, &StackFrame::new()) {
+                Err(Error::RuntimeError { kind, .. }) => kind,
+                other => panic!("Expected an error, got {:?}", other),
+            }
+        };
+        assert_eq!(fails("x = -\"a\";"), RuntimeErrorKind::TypeMismatch);
+        assert_eq!(fails("x = +true;"), RuntimeErrorKind::TypeMismatch);
+        assert_eq!(
+            fails("x = -(-9223372036854775807 - 1);"),
+            RuntimeErrorKind::Overflow
+        );
+    }
+}
+
+#[test]
+fn comparisons() {
+    let source = r#"
+        numbers = [1 < 2.5, 2.0 >= 2, 3 > 2, -1 <= -2];
+        same_type = ["a" < "b", false < true, null <= null, [1, 2] < [1, 3], [1] < [1, 0]];
+        //values of different types aren't less or greater than each other either way around
+        mixed = ["abc" > 5, "abc" < 5, null < 1, null >= 1, [1] < ["a"], {} < {}];
+        equality = [5 != "5", 1 == 1.0, null == null, [1, "a"] == [1, "a"]];
+    "#;
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        let mut runtime = Runtime::new();
+        runtime.set_backend(backend);
+        let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+        let frame = StackFrame::new();
+        repl_run(dummy, &mut runtime, &frame).unwrap();
+        let shown = |name: &str| frame.get_var(name).unwrap().to_string();
+        assert_eq!(shown("numbers"), "[true, true, true, false]");
+        assert_eq!(shown("same_type"), "[true, true, true, true, true]");
+        assert_eq!(shown("mixed"), "[false, false, false, false, false, false]");
+        assert_eq!(shown("equality"), "[true, true, true, true]");
+    }
+}
+
+#[test]
+fn remainder_power_and_floor_division() {
+    let source = "
+        a = [7 % 3, -7 % 3, 7 % -3, -7.5 % 2];
+        b = [7 div 2, -7 div 2, 7.5 div 2];
+        c = [2 ** 10, -2 ** 2, 2 ** -1, 2 ** 3 ** 2];
+        d = 10;
+        d %= 4;
+        d **= 3;
+    ";
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        let mut runtime = Runtime::new();
+        runtime.set_backend(backend);
+        let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+        let frame = StackFrame::new();
+        repl_run(dummy, &mut runtime, &frame).unwrap();
+        //the remainder takes the sign of the divisor, so it matches division that rounds down
+        assert_eq!(frame.get_var("a").unwrap().to_string(), "[1, 2, -2, 0.5]");
+        assert_eq!(frame.get_var("b").unwrap().to_string(), "[3, -4, 3.0]");
+        assert_eq!(
+            frame.get_var("c").unwrap().to_string(),
+            "[1024, -4, 0.5, 512]"
+        );
+        assert_eq!(frame.get_var("d"), Some(Value::Int(8)));
+
+        let mut fails = |source: &str| {
+            let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+            match repl_run(dummy, &mut runtime, &StackFrame::new()) {
+                Err(Error::RuntimeError { kind, .. }) => kind,
+                other => panic!("Expected an error, got {:?}", other),
+            }
+        };
+        assert_eq!(fails("x = 1 % 0;"), RuntimeErrorKind::DivisionByZero);
+        assert_eq!(fails("x = 1 div 0;"), RuntimeErrorKind::DivisionByZero);
+        assert_eq!(fails("x = 10 ** 19;"), RuntimeErrorKind::Overflow);
+        assert_eq!(
+            fails("x = (-9223372036854775807 - 1) div -1;"),
+            RuntimeErrorKind::Overflow
+        );
+        //only adding joins strings
+        assert_eq!(fails("x = \"ab\" * 2;"), RuntimeErrorKind::TypeMismatch);
+    }
+}
+
+#[test]
+fn fstrings() {
+    let source = r#"
+        class Money {
+            fn ~init(self, cents) { self.cents = cents; }
+            fn ~display(self) { return f"${self.cents div 100}.{self.cents % 100}"; }
+        }
+        sum = 10;
+        n = 4;
+        items = ["a", "b"];
+        a = f"total: {sum / n} of {n}, {{ok}}";
+        b = f"{items[1]}{f"-{items[0]}-"}{items}";
+        c = f"price: {new Money(1250)}";
+    "#;
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        let mut runtime = Runtime::new();
+        runtime.set_backend(backend);
+        let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+        let frame = StackFrame::new();
+        repl_run(dummy, &mut runtime, &frame).unwrap();
+        let string = |s: &str| Some(Value::EmString(s.to_owned()));
+        assert_eq!(frame.get_var("a"), string("total: 2 of 4, {ok}"));
+        assert_eq!(frame.get_var("b"), string("b-a-[\"a\", \"b\"]"));
+        //objects are shown with ~display, like anything else that's turned into a string
+        assert_eq!(frame.get_var("c"), string("price: $12.50"));
+    }
+}
+
+#[test]
+fn string_methods() {
+    let source = r#"
+        s = "  Grüße, 世界!  ".trim();
+        a = [s.len(), s[3], s.slice(7), s.slice(0, 5), s.find("世"), s.find("?")];
+        b = [s.upper(), s.lower(), s.starts_with("Grü"), s.replace("ß", "ss")];
+        c = "a,b,,c".split(",");
+        d = ", ".join([1, "two", 3.5]);
+        e = "añ😀".chars();
+    "#;
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        let mut runtime = Runtime::new();
+        runtime.set_backend(backend);
+        let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+        let frame = StackFrame::new();
+        repl_run(dummy, &mut runtime, &frame).unwrap();
+        let shown = |name: &str| frame.get_var(name).unwrap().to_string();
+        //lengths and positions count characters, not bytes
+        assert_eq!(shown("a"), r#"[10, "ß", "世界!", "Grüße", 7, -1]"#);
+        assert_eq!(
+            shown("b"),
+            r#"["GRÜSSE, 世界!", "grüße, 世界!", true, "Grüsse, 世界!"]"#
+        );
+        assert_eq!(shown("c"), r#"["a", "b", "", "c"]"#);
+        assert_eq!(shown("d"), "1, two, 3.5");
+        assert_eq!(shown("e"), r#"["a", "ñ", "😀"]"#);
+
+        let mut fails = |source: &str| {
+            let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+            match repl_run(dummy, &mut runtime, &StackFrame::new()) {
+                Err(Error::RuntimeError { kind, .. }) => kind,
+                other => panic!("Expected an error, got {:?}", other),
+            }
+        };
+        assert_eq!(fails("x = \"añ\"[2];"), RuntimeErrorKind::IndexOutOfBounds);
+        assert_eq!(
+            fails("x = \"añ\".slice(1, 3);"),
+            RuntimeErrorKind::IndexOutOfBounds
+        );
+        assert_eq!(
+            fails("x = \"a\".split(\"\");"),
+            RuntimeErrorKind::InvalidOperation
+        );
+        assert_eq!(fails("x = \"a\".find(1);"), RuntimeErrorKind::TypeMismatch);
+        assert_eq!(
+            fails("x = \"a\".trim(1);"),
+            RuntimeErrorKind::WrongArgumentCount
+        );
+        assert_eq!(fails("x = \"a\".pop();"), RuntimeErrorKind::MissingProperty);
+        assert_eq!(
+            fails("x = \"a\"; x[0] = \"b\";"),
+            RuntimeErrorKind::InvalidOperation
+        );
+    }
+}
+
+#[test]
+fn array_methods() {
+    let source = r#"
+        a = [3, 1, 2];
+        a.push(5);
+        a.insert(0, 9);
+        a.insert(5, 7);
+        popped = [a.pop(), a.remove(0)];
+        b = [a.len(), a.slice(1), a.slice(1, 2), a.concat([4.5, "x"]), a.contains(2.0), a.index_of(7)];
+        a.reverse();
+        reversed = a.slice(0);
+        a.sort();
+        people = [["bo", 30], ["al", 25], ["cy", 30]];
+        people.sort((x, y) => y[1] - x[1]);
+        c = [a.map(x => x * 10), a.filter(x => x % 2 == 1), a.reduce((s, x) => s + x), [].reduce((s, x) => s + x, 0)];
+        //functions can change the array while a method goes through it
+        d = [1, 2];
+        d.map(x => d.push(x));
+        //copying an array that holds itself only copies the reference to it
+        e = [1];
+        e.push(e);
+        e = [e.slice(0).len(), e.concat(e).len()];
+    "#;
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        let mut runtime = Runtime::new();
+        runtime.set_backend(backend);
+        let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+        let frame = StackFrame::new();
+        repl_run(dummy, &mut runtime, &frame).unwrap();
+        let shown = |name: &str| frame.get_var(name).unwrap().to_string();
+        assert_eq!(shown("popped"), "[7, 9]");
+        assert_eq!(
+            shown("b"),
+            r#"[4, [1, 2, 5], [1], [3, 1, 2, 5, 4.5, "x"], true, -1]"#
+        );
+        assert_eq!(shown("reversed"), "[5, 2, 1, 3]");
+        assert_eq!(shown("a"), "[1, 2, 3, 5]");
+        //sorting is stable, so people the same age stay in the order they were in
+        assert_eq!(shown("people"), r#"[["bo", 30], ["cy", 30], ["al", 25]]"#);
+        assert_eq!(shown("c"), "[[10, 20, 30, 50], [1, 3, 5], 11, 0]");
+        assert_eq!(shown("d"), "[1, 2, 1, 2]");
+        assert_eq!(shown("e"), "[2, 4]");
+
+        let mut fails = |source: &str| {
+            let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+            match repl_run(dummy, &mut runtime, &StackFrame::new()) {
+                Err(Error::RuntimeError { kind, .. }) => kind,
+                other => panic!("Expected an error, got {:?}", other),
+            }
+        };
+        assert_eq!(fails("x = [].pop();"), RuntimeErrorKind::IndexOutOfBounds);
+        assert_eq!(
+            fails("x = [1].remove(1);"),
+            RuntimeErrorKind::IndexOutOfBounds
+        );
+        assert_eq!(
+            fails("x = [1].insert(-1, 0);"),
+            RuntimeErrorKind::IndexOutOfBounds
+        );
+        assert_eq!(
+            fails("x = [1].slice(0, 2);"),
+            RuntimeErrorKind::IndexOutOfBounds
+        );
+        assert_eq!(
+            fails("x = [1, \"a\"]; x.sort();"),
+            RuntimeErrorKind::TypeMismatch
+        );
+        assert_eq!(
+            fails("x = [2, 1]; x.sort((a, b) => null);"),
+            RuntimeErrorKind::TypeMismatch
+        );
+        assert_eq!(
+            fails("x = [].reduce((a, b) => a);"),
+            RuntimeErrorKind::InvalidOperation
+        );
+        assert_eq!(fails("x = [1].map(3);"), RuntimeErrorKind::TypeMismatch);
+        assert_eq!(
+            fails("x = [1].filter((a, b) => a);"),
+            RuntimeErrorKind::WrongArgumentCount
+        );
     }
 }
+
+#[test]
+fn for_in_loops() {
+    let source = r#"
+        class Point { fn ~init(self) { self.x = 1; self.y = 2; } }
+        out = [];
+        for x in [1, 2, 3] { out.push(x * 10); }
+        for i, c in "héy" { out.push(f"{i}:{c}"); }
+        for k in {"b": 1, "a": 2} { out.push(k); }
+        for k in new Point() { out.push(k); }
+        ranges = [];
+        for i in 0..3 { ranges.push(i); }
+        for i in 0..=6 step 3 { ranges.push(i); }
+        for i in 5..0 step -2 { ranges.push(i); }
+        for i in 3..3 { ranges.push(i); }
+        //the loop sees elements added while it runs
+        grown = [1];
+        for x in grown { if x < 4 { grown.push(x + 1); } }
+        //every time around gets its own variable
+        fs = [];
+        for x in [1, 2] { fs.push(() => x); }
+        captured = [fs[0](), fs[1]()];
+        x = "outer";
+        for x in [1] { }
+        fn first_over(arr, n) { for i, x in arr { if x > n { return i; } } return -1; }
+        found = [first_over([5, 6, 7], 5), first_over([1], 5)];
+        nested = [];
+        for i in 0..2 { for j in 0..2 { nested.push(i * 10 + j); } }
+    "#;
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        let mut runtime = Runtime::new();
+        runtime.set_backend(backend);
+        let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+        let frame = StackFrame::new();
+        repl_run(dummy, &mut runtime, &frame).unwrap();
+        let shown = |name: &str| frame.get_var(name).unwrap().to_string();
+        assert_eq!(
+            shown("out"),
+            r#"[10, 20, 30, "0:h", "1:é", "2:y", "a", "b", "x", "y"]"#
+        );
+        assert_eq!(shown("ranges"), "[0, 1, 2, 0, 3, 6, 5, 3, 1]");
+        assert_eq!(shown("grown"), "[1, 2, 3, 4]");
+        assert_eq!(shown("captured"), "[1, 2]");
+        assert_eq!(shown("x"), "outer");
+        assert_eq!(shown("found"), "[1, -1]");
+        assert_eq!(shown("nested"), "[0, 1, 10, 11]");
+
+        let mut fails = |source: &str| {
+            let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+            match repl_run(dummy, &mut runtime, &StackFrame::new()) {
+                Err(Error::RuntimeError { kind, .. }) => kind,
+                other => panic!("Expected an error, got {:?}", other),
+            }
+        };
+        assert_eq!(
+            fails("for i in 0..3 step 0 { }"),
+            RuntimeErrorKind::InvalidOperation
+        );
+        assert_eq!(fails("for i in 0..1.5 { }"), RuntimeErrorKind::TypeMismatch);
+        assert_eq!(fails("for i in 3 { }"), RuntimeErrorKind::TypeMismatch);
+    }
+}
+
+#[test]
+fn cyclic_values() {
+    let source = r#"
+        class Node { fn ~init(self) { self.next = null; } }
+        a = [1];
+        a.push(a);
+        b = [1];
+        b.push(b);
+        arrays = [a == a, a == b, a == [1, [2]], a < b, a <= b, "" + a];
+        o = new Node();
+        o.next = o;
+        p = new Node();
+        p.next = p;
+        objects = [o == o, o == p];
+        m = {};
+        m["self"] = m;
+        n = {};
+        n["self"] = n;
+        maps = [m == m, m == n, "" + m];
+    "#;
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        let mut runtime = Runtime::new();
+        runtime.set_backend(backend);
+        let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+        let frame = StackFrame::new();
+        repl_run(dummy, &mut runtime, &frame).unwrap();
+        let shown = |name: &str| frame.get_var(name).unwrap().to_string();
+        //an array that holds itself is shown as [...] where it turns up again
+        assert_eq!(
+            shown("arrays"),
+            r#"[true, true, false, false, true, "[1, [...]]"]"#
+        );
+        assert_eq!(shown("objects"), "[true, true]");
+        assert_eq!(shown("maps"), r#"[true, true, "{"self": {...}}"]"#);
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_bridge() {
+    use serde::{Deserialize, Serialize};
+    use std::collections::HashMap;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Level {
+        Low,
+        Custom(i64),
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Config {
+        name: String,
+        retries: u32,
+        ratio: f64,
+        tags: Vec<String>,
+        limits: HashMap<String, i64>,
+        parent: Option<Box<Config>>,
+        level: Level,
+    }
+
+    let mut limits = HashMap::new();
+    limits.insert("cpu".to_owned(), 2);
+    let config = Config {
+        name: "main".to_owned(),
+        retries: 3,
+        ratio: 0.5,
+        tags: vec!["a".to_owned()],
+        limits,
+        parent: None,
+        level: Level::Custom(7),
+    };
+
+    //scripts see structs as objects and can change them like any other
+    let mut runtime = Runtime::new();
+    let frame = StackFrame::new();
+    frame.define("config".to_owned(), to_value(&config).unwrap());
+    let source = "
+        a = config.limits[\"cpu\"];
+        b = config.tags[0];
+        c = config.level[\"Custom\"];
+        config.retries = config.retries + 1;
+        config.level = \"Low\";
+    ";
+    let dummy = parser::parse(lexer::run(source).unwrap()).unwrap();
+    repl_run(dummy, &mut runtime, &frame).unwrap();
+    assert_eq!(frame.get_var("a"), Some(Value::Int(2)));
+    assert_eq!(frame.get_var("b"), Some(Value::EmString("a".to_owned())));
+    assert_eq!(frame.get_var("c"), Some(Value::Int(7)));
+
+    let back: Config = from_value(frame.get_var("config").unwrap()).unwrap();
+    assert_eq!(
+        back,
+        Config {
+            retries: 4,
+            level: Level::Low,
+            ..config
+        }
+    );
+
+    //values built by scripts can be read back too
+    let dummy = parser::parse(lexer::run("x = [1, { \"k\": null }];").unwrap()).unwrap();
+    repl_run(dummy, &mut runtime, &frame).unwrap();
+    let x: (i64, HashMap<String, Option<bool>>) = from_value(frame.get_var("x").unwrap()).unwrap();
+    assert_eq!(x.1.get("k"), Some(&None));
+
+    let err = from_value::<Config>(Value::Int(1)).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::RuntimeError {
+            kind: RuntimeErrorKind::TypeMismatch,
+            ..
+        }
+    ));
+    let dummy = parser::parse(lexer::run("f = x => x;").unwrap()).unwrap();
+    repl_run(dummy, &mut runtime, &frame).unwrap();
+    let func = frame.get_var("f").unwrap();
+    assert!(to_value(&func).is_err());
+}
@@ -1,22 +1,15 @@
+use crate::error::{Error, RuntimeErrorKind};
 use crate::interpreter::Value;
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
+///Arrays are shared, so indexing hands out copies of the elements and assigning goes through the shared reference
 pub trait Indexable<T> {
-    fn index<'a>(&'a self, index: usize) -> Result<&'a T, String>;
+    fn index(&self, index: usize) -> Result<T, Error>;
 
-    fn index_mut<'a>(&'a mut self, index: usize) -> Result<&'a mut T, String>;
+    fn set_index(&self, index: usize, val: T) -> Result<(), Error>;
 }
 
-pub trait Valuable {
-    fn inner(&self) -> &Value;
-    fn set_value(&mut self, val: Value);
-}
-
-pub trait Object {
-    fn get_prop(&self, prop: &'static str) -> Option<&dyn Valuable>;
-    fn set_prop(&mut self, prop: &str, val: Box<dyn Valuable>);
-}
 #[derive(Debug, Clone, PartialEq)]
 pub struct EmObject {
     pub members: HashMap<String, Box<Value>>,
@@ -34,13 +27,28 @@ impl EmObject {
     pub fn set_prop(&mut self, prop: String, val: Box<Value>) {
         self.members.insert(prop, val);
     }
+
+    ///The members that hold data, leaving out methods and the ones the interpreter uses like `~name`. They're
+    ///sorted by name, since objects don't keep them in any order
+    pub fn data_members(&self) -> Vec<(&String, &Value)> {
+        let mut members: Vec<(&String, &Value)> = self
+            .members
+            .iter()
+            .map(|(k, v)| (k, &**v))
+            .filter(|(k, v)| {
+                !k.starts_with('~') && !matches!(v, Value::Function(..) | Value::Closure(_))
+            })
+            .collect();
+        members.sort_by(|a, b| a.0.cmp(b.0));
+        members
+    }
 }
 
 impl std::fmt::Display for EmObject {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if let Some(v) = self.get_prop("~name"){
+        if let Some(v) = self.get_prop("~name") {
             write!(f, "{}", v)
-        }else {
+        } else {
             write!(f, "{:?}", self)
         }
     }
@@ -52,3 +60,133 @@ impl PartialOrd for EmObject {
         Some(self.members.len().cmp(&other.members.len()))
     }
 }
+
+///The values that can be used as keys in a map
+#[derive(Debug, Clone)]
+pub enum MapKey {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+impl MapKey {
+    ///Turns `val` into a key, or complains if it's a type that can't be one
+    pub fn from_value(val: &Value) -> Result<MapKey, Error> {
+        match val {
+            Value::EmBool(b) => Ok(MapKey::Bool(*b)),
+            Value::Int(i) => Ok(MapKey::Int(*i)),
+            //1.0 == 1, so whole floats need to be the same key as the integer they're equal to
+            Value::Float(f) if f.fract() == 0.0 && f.abs() < i64::MAX as f64 => {
+                Ok(MapKey::Int(*f as i64))
+            }
+            Value::Float(f) => Ok(MapKey::Float(*f)),
+            Value::EmString(s) => Ok(MapKey::Str(s.clone())),
+            other => Err(Error::runtime(
+                RuntimeErrorKind::TypeMismatch,
+                format!("{} can't be used as a map key", other),
+            )
+            .with_note("map keys can be strings, numbers, or bools")),
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            MapKey::Bool(b) => Value::EmBool(*b),
+            MapKey::Int(i) => Value::Int(*i),
+            MapKey::Float(f) => Value::Float(*f),
+            MapKey::Str(s) => Value::EmString(s.clone()),
+        }
+    }
+}
+
+impl MapKey {
+    ///Where each kind of key goes relative to the others
+    fn rank(&self) -> u8 {
+        match self {
+            MapKey::Bool(_) => 0,
+            MapKey::Int(_) | MapKey::Float(_) => 1,
+            MapKey::Str(_) => 2,
+        }
+    }
+}
+
+//Keys are sorted bools first, then numbers, then strings, which also decides the order maps are iterated in
+impl Ord for MapKey {
+    fn cmp(&self, other: &MapKey) -> Ordering {
+        match (self, other) {
+            (MapKey::Bool(a), MapKey::Bool(b)) => a.cmp(b),
+            (MapKey::Int(a), MapKey::Int(b)) => a.cmp(b),
+            (MapKey::Float(a), MapKey::Float(b)) => a.total_cmp(b),
+            //integers and floats are sorted together, an integer goes first if they're somehow the same
+            (MapKey::Int(a), MapKey::Float(b)) => (*a as f64).total_cmp(b).then(Ordering::Less),
+            (MapKey::Float(a), MapKey::Int(b)) => a.total_cmp(&(*b as f64)).then(Ordering::Greater),
+            (MapKey::Str(a), MapKey::Str(b)) => a.cmp(b),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for MapKey {
+    fn partial_cmp(&self, other: &MapKey) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for MapKey {
+    fn eq(&self, other: &MapKey) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for MapKey {}
+
+impl std::fmt::Display for MapKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MapKey::Str(s) => write!(f, "\"{}\"", s),
+            other => write!(f, "{}", other.to_value()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, PartialOrd)]
+pub struct EmMap {
+    pub entries: BTreeMap<MapKey, Value>,
+}
+
+impl EmMap {
+    pub fn get(&self, key: &Value) -> Result<Value, Error> {
+        let k = MapKey::from_value(key)?;
+        self.entries.get(&k).cloned().ok_or_else(|| {
+            Error::runtime(
+                RuntimeErrorKind::MissingProperty,
+                format!("Key {} not found in map", k),
+            )
+            .with_note("use has() to check whether a key is there first")
+        })
+    }
+
+    pub fn insert(&mut self, key: &Value, val: Value) -> Result<(), Error> {
+        self.entries.insert(MapKey::from_value(key)?, val);
+        Ok(())
+    }
+
+    pub fn has(&self, key: &Value) -> Result<bool, Error> {
+        Ok(self.entries.contains_key(&MapKey::from_value(key)?))
+    }
+
+    ///Removes `key` from the map, returning what it held or null if it wasn't there
+    pub fn remove(&mut self, key: &Value) -> Result<Value, Error> {
+        let k = MapKey::from_value(key)?;
+        Ok(self.entries.remove(&k).unwrap_or(Value::Null))
+    }
+
+    pub fn keys(&self) -> Vec<Value> {
+        self.entries.keys().map(|k| k.to_value()).collect()
+    }
+
+    pub fn values(&self) -> Vec<Value> {
+        self.entries.values().cloned().collect()
+    }
+}
@@ -0,0 +1,709 @@
+use super::{Home, Op, Place, Proto};
+use crate::error::{Error, RuntimeErrorKind};
+use crate::interpreter::resolver::children;
+use crate::interpreter::{invalid_operator, param_names, unary_op, Arithmetic, Comparison, Value};
+use crate::lexer::{Expression, Span};
+use crate::parser::{ExprNode, Node, Slot};
+use std::mem;
+use std::rc::Rc;
+
+///Compiles a whole script into the function that runs it. The top level works with the global scope by
+///name so that scripts can share it with the host and with each other, everything else gets slots.
+pub(crate) fn compile(tree: &Node) -> Proto {
+    let mut c = Compiler {
+        protos: vec![Proto::default()],
+        tops: vec![0],
+        level: 0,
+        scopes: vec![],
+        span: tree.span.clone(),
+    };
+    c.body(tree);
+    c.protos.pop().unwrap_or_default()
+}
+
+///Where the slots of a scope the resolver made a frame for ended up
+#[derive(Clone, Copy)]
+enum Scope {
+    Stack(u32), //where its slots start in the function's part of the stack
+    Frame(u32), //which frame on the heap has its slots, counting from the outermost
+}
+
+struct Compiler {
+    protos: Vec<Proto>, //the functions being compiled, innermost last
+    tops: Vec<u32>,     //how many slots on the stack each of them is using so far
+    level: u32,         //how many frames on the heap there are around the code being compiled
+    scopes: Vec<Scope>,
+    span: Span,
+}
+
+impl Compiler {
+    fn proto(&mut self) -> &mut Proto {
+        self.protos
+            .last_mut()
+            .expect("Compiled outside of a function")
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let span = self.span.clone();
+        let proto = self.proto();
+        proto.ops.push(op);
+        proto.spans.push(span);
+        proto.ops.len() - 1
+    }
+
+    fn emit_at(&mut self, op: Op, span: &Span) -> usize {
+        let outer = mem::replace(&mut self.span, span.clone());
+        let at = self.emit(op);
+        self.span = outer;
+        at
+    }
+
+    ///Points the jump at `at` to the next op
+    fn patch(&mut self, at: usize) {
+        let target = self.proto().ops.len() as u32;
+        match &mut self.proto().ops[at] {
+            Op::Jump(t) | Op::JumpIfFalse(t) | Op::JumpUnless(_, t) | Op::Next(t) => *t = target,
+            op => unreachable!("Tried to patch {:?}", op),
+        }
+    }
+
+    fn add_const(&mut self, val: Value) -> u32 {
+        let consts = &mut self.proto().consts;
+        consts.push(val);
+        consts.len() as u32 - 1
+    }
+
+    fn constant(&mut self, val: Value) {
+        let i = self.add_const(val);
+        self.emit(Op::Const(i));
+    }
+
+    fn name(&mut self, name: &str) -> u32 {
+        let names = &mut self.proto().names;
+        match names.iter().position(|n| n == name) {
+            Some(i) => i as u32,
+            None => {
+                names.push(name.to_owned());
+                names.len() as u32 - 1
+            }
+        }
+    }
+
+    ///Makes running this op fail with `err`, for mistakes that the tree walker only finds once it gets to them
+    fn fail(&mut self, err: Error) {
+        let i = self.proto().errors.len() as u32;
+        self.proto().errors.push(err);
+        self.emit(Op::Fail(i));
+    }
+
+    ///Turns where the resolver put a variable into where it is on the stack or in the frames
+    fn home(&self, var: Option<Slot>) -> Home {
+        match var {
+            Some(v) => match self.scopes[self.scopes.len() - 1 - v.depth] {
+                Scope::Stack(start) => Home::Local(start as usize + v.index),
+                Scope::Frame(level) => Home::Frame(Slot {
+                    depth: (self.level - level) as usize,
+                    index: v.index,
+                }),
+            },
+            None => Home::Global,
+        }
+    }
+
+    fn place(&mut self, name: &str, var: Option<Slot>) -> u32 {
+        let home = self.home(var);
+        let places = &mut self.proto().places;
+        places.push(Place {
+            name: name.to_owned(),
+            home,
+        });
+        places.len() as u32 - 1
+    }
+
+    ///Pushes the value of a variable. Locals on the stack get ops of their own, since they're most of what
+    ///loops work with.
+    fn get(&mut self, name: &str, var: Option<Slot>) {
+        match self.home(var) {
+            Home::Local(slot) => {
+                let n = self.name(name);
+                self.emit(Op::GetLocal(slot as u32, n));
+            }
+            _ => {
+                let place = self.place(name, var);
+                self.emit(Op::GetVar(place));
+            }
+        }
+    }
+
+    ///Assigns the value on top of the stack to a variable, leaving it there
+    fn set(&mut self, name: &str, var: Option<Slot>) {
+        match self.home(var) {
+            Home::Local(slot) => self.emit(Op::SetLocal(slot as u32)),
+            _ => {
+                let place = self.place(name, var);
+                self.emit(Op::SetVar(place))
+            }
+        };
+    }
+
+    ///Same as `set` but pops the value
+    fn assign(&mut self, name: &str, var: Option<Slot>) {
+        match self.home(var) {
+            Home::Local(slot) => self.emit(Op::AssignLocal(slot as u32)),
+            _ => {
+                let place = self.place(name, var);
+                self.emit(Op::Assign(place))
+            }
+        };
+    }
+
+    ///Same as `set`, but a global is made in the innermost global scope
+    fn define(&mut self, name: &str, var: Option<Slot>) {
+        match self.home(var) {
+            Home::Local(slot) => self.emit(Op::SetLocal(slot as u32)),
+            _ => {
+                let place = self.place(name, var);
+                self.emit(Op::Define(place))
+            }
+        };
+    }
+
+    ///Takes `n` slots on the stack for the function being compiled, giving where they start
+    fn reserve(&mut self, n: u32) -> u32 {
+        let top = self
+            .tops
+            .last_mut()
+            .expect("Compiled outside of a function");
+        let start = *top;
+        *top += n;
+        let proto = self.proto();
+        proto.locals = proto.locals.max((start + n) as usize);
+        start
+    }
+
+    ///Gives back the slots on the stack from `start` up, once the scope using them is done
+    fn release(&mut self, start: u32) {
+        if let Some(top) = self.tops.last_mut() {
+            *top = start;
+        }
+    }
+
+    ///Compiles `nodes` in a scope with `locals` slots. The slots go on the stack and are cleared each time the
+    ///scope is entered, unless something in it can hold on to the scope, then it gets a frame on the heap.
+    fn block(&mut self, locals: usize, nodes: &[&Node], f: impl FnOnce(&mut Compiler)) {
+        if locals == 0 {
+            return f(self);
+        }
+        let locals = locals as u32;
+        if nodes.iter().any(|n| makes_closure(n)) {
+            self.level += 1;
+            self.scopes.push(Scope::Frame(self.level));
+            self.emit(Op::PushScope(locals));
+            f(self);
+            self.emit(Op::PopScope);
+            self.scopes.pop();
+            self.level -= 1;
+        } else {
+            let start = self.reserve(locals);
+            self.emit(Op::ClearLocals(start, start + locals));
+            self.scopes.push(Scope::Stack(start));
+            f(self);
+            self.scopes.pop();
+            self.release(start);
+        }
+    }
+
+    ///Compiles a function body or the whole script. Blocks run directly in the function's scope.
+    fn body(&mut self, body: &Node) {
+        match &body.kind {
+            ExprNode::Block(v) => {
+                for node in v {
+                    self.discard(node);
+                }
+                self.emit(Op::Null);
+            }
+            _ => self.expr(body),
+        }
+        self.emit(Op::Return);
+    }
+
+    ///Compiles a function whose scope needs `locals` slots and leaves a closure of it on the stack. The
+    ///arguments become the first of its locals on the stack, or of its frame if closures can hold on to it.
+    fn function(&mut self, name: Option<String>, params: &[Node], body: &Node, locals: usize) {
+        let params: Vec<String> = param_names(params).iter().map(|p| p.to_string()).collect();
+        let own_frame = locals > 0 && makes_closure(body);
+        self.protos.push(Proto {
+            name,
+            params,
+            slots: if own_frame { locals } else { 0 },
+            ..Proto::default()
+        });
+        self.tops.push(0);
+        let scope = if own_frame {
+            self.level += 1;
+            Some(Scope::Frame(self.level))
+        } else if locals > 0 {
+            Some(Scope::Stack(self.reserve(locals as u32)))
+        } else {
+            None
+        };
+        self.scopes.extend(scope);
+
+        self.body(body);
+
+        if let Some(scope) = scope {
+            self.scopes.pop();
+            if let Scope::Frame(_) = scope {
+                self.level -= 1;
+            }
+        }
+        self.tops.pop();
+        let proto = self.protos.pop().unwrap_or_default();
+        let i = self.proto().protos.len() as u32;
+        self.proto().protos.push(Rc::new(proto));
+        self.emit(Op::Closure(i));
+    }
+
+    ///Compiles a node whose value isn't used, without leaving anything on the stack
+    fn discard(&mut self, node: &Node) {
+        let outer = mem::replace(&mut self.span, node.span.clone());
+        match &node.kind {
+            ExprNode::Block(v) => self.block(node.locals, &v.iter().collect::<Vec<_>>(), |c| {
+                for node in v {
+                    c.discard(node);
+                }
+            }),
+            ExprNode::Loop(ty, cond, body) => self.r#loop(node, ty, cond, body),
+            ExprNode::IfStatement(cond, body, branch) => {
+                self.r#if(cond, body, branch, Compiler::discard)
+            }
+            ExprNode::Illegal(_) | ExprNode::EOF => {}
+            _ => match plain_assignment(node) {
+                Some((n, var, val)) => {
+                    self.expr(val);
+                    self.assign(n, var);
+                }
+                None => {
+                    self.expr(node);
+                    self.emit(Op::Pop);
+                }
+            },
+        }
+        self.span = outer;
+    }
+
+    fn args(&mut self, args: &[Node]) -> u32 {
+        for arg in args {
+            self.expr(arg);
+        }
+        args.len() as u32
+    }
+
+    ///Compiles a node that leaves its value on the stack
+    fn expr(&mut self, node: &Node) {
+        let outer = mem::replace(&mut self.span, node.span.clone());
+        match &node.kind {
+            ExprNode::Block(v) => {
+                self.block(node.locals, &v.iter().collect::<Vec<_>>(), |c| {
+                    for node in v {
+                        c.discard(node);
+                    }
+                });
+                self.emit(Op::Null);
+            }
+            ExprNode::ReturnVal(v) => {
+                self.expr(v);
+                self.emit(Op::Return);
+            }
+            ExprNode::Operation(o, l, r) => self.operation(o, l, r),
+            ExprNode::Call(name, args) => self.call(name, node.var, args),
+            ExprNode::MethodCall(method, args) => self.method_call(method, args),
+            ExprNode::StrLiteral(s) => self.constant(Value::EmString(*s.clone())),
+            ExprNode::IntLiteral(n) => self.constant(Value::Int(*n)),
+            ExprNode::FloatLiteral(n) => self.constant(Value::Float(*n)),
+            ExprNode::BoolLiteral(b) => self.constant(Value::EmBool(*b)),
+            ExprNode::Name(n) => self.get(n, node.var),
+            ExprNode::Func(name, params, body) => match &**name {
+                Expression::Ident(n) => {
+                    self.function(Some(n.clone()), params, body, node.locals);
+                    self.define(n, node.var);
+                }
+                other => self.fail(Error::runtime(
+                    RuntimeErrorKind::InvalidOperation,
+                    format!("Expected identifier, found {}", other),
+                )),
+            },
+            ExprNode::Lambda(params, body) => self.function(None, params, body, node.locals),
+            ExprNode::Invoke(callee, args) => {
+                self.expr(callee);
+                let argc = self.args(args);
+                self.emit(Op::Call(argc));
+            }
+            ExprNode::Group(e) => self.expr(e),
+            ExprNode::Unary(op, operand) => match unary_op(op) {
+                Some(op) => {
+                    self.expr(operand);
+                    self.emit(Op::Unary(op));
+                }
+                None => self.fail(invalid_operator(op)),
+            },
+            ExprNode::Loop(ty, cond, body) => {
+                self.r#loop(node, ty, cond, body);
+                self.emit(Op::Null);
+            }
+            ExprNode::IfStatement(cond, body, branch) => {
+                self.r#if(cond, body, branch, Compiler::expr)
+            }
+            ExprNode::Array(v) => {
+                let len = self.args(v);
+                self.emit(Op::Array(len));
+            }
+            ExprNode::Map(v) => {
+                for (key, val) in v {
+                    self.expr(key);
+                    self.expr(val);
+                }
+                self.emit(Op::Map(v.len() as u32));
+            }
+            ExprNode::Index(target, index) => {
+                self.expr(target);
+                self.expr(index);
+                self.emit_at(Op::GetIndex, &index.span);
+            }
+            ExprNode::New(name, args) => match &**name {
+                Expression::Ident(n) => {
+                    let place = self.place(n, node.var);
+                    self.emit(Op::GetClass(place));
+                    let argc = self.args(args);
+                    self.emit(Op::New(argc));
+                }
+                other => self.fail(Error::runtime(
+                    RuntimeErrorKind::InvalidOperation,
+                    format!("Expected object, found {:?}", other),
+                )),
+            },
+            ExprNode::Class(name, body) => self.class(node, name, body),
+            _ => {
+                self.emit(Op::Null);
+            }
+        }
+        self.span = outer;
+    }
+
+    fn operation(&mut self, opr: &Expression, left: &Node, right: &Node) {
+        match opr {
+            Expression::Equal => self.assignment(left, right),
+            Expression::Operator('.') => match right.kind.inner() {
+                Some(prop) => {
+                    self.expr(left);
+                    let n = self.name(&prop);
+                    self.emit_at(Op::GetMember(n), &right.span);
+                }
+                None => self.fail(
+                    Error::runtime(
+                        RuntimeErrorKind::InvalidOperation,
+                        format!("Expected property name, found {:?}", right.kind),
+                    )
+                    .at(&right.span),
+                ),
+            },
+            Expression::Operator(_) | Expression::Power | Expression::Key(_) => {
+                match Arithmetic::from_op(opr) {
+                    Some(op) => {
+                        self.expr(left);
+                        //a number on the right, like in `i + 1`, goes in the op rather than on the stack
+                        match &right.kind {
+                            ExprNode::IntLiteral(n) => {
+                                let i = self.add_const(Value::Int(*n));
+                                self.emit(Op::ArithConst(op, i));
+                            }
+                            ExprNode::FloatLiteral(n) => {
+                                let i = self.add_const(Value::Float(*n));
+                                self.emit(Op::ArithConst(op, i));
+                            }
+                            _ => {
+                                self.expr(right);
+                                self.emit(Op::Arith(op));
+                            }
+                        }
+                    }
+                    None => self.fail(invalid_operator(opr)),
+                }
+            }
+            Expression::BoolOp(op) if op == "&&" || op == "||" => {
+                self.logical(op == "||", left, right)
+            }
+            Expression::BoolOp(op) => match Comparison::from_op(op) {
+                Some(cmp) => {
+                    self.expr(left);
+                    self.expr(right);
+                    self.emit(Op::Compare(cmp));
+                }
+                None => self.fail(invalid_operator(opr)),
+            },
+            _ => {
+                self.emit(Op::Null);
+            }
+        }
+    }
+
+    ///Compiles `&&` or `||` with jumps, so the right side only runs if it can change the result
+    fn logical(&mut self, or: bool, left: &Node, right: &Node) {
+        let mut to_false = vec![];
+        let mut to_end = vec![];
+        self.expr(left);
+        let skip = self.emit(Op::JumpIfFalse(0));
+        if or {
+            self.constant(Value::EmBool(true));
+            to_end.push(self.emit(Op::Jump(0)));
+            self.patch(skip);
+        } else {
+            to_false.push(skip);
+        }
+        self.expr(right);
+        to_false.push(self.emit(Op::JumpIfFalse(0)));
+        self.constant(Value::EmBool(true));
+        to_end.push(self.emit(Op::Jump(0)));
+        for at in to_false {
+            self.patch(at);
+        }
+        self.constant(Value::EmBool(false));
+        for at in to_end {
+            self.patch(at);
+        }
+    }
+
+    fn assignment(&mut self, left: &Node, right: &Node) {
+        match &left.kind {
+            ExprNode::Name(n) => {
+                self.expr(right);
+                self.set(n, left.var);
+            }
+            ExprNode::Index(target, index) => {
+                self.expr(right);
+                self.expr(target);
+                self.expr(index);
+                self.emit_at(Op::SetIndex, &index.span);
+            }
+            ExprNode::Operation(o, l, r) => match (&**o, &r.kind) {
+                (Expression::Operator('.'), ExprNode::Name(prop)) => {
+                    self.expr(l);
+                    self.expr(right);
+                    let n = self.name(prop);
+                    self.emit_at(Op::SetMember(n), &l.span);
+                }
+                (Expression::Operator('.'), other) => self.fail(
+                    Error::runtime(
+                        RuntimeErrorKind::InvalidOperation,
+                        format!("Unexpected symbol {:?}", other),
+                    )
+                    .at(&r.span),
+                ),
+                (o, _) => self.fail(Error::runtime(
+                    RuntimeErrorKind::InvalidOperation,
+                    format!("Can't assign to the result of {}", o),
+                )),
+            },
+            other => self.fail(Error::runtime(
+                RuntimeErrorKind::InvalidOperation,
+                format!("Error assigning to variable {:?}", other),
+            )),
+        }
+    }
+
+    fn call(&mut self, name: &Expression, var: Option<Slot>, args: &[Node]) {
+        match name {
+            Expression::Key(k) => match args.first() {
+                Some(arg) => {
+                    self.expr(arg);
+                    if k == "return" {
+                        self.emit(Op::Return);
+                    } else {
+                        self.emit(Op::Pop);
+                        self.emit(Op::Null);
+                    }
+                }
+                None => self.fail(Error::runtime(
+                    RuntimeErrorKind::WrongArgumentCount,
+                    format!("Expected a value after {}", name),
+                )),
+            },
+            Expression::Ident(n) => {
+                let place = self.place(n, var);
+                let n = self.name(n);
+                self.emit(Op::Callee(place, n));
+                let argc = self.args(args);
+                self.emit(Op::CallNamed(n, argc));
+            }
+            _ => self.fail(Error::runtime(
+                RuntimeErrorKind::InvalidOperation,
+                format!("Expected keyword or identifier, found {:?}", name),
+            )),
+        }
+    }
+
+    fn method_call(&mut self, method: &Node, args: &[Node]) {
+        let (target, member) = match &method.kind {
+            ExprNode::Operation(_, target, member) => (target, member),
+            other => {
+                return self.fail(Error::runtime(
+                    RuntimeErrorKind::InvalidOperation,
+                    format!("Unexpected expression {:?}", other),
+                ))
+            }
+        };
+        let prop = member.kind.inner().unwrap_or_default();
+        match &target.kind {
+            //builtins can be grouped under a name like `json.parse`, as long as a variable isn't hiding them
+            ExprNode::Name(n) => {
+                let place = self.place(n, target.var);
+                let n = self.name(&format!("{}.{}", n, prop));
+                self.emit_at(Op::Callee(place, n), &target.span);
+                let argc = self.args(args);
+                self.emit_at(Op::CallQualified(n, argc), &member.span);
+            }
+            _ => {
+                self.expr(target);
+                let argc = self.args(args);
+                let n = self.name(&prop);
+                self.emit_at(Op::CallMethod(n, argc), &member.span);
+            }
+        }
+    }
+
+    ///Compiles `cond` and a jump to be patched that's taken when it isn't true. A comparison jumps on its own
+    ///without pushing whether it was true first.
+    fn jump_unless(&mut self, cond: &Node) -> usize {
+        if let ExprNode::Operation(o, left, right) = &cond.kind {
+            if let Expression::BoolOp(op) = &**o {
+                if let Some(cmp) = Comparison::from_op(op) {
+                    self.expr(left);
+                    self.expr(right);
+                    return self.emit_at(Op::JumpUnless(cmp, 0), &cond.span);
+                }
+            }
+        }
+        self.expr(cond);
+        self.emit(Op::JumpIfFalse(0))
+    }
+
+    ///Compiles `body` if `cond` is true and `branch` otherwise, using `arm` for both
+    fn r#if(&mut self, cond: &Node, body: &Node, branch: &Node, arm: fn(&mut Compiler, &Node)) {
+        let skip = self.jump_unless(cond);
+        arm(self, body);
+        let end = self.emit(Op::Jump(0));
+        self.patch(skip);
+        arm(self, branch);
+        self.patch(end);
+    }
+
+    ///Loops don't leave anything on the stack, since their value is always null
+    fn r#loop(&mut self, node: &Node, ty: &str, cond: &Node, body: &Node) {
+        match (ty, &cond.kind) {
+            ("while", _) => {
+                let start = self.proto().ops.len() as u32;
+                let exit = self.jump_unless(cond);
+                self.discard(body);
+                self.emit(Op::Jump(start));
+                self.patch(exit);
+            }
+            //the loop variable only exists for the duration of the loop
+            ("for", ExprNode::ForLoopDec(dec, con, inc)) => {
+                self.block(node.locals, &[dec, con, inc, body], |c| {
+                    c.discard(dec);
+                    let start = c.proto().ops.len() as u32;
+                    let exit = c.jump_unless(con);
+                    c.discard(body);
+                    c.discard(inc);
+                    c.emit(Op::Jump(start));
+                    c.patch(exit);
+                });
+            }
+            //the loop's scope is made again every time around, so closures in it keep that time's variables
+            ("for", ExprNode::ForIn(vars, over)) => {
+                match &over.kind {
+                    ExprNode::Range(start, end, step, inclusive) => {
+                        self.expr(start);
+                        self.expr(end);
+                        self.expr(step);
+                        self.emit_at(Op::Range(*inclusive), &over.span);
+                    }
+                    _ => {
+                        self.expr(over);
+                        self.emit_at(Op::Iter, &over.span);
+                    }
+                }
+                let start = self.proto().ops.len() as u32;
+                let exit = self.emit(Op::Next(0));
+                let mut region: Vec<&Node> = vars.iter().collect();
+                region.push(body);
+                self.block(node.locals, &region, |c| {
+                    //the item is on top, with its position under it
+                    c.loop_var(vars.last());
+                    match vars.as_slice() {
+                        [counter, _] => c.loop_var(Some(counter)),
+                        _ => c.loop_var(None),
+                    }
+                    c.discard(body);
+                });
+                self.emit(Op::Jump(start));
+                self.patch(exit);
+            }
+            _ => {}
+        }
+    }
+
+    ///Moves the value on top of the stack into a for-in loop's variable, or drops it if there isn't one
+    fn loop_var(&mut self, var: Option<&Node>) {
+        match var.map(|v| (&v.kind, v.var)) {
+            Some((ExprNode::Name(n), slot)) => self.assign(n, slot),
+            _ => {
+                self.emit(Op::Pop);
+            }
+        }
+    }
+
+    ///Classes are built up as an object, with each method added to it as it's defined. The methods get a scope
+    ///of their own so they don't end up defined next to the class.
+    fn class(&mut self, node: &Node, name: &Expression, body: &Node) {
+        let class = match name {
+            Expression::Ident(s) => s,
+            other => {
+                return self.fail(Error::runtime(
+                    RuntimeErrorKind::InvalidOperation,
+                    format!("Expected an identifier, found {}", other),
+                ))
+            }
+        };
+        let n = self.name(class);
+        self.emit(Op::Class(n));
+        if let ExprNode::Block(v) = &body.kind {
+            self.block(node.locals, &v.iter().collect::<Vec<_>>(), |c| {
+                for node in v {
+                    c.expr(node);
+                    c.emit_at(Op::AddMethod, &node.span);
+                }
+            });
+        }
+        self.define(class, node.var);
+    }
+}
+
+///The variable, where the resolver put it, and value of a plain assignment like `x = 1`
+fn plain_assignment(node: &Node) -> Option<(&str, Option<Slot>, &Node)> {
+    match &node.kind {
+        ExprNode::Operation(o, l, r) if **o == Expression::Equal => match &l.kind {
+            ExprNode::Name(n) => Some((n, l.var, r)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+///Whether running `node` could make a closure that holds on to the scope it's in
+fn makes_closure(node: &Node) -> bool {
+    match &node.kind {
+        ExprNode::Func(..) | ExprNode::Lambda(..) | ExprNode::Class(..) => true,
+        _ => children(node).into_iter().any(makes_closure),
+    }
+}
@@ -0,0 +1,662 @@
+mod compiler;
+
+use crate::error::{Error, RuntimeErrorKind};
+use crate::interpreter::env::Frame;
+use crate::interpreter::iter::Items;
+use crate::interpreter::types::{EmMap, EmObject, MapKey};
+use crate::interpreter::{
+    find_class, index_value, int_arithmetic, set_index_value, unary, undefined, wrong_arg_count,
+    Arithmetic, Comparison, Runtime, StackFrame, Value,
+};
+use crate::lexer::Span;
+use crate::parser::Slot;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+
+//A compiler that turns the tree into bytecode, and a stack based VM that runs it. The compiler keeps the slots
+//the resolver gave each variable, but puts them on the VM's stack under what each call is working on, unless
+//a closure could hold on to them. Calls between compiled functions don't recurse, each one just pushes a frame.
+
+///What runs scripts. The tree walker runs the tree straight from the parser, while the VM compiles it to
+///bytecode first, which takes a little longer up front but runs loops and function calls much faster.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    #[default]
+    TreeWalker,
+    Vm,
+}
+
+///A single instruction. Most of them take their operands off of the stack and push their result back on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Op {
+    Const(u32),
+    Null,
+    Pop,
+    GetVar(u32),           //place
+    SetVar(u32),           //place, leaving the value on the stack
+    Assign(u32),           //place, same as SetVar but pops the value
+    Define(u32),           //place, for definitions, which make a global rather than assign to one
+    GetLocal(u32, u32),    //slot, name; the same as GetVar for a local on the stack
+    SetLocal(u32),         //slot
+    AssignLocal(u32),      //slot
+    Callee(u32, u32), //place, builtin; pushes whether the variable exists, then its value or null
+    ClearLocals(u32, u32), //first local, end of the locals
+    PushScope(u32),   //number of slots
+    PopScope,
+    Arith(Arithmetic),
+    ArithConst(Arithmetic, u32), //constant; the same as Arith with the constant as the right side
+    Unary(char),
+    Compare(Comparison),
+    Jump(u32),
+    JumpIfFalse(u32),
+    JumpUnless(Comparison, u32), //jump target; the same as Compare then JumpIfFalse
+    Iter,                        //starts going through what's on the stack, for a for-in loop
+    Range(bool), //whether the end is included; starts counting with the start, end, and step on the stack
+    Next(u32), //jump target; pushes the position and the next item, or jumps once there aren't any left
+    Call(u32), //argument count
+    CallNamed(u32, u32), //name, argument count; calls what Callee found, or the builtin if it found nothing
+    CallQualified(u32, u32), //name like `json.parse`, argument count; the same but for methods
+    CallMethod(u32, u32), //name, argument count
+    GetMember(u32),      //name
+    SetMember(u32),      //name
+    GetIndex,
+    SetIndex,
+    Array(u32), //length
+    Map(u32),   //number of entries
+    Closure(u32),
+    Class(u32), //name
+    AddMethod,
+    GetClass(u32), //place
+    New(u32),      //argument count
+    Fail(u32),
+    Return,
+}
+
+///Where a variable is kept
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Home {
+    Local(usize), //a slot in the running function's part of the stack
+    Frame(Slot), //a slot in a frame on the heap, counting frames out from the innermost rather than scopes
+    Global,      //globals don't have a slot and are looked up by name
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Place {
+    pub name: String,
+    pub home: Home,
+}
+
+///A compiled function, or the top level of a script
+#[derive(Debug, Default)]
+pub(crate) struct Proto {
+    pub name: Option<String>,
+    pub params: Vec<String>,
+    ///How many slots each call gets in a frame on the heap, which is only needed when closures made in the
+    ///function can hold on to its variables. Otherwise they're all on the stack.
+    pub slots: usize,
+    ///How many slots each call takes on the stack for its locals
+    pub locals: usize,
+    pub ops: Vec<Op>,
+    ///Where each op came from, for error messages
+    pub spans: Vec<Span>,
+    pub consts: Vec<Value>,
+    pub names: Vec<String>,
+    pub places: Vec<Place>,
+    pub protos: Vec<Rc<Proto>>,
+    pub errors: Vec<Error>,
+}
+
+///A compiled function along with the frame it was made in
+pub struct Closure {
+    pub(crate) proto: Rc<Proto>,
+    pub(crate) env: Rc<Frame>,
+    pub(crate) globals: StackFrame,
+}
+
+impl Closure {
+    pub(crate) fn arity(&self) -> usize {
+        self.proto.params.len()
+    }
+}
+
+impl fmt::Display for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let params = self.proto.params.join(", ");
+        match &self.proto.name {
+            Some(name) => write!(f, "fn {}({})", name, params),
+            None => write!(f, "fn ({})", params),
+        }
+    }
+}
+
+//Closures hold frames that can hold the closure, so like stack frames they're compared by identity
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Closure({})", self)
+    }
+}
+
+impl PartialEq for Closure {
+    fn eq(&self, other: &Closure) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl PartialOrd for Closure {
+    fn partial_cmp(&self, other: &Closure) -> Option<std::cmp::Ordering> {
+        if self == other {
+            Some(std::cmp::Ordering::Equal)
+        } else {
+            None
+        }
+    }
+}
+
+///A function that's running on the VM. Its locals are on the stack from `base` up, under what it's working on.
+struct CallFrame {
+    proto: Rc<Proto>,
+    ip: usize,
+    base: usize,
+    ret: usize, //how much of the stack there was before the call, which is all that's left once it returns
+    iters: usize, //how many for-in loops were already running when it was called
+    frame: Rc<Frame>,
+    globals: StackFrame,
+}
+
+impl Runtime {
+    ///Compiles `tree` and runs it with `globals` as the top level scope
+    pub(crate) fn run_vm(
+        &mut self,
+        tree: &crate::parser::Node,
+        globals: &StackFrame,
+    ) -> Result<Value, Error> {
+        let proto = Rc::new(compiler::compile(tree));
+        let ret = self.stack.len();
+        self.stack.resize(ret + proto.locals, None);
+        let script = CallFrame {
+            proto,
+            ip: 0,
+            base: ret,
+            ret,
+            iters: 0,
+            frame: Rc::new(Frame::new(0, None)),
+            globals: globals.clone(),
+        };
+        let res = self.execute(script);
+        self.stack.truncate(ret);
+        res
+    }
+
+    pub(crate) fn call_closure(
+        &mut self,
+        closure: &Closure,
+        args: Vec<Value>,
+    ) -> Result<Value, Error> {
+        let ret = self.stack.len();
+        let argc = args.len();
+        self.stack.extend(args.into_iter().map(Some));
+        let res = self.enter(closure, argc, ret, 0).and_then(|call| {
+            let res = self.execute(call);
+            self.budget.exit_call();
+            res
+        });
+        self.stack.truncate(ret);
+        res
+    }
+
+    ///Starts a call to `closure` with its `argc` arguments on top of the stack, which has to be matched by
+    ///`exit_call` once it returns
+    fn enter(
+        &mut self,
+        closure: &Closure,
+        argc: usize,
+        ret: usize,
+        iters: usize,
+    ) -> Result<CallFrame, Error> {
+        let proto = &closure.proto;
+        if argc != proto.params.len() {
+            return Err(wrong_arg_count(
+                &closure.to_string(),
+                proto.params.len(),
+                argc,
+            ));
+        }
+        self.budget.enter_call()?;
+        //the arguments are already where the first locals go, unless they need to go in a frame
+        let (base, frame) = if proto.slots > 0 {
+            let args = self.pop_n(argc as u32);
+            let frame = Frame::with_args(proto.slots, args, Some(closure.env.clone()));
+            (self.stack.len(), Rc::new(frame))
+        } else {
+            (self.stack.len() - argc, closure.env.clone())
+        };
+        self.stack.resize(base + proto.locals, None);
+        Ok(CallFrame {
+            proto: proto.clone(),
+            ip: 0,
+            base,
+            ret,
+            iters,
+            frame,
+            globals: closure.globals.clone(),
+        })
+    }
+
+    ///Runs `call` until it returns, along with everything it calls that was compiled too
+    fn execute(&mut self, mut call: CallFrame) -> Result<Value, Error> {
+        let mut callers = vec![];
+        let res = self.run_ops(&mut call, &mut callers);
+        //the calls an error cut short never got to return
+        for _ in &callers {
+            self.budget.exit_call();
+        }
+        //errors that don't know where they happened get pointed at the op that was running
+        res.map_err(|e| e.at(&call.proto.spans[call.ip - 1]))
+    }
+
+    fn push(&mut self, val: Value) {
+        self.stack.push(Some(val));
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().flatten().expect("VM stack underflow")
+    }
+
+    fn pop_n(&mut self, n: u32) -> Vec<Value> {
+        let at = self.stack.len() - n as usize;
+        self.stack
+            .drain(at..)
+            .map(|v| v.expect("VM stack underflow"))
+            .collect()
+    }
+
+    ///Finds the value of a variable, or `None` if it hasn't been assigned yet
+    fn lookup(&self, call: &CallFrame, place: &Place) -> Option<Value> {
+        match place.home {
+            Home::Local(i) => self.stack[call.base + i].clone(),
+            Home::Frame(slot) => call.frame.get(slot),
+            Home::Global => call.globals.get_var(&place.name),
+        }
+    }
+
+    fn assign(&mut self, call: &CallFrame, place: &Place, val: Value) {
+        match place.home {
+            Home::Local(i) => self.stack[call.base + i] = Some(val),
+            Home::Frame(slot) => call.frame.set(slot, val),
+            Home::Global => call.globals.set_var(place.name.clone(), val),
+        }
+    }
+
+    fn define(&mut self, call: &CallFrame, place: &Place, val: Value) {
+        match place.home {
+            Home::Global => call.globals.define(place.name.clone(), val),
+            _ => self.assign(call, place, val),
+        }
+    }
+
+    ///Calls `closure` by pushing a frame for it, so it starts running on the next op
+    fn call(
+        &mut self,
+        call: &mut CallFrame,
+        callers: &mut Vec<CallFrame>,
+        closure: &Closure,
+        argc: usize,
+        ret: usize,
+        iters: usize,
+    ) -> Result<(), Error> {
+        let callee = self.enter(closure, argc, ret, iters)?;
+        callers.push(mem::replace(call, callee));
+        Ok(())
+    }
+
+    ///The method `name` of `receiver` if it's a compiled function that takes `argc` arguments after the object
+    fn compiled_method(receiver: &Option<Value>, name: &str, argc: usize) -> Option<Rc<Closure>> {
+        match receiver {
+            Some(Value::Object(o)) => match o.borrow().get_prop(name) {
+                Some(Value::Closure(c)) if c.arity() == argc + 1 => Some(c.clone()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn run_ops(
+        &mut self,
+        call: &mut CallFrame,
+        callers: &mut Vec<CallFrame>,
+    ) -> Result<Value, Error> {
+        //what the for-in loops that are running go through, innermost last
+        let mut iters: Vec<Items> = vec![];
+        loop {
+            let op = call.proto.ops[call.ip];
+            call.ip += 1;
+            self.budget.step()?;
+            match op {
+                Op::Const(i) => {
+                    let val = call.proto.consts[i as usize].clone();
+                    if let Value::EmString(s) = &val {
+                        self.allocate(s.len())?;
+                    }
+                    self.push(val);
+                }
+                Op::Null => self.push(Value::Null),
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::GetVar(p) => {
+                    let place = &call.proto.places[p as usize];
+                    let val = self
+                        .lookup(call, place)
+                        .ok_or_else(|| undefined(&place.name))?;
+                    self.push(val);
+                }
+                Op::SetVar(p) => {
+                    let val = self.stack.last().cloned().flatten().unwrap_or(Value::Null);
+                    self.assign(call, &call.proto.places[p as usize], val);
+                }
+                Op::Assign(p) => {
+                    let val = self.pop();
+                    self.assign(call, &call.proto.places[p as usize], val);
+                }
+                Op::Define(p) => {
+                    let val = self.stack.last().cloned().flatten().unwrap_or(Value::Null);
+                    self.define(call, &call.proto.places[p as usize], val);
+                }
+                Op::GetLocal(i, n) => match &self.stack[call.base + i as usize] {
+                    Some(val) => self.push(val.clone()),
+                    None => return Err(undefined(&call.proto.names[n as usize])),
+                },
+                Op::SetLocal(i) => {
+                    let val = self.stack.last().cloned().flatten();
+                    self.stack[call.base + i as usize] = val;
+                }
+                Op::AssignLocal(i) => {
+                    let val = self.stack.pop().flatten();
+                    self.stack[call.base + i as usize] = val;
+                }
+                Op::Callee(p, n) => {
+                    let place = &call.proto.places[p as usize];
+                    let found = self.lookup(call, place);
+                    if found.is_none()
+                        && !self.functions.contains_key(&call.proto.names[n as usize])
+                    {
+                        return Err(undefined(&place.name));
+                    }
+                    self.push(Value::EmBool(found.is_some()));
+                    self.push(found.unwrap_or(Value::Null));
+                }
+                Op::ClearLocals(start, end) => {
+                    let (start, end) = (call.base + start as usize, call.base + end as usize);
+                    for slot in &mut self.stack[start..end] {
+                        *slot = None;
+                    }
+                }
+                Op::PushScope(slots) => {
+                    call.frame = Rc::new(Frame::new(slots as usize, Some(call.frame.clone())));
+                }
+                Op::PopScope => {
+                    call.frame = call
+                        .frame
+                        .parent
+                        .clone()
+                        .expect("Popped the outermost frame");
+                }
+                Op::Arith(o) => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    //integers are matched on their own so they skip everything other values need
+                    let res = match (left, right) {
+                        (Value::Int(a), Value::Int(b)) => int_arithmetic(o, a, b)?,
+                        (left, right) => self.arithmetic(o, left, right)?,
+                    };
+                    self.push(res);
+                }
+                Op::ArithConst(o, i) => {
+                    let left = self.pop();
+                    let res = match (left, &call.proto.consts[i as usize]) {
+                        (Value::Int(a), Value::Int(b)) => int_arithmetic(o, a, *b)?,
+                        (left, right) => self.arithmetic(o, left, right.clone())?,
+                    };
+                    self.push(res);
+                }
+                Op::Unary(o) => {
+                    let val = self.pop();
+                    self.push(unary(o, val)?);
+                }
+                Op::Compare(c) => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.push(Value::EmBool(compared(c, left, right)));
+                }
+                Op::Jump(target) => call.ip = target as usize,
+                Op::JumpIfFalse(target) => {
+                    //like the tree walker, anything but true counts as false
+                    match self.pop() {
+                        Value::EmBool(true) => {}
+                        _ => call.ip = target as usize,
+                    }
+                }
+                Op::JumpUnless(c, target) => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    if !compared(c, left, right) {
+                        call.ip = target as usize;
+                    }
+                }
+                Op::Iter => {
+                    let val = self.pop();
+                    iters.push(Items::new(val)?);
+                }
+                Op::Range(inclusive) => {
+                    let step = self.pop();
+                    let end = self.pop();
+                    let start = self.pop();
+                    iters.push(Items::range(start, end, step, inclusive)?);
+                }
+                Op::Next(target) => match iters.last_mut().and_then(Iterator::next) {
+                    Some((i, item)) => {
+                        self.push(Value::Int(i));
+                        self.push(item);
+                    }
+                    None => {
+                        iters.pop();
+                        call.ip = target as usize;
+                    }
+                },
+                Op::Call(argc) => {
+                    let at = self.stack.len() - argc as usize - 1;
+                    match &self.stack[at] {
+                        Some(Value::Closure(c)) => {
+                            let c = c.clone();
+                            self.call(call, callers, &c, argc as usize, at, iters.len())?;
+                        }
+                        _ => {
+                            let args = self.pop_n(argc);
+                            let func = self.pop();
+                            let res = self.call_function(&func, args)?;
+                            self.push(res);
+                        }
+                    }
+                }
+                Op::CallNamed(n, argc) => {
+                    let at = self.stack.len() - argc as usize - 2;
+                    //like the tree walker, variables hide builtins with the same name
+                    if self.stack[at] == Some(Value::EmBool(true)) {
+                        if let Some(Value::Closure(c)) = &self.stack[at + 1] {
+                            let c = c.clone();
+                            self.call(call, callers, &c, argc as usize, at, iters.len())?;
+                            continue;
+                        }
+                    }
+                    let args = self.pop_n(argc);
+                    let callee = self.pop();
+                    let name = &call.proto.names[n as usize];
+                    let res = if self.pop() == Value::EmBool(true) {
+                        self.call_function(&callee, args)?
+                    } else {
+                        self.call_builtin(name, args)?
+                    };
+                    self.push(res);
+                }
+                Op::CallQualified(n, argc) => {
+                    let args = self.pop_n(argc);
+                    let receiver = self.pop();
+                    let name = &call.proto.names[n as usize];
+                    let res = if self.pop() == Value::EmBool(true) {
+                        let method = name.split_once('.').map_or("", |(_, m)| m);
+                        self.call_method(receiver, method, args)?
+                    } else {
+                        self.call_builtin(name, args)?
+                    };
+                    self.push(res);
+                }
+                Op::CallMethod(n, argc) => {
+                    let name = &call.proto.names[n as usize];
+                    //methods get the object they were called on as their first argument, which is already there
+                    let at = self.stack.len() - argc as usize - 1;
+                    if let Some(c) = Runtime::compiled_method(&self.stack[at], name, argc as usize)
+                    {
+                        self.call(call, callers, &c, argc as usize + 1, at, iters.len())?;
+                        continue;
+                    }
+                    let args = self.pop_n(argc);
+                    let receiver = self.pop();
+                    let res = self.call_method(receiver, name, args)?;
+                    self.push(res);
+                }
+                Op::GetMember(n) => {
+                    let obj = self.pop();
+                    let prop = &call.proto.names[n as usize];
+                    let val = match &obj {
+                        Value::Object(o) => {
+                            o.borrow().get_prop(prop).cloned().ok_or_else(|| {
+                                Error::runtime(
+                                    RuntimeErrorKind::MissingProperty,
+                                    format!("{} has no property {}", o.borrow(), prop),
+                                )
+                            })?
+                        }
+                        other => return Err(not_an_object(other)),
+                    };
+                    self.push(val);
+                }
+                Op::SetMember(n) => {
+                    let val = self.pop();
+                    match self.pop() {
+                        Value::Object(o) => o
+                            .borrow_mut()
+                            .set_prop(call.proto.names[n as usize].clone(), Box::new(val.clone())),
+                        other => return Err(not_an_object(&other)),
+                    }
+                    self.push(val);
+                }
+                Op::GetIndex => {
+                    let key = self.pop();
+                    let target = self.pop();
+                    let val = index_value(&target, key)?;
+                    self.push(val);
+                }
+                Op::SetIndex => {
+                    let key = self.pop();
+                    let target = self.pop();
+                    let val = self.pop();
+                    set_index_value(&target, key, val.clone())?;
+                    self.push(val);
+                }
+                Op::Array(len) => {
+                    let items = self.pop_n(len);
+                    self.allocate(items.len() * mem::size_of::<Value>())?;
+                    self.push(Value::array(items));
+                }
+                Op::Map(len) => {
+                    let entries = self.pop_n(len * 2);
+                    let mut map = EmMap::default();
+                    let mut entries = entries.into_iter();
+                    while let (Some(k), Some(v)) = (entries.next(), entries.next()) {
+                        map.insert(&k, v)?;
+                    }
+                    self.allocate(len as usize * mem::size_of::<(MapKey, Value)>())?;
+                    self.push(Value::map(map));
+                }
+                Op::Closure(i) => {
+                    let closure = Closure {
+                        proto: call.proto.protos[i as usize].clone(),
+                        env: call.frame.clone(),
+                        globals: call.globals.clone(),
+                    };
+                    self.push(Value::Closure(Rc::new(closure)));
+                }
+                Op::Class(n) => {
+                    //the name property will be the name of the class for now, this might change in the future
+                    let mut members = HashMap::new();
+                    let name = Value::EmString(call.proto.names[n as usize].clone());
+                    members.insert("~name".to_owned(), Box::new(name));
+                    self.push(Value::object(EmObject { members }));
+                }
+                Op::AddMethod => {
+                    let method = self.pop();
+                    let name = match &method {
+                        Value::Closure(c) => c.proto.name.clone(),
+                        _ => None,
+                    };
+                    match (name, self.stack.last()) {
+                        (Some(name), Some(Some(Value::Object(class)))) => {
+                            class.borrow_mut().set_prop(name, Box::new(method));
+                        }
+                        _ => {
+                            return Err(Error::runtime(
+                                RuntimeErrorKind::InvalidOperation,
+                                format!("Unexpected {} in class definition", method),
+                            )
+                            .with_note("classes can only contain function definitions"))
+                        }
+                    }
+                }
+                Op::GetClass(p) => {
+                    let place = &call.proto.places[p as usize];
+                    let class = find_class(&place.name, self.lookup(call, place))?;
+                    self.push(Value::Object(class));
+                }
+                Op::New(argc) => {
+                    let args = self.pop_n(argc);
+                    let obj = match self.pop() {
+                        Value::Object(class) => self.instantiate(&class, args)?,
+                        other => return Err(not_an_object(&other)),
+                    };
+                    self.push(obj);
+                }
+                Op::Fail(i) => return Err(call.proto.errors[i as usize].clone()),
+                Op::Return => {
+                    let val = self.pop();
+                    match callers.pop() {
+                        Some(caller) => {
+                            self.stack.truncate(call.ret);
+                            iters.truncate(call.iters);
+                            self.budget.exit_call();
+                            *call = caller;
+                            self.push(val);
+                        }
+                        None => return Ok(val),
+                    }
+                }
+            }
+        }
+    }
+}
+
+///Runs a comparison, with integers matched on their own so they skip everything other values need
+fn compared(c: Comparison, left: Value, right: Value) -> bool {
+    match (left, right) {
+        (Value::Int(a), Value::Int(b)) => c.orders(a.cmp(&b)),
+        (left, right) => c.test(&left, &right),
+    }
+}
+
+fn not_an_object(val: &Value) -> Error {
+    Error::runtime(
+        RuntimeErrorKind::TypeMismatch,
+        format!("{} is not an object", val),
+    )
+}
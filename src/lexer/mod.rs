@@ -4,10 +4,13 @@ extern crate regex;
 #[cfg(test)]
 mod tests;
 
+use crate::error::{Error, LexErrorKind};
 use regex::Regex;
+use std::fmt;
 use std::iter::Peekable;
-use std::process;
-use std::str::Chars;
+use std::mem;
+use std::rc::Rc;
+use std::str::CharIndices;
 
 // Enums are more idomatic and make the resulting Vec much easier to understand
 // I may need more types to make things easier to work with but for now I think
@@ -17,13 +20,17 @@ use std::str::Chars;
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub enum Expression {
     Ident(String),
-    Number(f32),
+    Int(i64),
+    Float(f64),
     Word(String),
     Key(String),
     Operator(char),
     CompoundOp(String),
     BoolOp(String),
+    Power,       //**, which isn't a single character like the other arithmetic operators
+    Range(bool), //.. or ..=, and whether the end is included
     Equal,
+    Arrow,
     Rparen,
     Lparen,
     Rbrace,
@@ -31,7 +38,10 @@ pub enum Expression {
     Lbracket,
     Rbracket,
     Semicolon,
+    Colon,
     Comma,
+    FStringStart, //f", followed by the text as words and the expressions in braces
+    FStringEnd,
     EOF,
 }
 
@@ -39,12 +49,17 @@ impl std::fmt::Display for Expression {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Expression::Ident(name) => write!(f, "Identifier: {}", name),
-            Expression::Number(n) => write!(f, "Number: {}", n),
+            Expression::Int(n) => write!(f, "Number: {}", n),
+            Expression::Float(n) => write!(f, "Number: {}", n),
             Expression::Word(n) => write!(f, "String: {}", n),
             Expression::Key(n) => write!(f, "Keyword: {}", n),
             Expression::Operator(n) => write!(f, "Operator: {}", n),
             Expression::BoolOp(n) => write!(f, "Operator: {}", n),
+            Expression::Power => write!(f, "Operator: **"),
+            Expression::Range(false) => write!(f, "Operator: .."),
+            Expression::Range(true) => write!(f, "Operator: ..="),
             Expression::Equal => write!(f, "Operator: ="),
+            Expression::Arrow => write!(f, "Operator: =>"),
             Expression::Rparen => write!(f, "Symbol: )"),
             Expression::Lparen => write!(f, "Symbol: ("),
             Expression::Rbracket => write!(f, "Symbol: ]"),
@@ -52,14 +67,143 @@ impl std::fmt::Display for Expression {
             Expression::Rbrace => write!(f, "Symbol: }}"),
             Expression::Lbrace => write!(f, "Symbol: {{"),
             Expression::Semicolon => write!(f, "Symbol: ;"),
+            Expression::Colon => write!(f, "Symbol: :"),
             Expression::Comma => write!(f, "Symbol: ,"),
-            _ => write!(f, "{}", self),
+            Expression::FStringStart => write!(f, "Symbol: f\""),
+            Expression::FStringEnd => write!(f, "Symbol: \""),
+            _ => write!(f, "{:?}", self),
         }
     }
 }
 
-pub fn run(data: &str) -> Vec<Expression> {
-    Lexer::new().tokenize(data)
+///A region of source code, used to point errors back at the code that caused them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Span {
+    pub file: Rc<str>,
+    pub line: usize,
+    pub col: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    ///Creates a span covering everything from the start of this one to the end of `other`
+    pub fn to(&self, other: &Span) -> Span {
+        //spans made by hand (line 0) don't point anywhere, so just use the other one
+        if self.line == 0 {
+            return other.clone();
+        } else if other.line == 0 {
+            return self.clone();
+        }
+        Span {
+            end: other.end.max(self.end),
+            ..self.clone()
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.col)
+    }
+}
+
+///A single token along with where it was found in the source
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub kind: Expression,
+    pub span: Span,
+}
+
+impl Token {
+    pub fn new(kind: Expression, span: Span) -> Token {
+        Token { kind, span }
+    }
+}
+
+//Tokens are compared by kind only so that streams lexed from different places can still be compared
+impl PartialEq for Token {
+    fn eq(&self, other: &Token) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl From<Expression> for Token {
+    fn from(kind: Expression) -> Token {
+        Token::new(kind, Span::default())
+    }
+}
+
+///Tokenizes the provided string, reporting positions as coming from `<input>`
+pub fn run(data: &str) -> Result<Vec<Token>, Error> {
+    run_file("<input>", data)
+}
+
+///Tokenizes the provided string, reporting positions as coming from the file `name`
+pub fn run_file(name: &str, data: &str) -> Result<Vec<Token>, Error> {
+    Lexer::new(name).tokenize(data)
+}
+
+///Where a character sits in the source code
+#[derive(Debug, Clone, Copy)]
+struct Position {
+    offset: usize,
+    line: usize,
+    col: usize,
+}
+
+///Wraps the characters of the source and keeps track of the current line and column
+struct Cursor<'a> {
+    chars: Peekable<CharIndices<'a>>,
+    len: usize,
+    line: usize,
+    col: usize,
+    ///Position of the character most recently returned by `next`
+    last: Position,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(data: &'a str) -> Cursor<'a> {
+        Cursor {
+            chars: data.char_indices().peekable(),
+            len: data.len(),
+            line: 1,
+            col: 1,
+            last: Position {
+                offset: 0,
+                line: 1,
+                col: 1,
+            },
+        }
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let (offset, c) = self.chars.next()?;
+        self.last = Position {
+            offset,
+            line: self.line,
+            col: self.col,
+        };
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        Some(c)
+    }
+
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek().map(|(_, c)| c)
+    }
+
+    ///Byte offset just past the character most recently returned by `next`
+    fn offset(&mut self) -> usize {
+        match self.chars.peek() {
+            Some((i, _)) => *i,
+            None => self.len,
+        }
+    }
 }
 
 ///Describes the current state of the lexer
@@ -67,6 +211,8 @@ pub fn run(data: &str) -> Vec<Expression> {
 enum State {
     Nothing,
     EmString,
+    RawString,
+    FString,
     EmName,
     EmNumber,
     Comment,
@@ -76,38 +222,46 @@ enum State {
 struct Lexer {
     current_state: State,
     token: String,
+    file: Rc<str>,
+    ///Where the token currently being accumulated started
+    start: Position,
     valid_num: Regex,
     valid_chars: Regex,
     valid_symb: Regex,
     check: bool,
+    ///Where each f-string that's being read started, and how many braces are open in the expression being read
+    ///from it
+    fstrings: Vec<(Position, usize)>,
 }
 
 impl Lexer {
     ///Creates a new lexer and initializes the regular expressions
-    pub fn new() -> Lexer {
+    pub fn new(file: &str) -> Lexer {
         Lexer {
             current_state: State::Nothing,
             token: String::new(),
+            file: Rc::from(file),
+            start: Position {
+                offset: 0,
+                line: 1,
+                col: 1,
+            },
             valid_num: Regex::new(r"\d*").unwrap(),
             valid_chars: Regex::new(r"\D+[[:word:]]*").unwrap(),
-            valid_symb: Regex::new(r"[\{\}\(\)=;.\*\+\-/#!,\t\n\[\]]").unwrap(),
+            valid_symb: Regex::new(r"[\{\}\(\)=;:.\*\+\-/%#!&|,\t\n\[\]]").unwrap(),
             check: false,
+            fstrings: vec![],
         }
     }
 
-    ///Loops through the characters in the provided string can outputs a vec of expressions
-    pub fn tokenize(&mut self, data: &str) -> Vec<Expression> {
+    ///Loops through the characters in the provided string can outputs a vec of tokens
+    pub fn tokenize(&mut self, data: &str) -> Result<Vec<Token>, Error> {
         let mut result = vec![];
 
-        let mut ch = data.chars().peekable();
+        let mut ch = Cursor::new(data);
 
         while let Some(c) = ch.next() {
-            // println!(
-            //     "Current char: {:?}\nNext char: {:?}\nCurrent token: {}",
-            //     c,
-            //     ch.peek().unwrap_or(&'?'),
-            //     self.token
-            // );
+            let pos = ch.last;
             match self.current_state {
                 State::Comment => {
                     if c == '\n' {
@@ -115,28 +269,88 @@ impl Lexer {
                         self.token.clear();
                     }
                 }
-                State::EmString => {
+                State::EmString | State::RawString => {
                     if c == '"' {
-                        result.push(Expression::Word(self.token.clone()));
+                        let span = self.span(self.start, ch.offset());
+                        result.push(Token::new(Expression::Word(self.token.clone()), span));
                         self.token.clear();
                         self.current_state = State::Nothing;
+                    } else if c == '\\' && self.current_state == State::EmString {
+                        let escaped = self.escape(pos, &mut ch)?;
+                        self.token.push(escaped);
                     } else {
                         self.token.push(c);
                     }
                 }
                 State::EmNumber => {
-                    if let Some(r) = self.num_handle(c, &mut ch) {
-                        result.push(r);
+                    if let Some(r) = self.num_handle(c, &mut ch)? {
+                        //if the current char still needs processing it isn't part of this token
+                        let end = if self.check { pos.offset } else { ch.offset() };
+                        result.push(Token::new(r, self.span(self.start, end)));
+                    }
+                }
+                State::FString => {
+                    if self.token.is_empty() {
+                        self.start = pos;
                     }
+                    match c {
+                        '"' => {
+                            self.end_text(&mut result, pos);
+                            result.push(Token::new(
+                                Expression::FStringEnd,
+                                self.span(pos, ch.offset()),
+                            ));
+                            self.fstrings.pop();
+                            self.current_state = State::Nothing;
+                        }
+                        '\\' => {
+                            let escaped = self.escape(pos, &mut ch)?;
+                            self.token.push(escaped);
+                        }
+                        //doubled braces are how an f-string has a brace in its text
+                        '{' | '}' if ch.peek() == Some(&c) => {
+                            ch.next();
+                            self.token.push(c);
+                        }
+                        '{' => {
+                            self.end_text(&mut result, pos);
+                            result
+                                .push(Token::new(Expression::Lbrace, self.span(pos, ch.offset())));
+                            self.current_state = State::Nothing;
+                        }
+                        '}' => {
+                            return Err(Error::lex(
+                                LexErrorKind::UnmatchedBrace,
+                                "Unmatched \"}\" in f-string",
+                                self.span(pos, ch.offset()),
+                            )
+                            .with_note("use \"}}\" for a brace in the text"))
+                        }
+                        _ => self.token.push(c),
+                    }
+                }
+                //an r right before a quote starts a raw string, which keeps backslashes as they are
+                State::EmName if c == '"' && self.token == "r" => {
+                    self.current_state = State::RawString;
+                    self.token.clear();
+                }
+                State::EmName if c == '"' && self.token == "f" => {
+                    result.push(Token::new(
+                        Expression::FStringStart,
+                        self.span(self.start, ch.offset()),
+                    ));
+                    self.fstrings.push((self.start, 0));
+                    self.current_state = State::FString;
+                    self.token.clear();
                 }
                 State::EmName => {
                     if let Some(r) = self.name_handle(c) {
-                        result.push(r);
+                        result.push(Token::new(r, self.span(self.start, pos.offset)));
                     }
                 }
                 State::Nothing => {
-                    if let Some(r) = self.nothing_handle(c, &mut ch) {
-                        result.push(r);
+                    if let Some(r) = self.nothing_handle(c, pos, &mut ch) {
+                        result.push(Token::new(r, self.span(pos, ch.offset())));
                     }
                 }
             }
@@ -144,63 +358,174 @@ impl Lexer {
             //check after everything for a nothing state to ensure the
             //current character is processed correctly
             if self.check {
-                if let Some(r) = self.nothing_handle(c, &mut ch) {
-                    result.push(r);
+                if let Some(r) = self.nothing_handle(c, pos, &mut ch) {
+                    result.push(Token::new(r, self.span(pos, ch.offset())));
                 }
                 self.check = false;
             }
-            // println!("Current result: {:?}", result);
         }
 
-        result //return the result
+        //names and numbers are only finished by the character after them, so make sure one
+        //at the very end of the input doesn't get lost
+        let end = ch.offset();
+        let last = match self.current_state {
+            State::EmName => self.name_handle(' '),
+            State::EmNumber => self.num_handle(' ', &mut ch)?,
+            State::EmString | State::RawString => {
+                return Err(Error::lex(
+                    LexErrorKind::UnterminatedString,
+                    "Unterminated string literal",
+                    self.span(self.start, end),
+                )
+                .with_note("strings need a closing '\"'"))
+            }
+            _ => None,
+        };
+        if let Some(r) = last {
+            result.push(Token::new(r, self.span(self.start, end)));
+        }
+        if let Some((start, _)) = self.fstrings.last() {
+            let err = Error::lex(
+                LexErrorKind::UnterminatedString,
+                "Unterminated string literal",
+                self.span(*start, end),
+            );
+            return Err(if self.current_state == State::FString {
+                err.with_note("strings need a closing '\"'")
+            } else {
+                err.with_note("expressions in f-strings need a closing '}'")
+            });
+        }
+
+        Ok(result) //return the result
     }
 
-    ///Handles generation of number literals
-    fn num_handle(&mut self, c: char, iter: &mut Peekable<Chars<'_>>) -> Option<Expression> {
-        let result: Option<Expression>;
-        if c.is_whitespace() || self.valid_symb.is_match(&c.to_string()) {
-            self.current_state = State::Nothing;
+    ///Finishes the piece of an f-string's text that ends at `end`, if there is any
+    fn end_text(&mut self, result: &mut Vec<Token>, end: Position) {
+        if !self.token.is_empty() {
+            let text = Expression::Word(mem::take(&mut self.token));
+            result.push(Token::new(text, self.span(self.start, end.offset)));
+        }
+    }
 
-            if !c.is_whitespace() && c.is_numeric() || c == '.' {
-                //the current char could be part of the thing we're accumulating
-                self.token.push(c);
-            }
-            result = Some(Expression::Number(
-                self.token.parse::<f32>().unwrap_or_else(|e| {
-                    println!(
-                        "Got this er!ror message ({}) when parsing this: {}",
-                        e, self.token
-                    );
-
-                    process::exit(-1);
-                }),
-            ));
-            self.token.clear();
-            self.check = true;
+    ///Creates a span from `start` up to the byte offset `end`
+    fn span(&self, start: Position, end: usize) -> Span {
+        Span {
+            file: self.file.clone(),
+            line: start.line,
+            col: start.col,
+            start: start.offset,
+            end,
+        }
+    }
+
+    ///Turns the accumulated token into a number, which is an integer unless it has a decimal point
+    fn make_number(&self) -> Result<Expression, Error> {
+        let res = if self.token.contains('.') {
+            self.token
+                .parse::<f64>()
+                .map(Expression::Float)
+                .map_err(|e| e.to_string())
         } else {
-            if let Some(char) = iter.peek() {
-                if *char == ']' {
-                    if c.is_numeric() || c == '.' {
-                        self.token.push(c);
-                    }
-                    let tmp = Some(Expression::Number(
-                        self.token.parse::<f32>().unwrap_or_else(|e| {
-                            println!(
-                                "Got this error message ({}) when parsing this: {}",
-                                e, self.token
-                            );
-                            process::exit(-1);
-                        }),
-                    ));
-                    self.token.clear();
-                    self.current_state = State::Nothing;
-                    return tmp;
+            self.token
+                .parse::<i64>()
+                .map(Expression::Int)
+                .map_err(|e| e.to_string())
+        };
+        res.map_err(|note| {
+            let end = self.start.offset + self.token.len();
+            Error::lex(
+                LexErrorKind::InvalidNumber,
+                format!("Invalid number literal {}", self.token),
+                self.span(self.start, end),
+            )
+            .with_note(note)
+        })
+    }
+
+    ///Reads the escape sequence after a backslash at `pos` in a string, giving the character it stands for
+    fn escape(&self, pos: Position, ch: &mut Cursor<'_>) -> Result<char, Error> {
+        let c = match ch.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('"') => '"',
+            Some('u') => return self.unicode_escape(pos, ch),
+            Some(other) => {
+                return Err(Error::lex(
+                    LexErrorKind::InvalidEscape,
+                    format!("Unknown escape sequence \\{}", other),
+                    self.span(pos, ch.offset()),
+                )
+                .with_note("the escapes are \\n \\t \\r \\0 \\\\ \\\" and \\u{...}")
+                .with_note("use a raw string like r\"C:\\dir\" to keep backslashes as they are"))
+            }
+            None => {
+                return Err(Error::lex(
+                    LexErrorKind::UnterminatedString,
+                    "Unterminated string literal",
+                    self.span(self.start, ch.offset()),
+                )
+                .with_note("strings need a closing '\"'"))
+            }
+        };
+        Ok(c)
+    }
+
+    ///Reads the `{...}` part of a `\u{...}` escape, which is the character's code point in hex
+    fn unicode_escape(&self, pos: Position, ch: &mut Cursor<'_>) -> Result<char, Error> {
+        let mut digits = String::new();
+        let mut closed = false;
+        if ch.peek() == Some(&'{') {
+            ch.next();
+            while let Some(&c) = ch.peek() {
+                if !c.is_ascii_hexdigit() && c != '}' {
+                    break;
                 }
+                ch.next();
+                if c == '}' {
+                    closed = true;
+                    break;
+                }
+                digits.push(c);
             }
+        }
+        let code = match u32::from_str_radix(&digits, 16) {
+            Ok(code) if closed && digits.len() <= 6 => char::from_u32(code),
+            _ => None,
+        };
+        code.ok_or_else(|| {
+            Error::lex(
+                LexErrorKind::InvalidEscape,
+                "Invalid unicode escape",
+                self.span(pos, ch.offset()),
+            )
+            .with_note("unicode escapes are 1 to 6 hex digits in braces, like \\u{1F600}")
+        })
+    }
+
+    ///Handles generation of number literals
+    fn num_handle(&mut self, c: char, iter: &mut Cursor<'_>) -> Result<Option<Expression>, Error> {
+        //a dot is only a decimal point if there's a digit after it, otherwise it's accessing a member
+        let point = c == '.'
+            && !self.token.contains('.')
+            && iter.peek().is_some_and(|n| n.is_ascii_digit());
+        if c.is_ascii_digit() || point {
             self.token.push(c);
-            result = None;
+            Ok(None)
+        } else if c.is_alphanumeric() || c == '_' {
+            //letters stuck to a number make it invalid, which gets reported once the whole thing is read
+            self.token.push(c);
+            Ok(None)
+        } else {
+            self.current_state = State::Nothing;
+            let result = self.make_number()?;
+            self.token.clear();
+            self.check = true;
+            Ok(Some(result))
         }
-        result
     }
 
     ///Handles the generation of identifiers and keywords
@@ -229,7 +554,7 @@ impl Lexer {
                     result = Some(Expression::Key(self.token.to_string()));
                     self.token.clear();
                 }
-                "while" | "for" => {
+                "while" | "for" | "in" => {
                     result = Some(Expression::Key(self.token.to_string()));
                     self.token.clear();
                 }
@@ -237,6 +562,21 @@ impl Lexer {
                     result = Some(Expression::Key(self.token.to_string()));
                     self.token.clear();
                 }
+                //floor division is a word since `//` already starts a comment
+                "div" => {
+                    result = Some(Expression::Key(self.token.to_string()));
+                    self.token.clear();
+                }
+                //the word forms of the logical operators are the same tokens as the symbols
+                "and" | "or" | "not" => {
+                    let op = match self.token.as_str() {
+                        "and" => "&&",
+                        "or" => "||",
+                        _ => "!",
+                    };
+                    result = Some(Expression::BoolOp(op.to_owned()));
+                    self.token.clear();
+                }
                 _ => {
                     result = Some(Expression::Ident(self.token.to_string()));
 
@@ -252,21 +592,40 @@ impl Lexer {
     }
 
     ///The default state of the lexer, handles symbols and decides when to change states
-    fn nothing_handle(&mut self, c: char, ch: &mut Peekable<Chars<'_>>) -> Option<Expression> {
-        // println!(
-        //     "This is what it looks like when you call char.to_string(): {:?}",
-        //     c.to_string()
-        // );
+    fn nothing_handle(
+        &mut self,
+        c: char,
+        pos: Position,
+        ch: &mut Cursor<'_>,
+    ) -> Option<Expression> {
         match c {
             '\t' | ' ' | '\n' | '\r' => None,
             '"' => {
                 self.current_state = State::EmString;
+                self.start = pos;
                 self.token.clear();
                 None
             }
             ',' => Some(Expression::Comma),
-            '{' => Some(Expression::Lbrace),
-            '}' => Some(Expression::Rbrace),
+            ':' => Some(Expression::Colon),
+            '{' => {
+                if let Some((_, open)) = self.fstrings.last_mut() {
+                    *open += 1;
+                }
+                Some(Expression::Lbrace)
+            }
+            '}' => {
+                match self.fstrings.last_mut() {
+                    Some((_, open)) if *open > 0 => *open -= 1,
+                    //this closes an expression in an f-string, so its text carries on after it
+                    Some(_) => {
+                        self.current_state = State::FString;
+                        self.token.clear();
+                    }
+                    None => {}
+                }
+                Some(Expression::Rbrace)
+            }
             '(' => Some(Expression::Lparen),
             ')' => Some(Expression::Rparen),
             '[' => Some(Expression::Lbracket),
@@ -276,6 +635,9 @@ impl Lexer {
                     if *sym == '=' {
                         ch.next();
                         Some(Expression::BoolOp("==".to_owned()))
+                    } else if *sym == '>' {
+                        ch.next();
+                        Some(Expression::Arrow)
                     } else {
                         Some(Expression::Equal)
                     }
@@ -286,16 +648,34 @@ impl Lexer {
 
             '*' => {
                 if let Some(sym) = ch.peek() {
-                    if *sym == '=' {
-                        ch.next();
-                        Some(Expression::CompoundOp("*=".to_owned()))
-                    } else {
-                        Some(Expression::Operator(c))
+                    match sym {
+                        '=' => {
+                            ch.next();
+                            Some(Expression::CompoundOp("*=".to_owned()))
+                        }
+                        '*' => {
+                            ch.next();
+                            if ch.peek() == Some(&'=') {
+                                ch.next();
+                                Some(Expression::CompoundOp("**=".to_owned()))
+                            } else {
+                                Some(Expression::Power)
+                            }
+                        }
+                        _ => Some(Expression::Operator(c)),
                     }
                 } else {
                     None
                 }
             }
+            '%' => {
+                if ch.peek() == Some(&'=') {
+                    ch.next();
+                    Some(Expression::CompoundOp("%=".to_owned()))
+                } else {
+                    Some(Expression::Operator(c))
+                }
+            }
             '+' => {
                 if let Some(sym) = ch.peek() {
                     match sym {
@@ -330,12 +710,23 @@ impl Lexer {
                     None
                 }
             }
-            '.' => Some(Expression::Operator(c)),
+            '.' => {
+                if ch.peek() == Some(&'.') {
+                    ch.next();
+                    let inclusive = ch.peek() == Some(&'=');
+                    if inclusive {
+                        ch.next();
+                    }
+                    Some(Expression::Range(inclusive))
+                } else {
+                    Some(Expression::Operator(c))
+                }
+            }
             '#' => {
                 ch.next();
                 self.current_state = State::Comment;
                 None
-            } 
+            }
             '/' => {
                 if let Some(sym) = ch.peek() {
                     match sym {
@@ -356,16 +747,20 @@ impl Lexer {
             }
             ';' => Some(Expression::Semicolon),
             '!' => {
-                if let Some(sym) = ch.peek() {
-                    if *sym == '=' {
-                        ch.next();
-                        Some(Expression::BoolOp("!=".to_owned()))
-                    } else {
-                        None
-                        //if we need other operators to do with the bang they would go here
-                    }
+                if ch.peek() == Some(&'=') {
+                    ch.next();
+                    Some(Expression::BoolOp("!=".to_owned()))
                 } else {
-                    None
+                    Some(Expression::BoolOp("!".to_owned()))
+                }
+            }
+            //a single '&' or '|' isn't an operator, but keeping it lets the parser point at it
+            '&' | '|' => {
+                if ch.peek() == Some(&c) {
+                    ch.next();
+                    Some(Expression::BoolOp(format!("{}{}", c, c)))
+                } else {
+                    Some(Expression::Operator(c))
                 }
             }
 
@@ -395,6 +790,9 @@ impl Lexer {
                 }
             }
             _ => {
+                if self.token.is_empty() {
+                    self.start = pos;
+                }
                 self.token.push(c);
                 if self.valid_chars.is_match(&self.token) {
                     self.current_state = State::EmName;
@@ -1,3 +1,4 @@
+use crate::error::{Error, LexErrorKind};
 use crate::lexer;
 use crate::lexer::Expression;
 
@@ -16,5 +17,289 @@ fn token_generation() {
         Expression::Rbrace,
     ];
 
-    assert_eq!(expected, lexer::run(dummy));
+    let kinds: Vec<Expression> = lexer::run(dummy)
+        .unwrap()
+        .into_iter()
+        .map(|t| t.kind)
+        .collect();
+    assert_eq!(expected, kinds);
+}
+
+#[test]
+fn token_spans() {
+    let dummy = "x = 10;\n  println(\"hi\");";
+    let tokens = lexer::run_file("test.em", dummy).unwrap();
+
+    let positions: Vec<(usize, usize, usize, usize)> = tokens
+        .iter()
+        .map(|t| (t.span.line, t.span.col, t.span.start, t.span.end))
+        .collect();
+    assert_eq!(
+        positions,
+        vec![
+            (1, 1, 0, 1),    // x
+            (1, 3, 2, 3),    // =
+            (1, 5, 4, 6),    // 10
+            (1, 7, 6, 7),    // ;
+            (2, 3, 10, 17),  // println
+            (2, 10, 17, 18), // (
+            (2, 11, 18, 22), // "hi"
+            (2, 15, 22, 23), // )
+            (2, 16, 23, 24), // ;
+        ]
+    );
+    assert_eq!(format!("{}", tokens[4].span), "test.em:2:3");
+}
+
+#[test]
+fn lex_errors() {
+    match lexer::run_file("test.em", "x = \"hello;") {
+        Err(Error::LexError { kind, span, .. }) => {
+            assert_eq!(kind, LexErrorKind::UnterminatedString);
+            assert_eq!((span.line, span.col), (1, 5));
+        }
+        other => panic!("Expected an unterminated string, got {:?}", other),
+    }
+
+    assert!(matches!(
+        lexer::run("x = 12ab;"),
+        Err(Error::LexError {
+            kind: LexErrorKind::InvalidNumber,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn arrow_token() {
+    let kinds: Vec<Expression> = lexer::run("f = x => x == 1;")
+        .unwrap()
+        .into_iter()
+        .map(|t| t.kind)
+        .collect();
+    assert_eq!(
+        kinds,
+        vec![
+            Expression::Ident("f".to_owned()),
+            Expression::Equal,
+            Expression::Ident("x".to_owned()),
+            Expression::Arrow,
+            Expression::Ident("x".to_owned()),
+            Expression::BoolOp("==".to_owned()),
+            Expression::Int(1),
+            Expression::Semicolon,
+        ]
+    );
+}
+
+#[test]
+fn number_literals() {
+    let kinds: Vec<Expression> = lexer::run("1 2.5 9007199254740993 a[0].b 3.x")
+        .unwrap()
+        .into_iter()
+        .map(|t| t.kind)
+        .collect();
+    assert_eq!(
+        kinds,
+        vec![
+            Expression::Int(1),
+            Expression::Float(2.5),
+            Expression::Int(9_007_199_254_740_993),
+            Expression::Ident("a".to_owned()),
+            Expression::Lbracket,
+            Expression::Int(0),
+            Expression::Rbracket,
+            Expression::Operator('.'),
+            Expression::Ident("b".to_owned()),
+            Expression::Int(3),
+            Expression::Operator('.'),
+            Expression::Ident("x".to_owned()),
+        ]
+    );
+
+    //integers that don't fit in 64 bits are an error rather than being rounded
+    assert!(matches!(
+        lexer::run("x = 99999999999999999999;"),
+        Err(Error::LexError {
+            kind: LexErrorKind::InvalidNumber,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn logical_tokens() {
+    let kinds: Vec<Expression> = lexer::run("a&&!b || c and not d or e != f & g")
+        .unwrap()
+        .into_iter()
+        .map(|t| t.kind)
+        .collect();
+    let op = |o: &str| Expression::BoolOp(o.to_owned());
+    let name = |n: &str| Expression::Ident(n.to_owned());
+    //the words are the same tokens as the symbols
+    assert_eq!(
+        kinds,
+        vec![
+            name("a"),
+            op("&&"),
+            op("!"),
+            name("b"),
+            op("||"),
+            name("c"),
+            op("&&"),
+            op("!"),
+            name("d"),
+            op("||"),
+            name("e"),
+            op("!="),
+            name("f"),
+            Expression::Operator('&'),
+            name("g"),
+        ]
+    );
+}
+
+#[test]
+fn arithmetic_tokens() {
+    let kinds: Vec<Expression> = lexer::run("a % b ** c div d %= e **= f")
+        .unwrap()
+        .into_iter()
+        .map(|t| t.kind)
+        .collect();
+    let name = |n: &str| Expression::Ident(n.to_owned());
+    assert_eq!(
+        kinds,
+        vec![
+            name("a"),
+            Expression::Operator('%'),
+            name("b"),
+            Expression::Power,
+            name("c"),
+            Expression::Key("div".to_owned()),
+            name("d"),
+            Expression::CompoundOp("%=".to_owned()),
+            name("e"),
+            Expression::CompoundOp("**=".to_owned()),
+            name("f"),
+        ]
+    );
+}
+
+#[test]
+fn range_tokens() {
+    let kinds: Vec<Expression> = lexer::run("for i in 0..n { } 1..=2 a.b")
+        .unwrap()
+        .into_iter()
+        .map(|t| t.kind)
+        .collect();
+    let name = |n: &str| Expression::Ident(n.to_owned());
+    assert_eq!(
+        kinds,
+        vec![
+            Expression::Key("for".to_owned()),
+            name("i"),
+            Expression::Key("in".to_owned()),
+            Expression::Int(0),
+            Expression::Range(false),
+            name("n"),
+            Expression::Lbrace,
+            Expression::Rbrace,
+            Expression::Int(1),
+            Expression::Range(true),
+            Expression::Int(2),
+            name("a"),
+            Expression::Operator('.'),
+            name("b"),
+        ]
+    );
+}
+
+#[test]
+fn string_escapes() {
+    let words: Vec<Expression> = lexer::run(
+        r#"a = "tab\there \"quoted\" back\\slash\n \u{e9}\u{1F600}"; b = r"C:\new\dir"; c = "two
+lines";"#,
+    )
+    .unwrap()
+    .into_iter()
+    .map(|t| t.kind)
+    .filter(|k| matches!(k, Expression::Word(_)))
+    .collect();
+    let word = |w: &str| Expression::Word(w.to_owned());
+    assert_eq!(
+        words,
+        vec![
+            word("tab\there \"quoted\" back\\slash\n é😀"),
+            word("C:\\new\\dir"),
+            word("two\nlines"),
+        ]
+    );
+
+    for source in [r#"x = "\q";"#, r#"x = "\u{110000}";"#, r#"x = "\u{41";"#] {
+        match lexer::run_file("test.em", source) {
+            Err(Error::LexError { kind, span, .. }) => {
+                assert_eq!(kind, LexErrorKind::InvalidEscape);
+                assert_eq!((span.line, span.col), (1, 6));
+            }
+            other => panic!("Expected an invalid escape, got {:?}", other),
+        }
+    }
+    //the quote after a backslash doesn't end the string
+    assert!(matches!(
+        lexer::run(r#"x = "a\";"#),
+        Err(Error::LexError {
+            kind: LexErrorKind::UnterminatedString,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn fstring_tokens() {
+    let kinds: Vec<Expression> = lexer::run(r#"f"a {m["k"]} {{b}}\t{ {1: x}[1] }""#)
+        .unwrap()
+        .into_iter()
+        .map(|t| t.kind)
+        .collect();
+    let word = |w: &str| Expression::Word(w.to_owned());
+    //the text is split around the expressions, which are lexed like any other code
+    assert_eq!(
+        kinds,
+        vec![
+            Expression::FStringStart,
+            word("a "),
+            Expression::Lbrace,
+            Expression::Ident("m".to_owned()),
+            Expression::Lbracket,
+            word("k"),
+            Expression::Rbracket,
+            Expression::Rbrace,
+            word(" {b}\t"),
+            Expression::Lbrace,
+            Expression::Lbrace,
+            Expression::Int(1),
+            Expression::Colon,
+            Expression::Ident("x".to_owned()),
+            Expression::Rbrace,
+            Expression::Lbracket,
+            Expression::Int(1),
+            Expression::Rbracket,
+            Expression::Rbrace,
+            Expression::FStringEnd,
+        ]
+    );
+
+    for (source, kind) in [
+        (r#"x = f"a {b"#, LexErrorKind::UnterminatedString),
+        (r#"x = f"a {b}"#, LexErrorKind::UnterminatedString),
+        (r#"x = f"a } b""#, LexErrorKind::UnmatchedBrace),
+    ] {
+        match lexer::run_file("test.em", source) {
+            Err(Error::LexError { kind: k, span, .. }) => {
+                assert_eq!(k, kind);
+                assert_eq!(span.line, 1);
+            }
+            other => panic!("Expected {:?}, got {:?}", kind, other),
+        }
+    }
 }
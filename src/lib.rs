@@ -1,5 +1,7 @@
-#![feature(test)]
+#![cfg_attr(test, feature(test))]
 
+pub mod engine;
+pub mod error;
 pub mod interpreter;
 pub mod lexer;
 pub mod parser;
@@ -7,25 +9,52 @@ pub mod parser;
 #[cfg(test)]
 mod bench;
 
+pub use engine::Engine;
+pub use error::Error;
+#[cfg(feature = "serde")]
+pub use interpreter::{from_value, to_value};
+pub use interpreter::{
+    Backend, Capabilities, FromArgs, FromValue, FsAccess, IntoArgs, IntoValue, Limits,
+    OutputBuffer, Value,
+};
+
 ///Runs the lexer, parser, and interpreter on the provided string
-pub fn run(data: String, args: &str, debug: bool) {
-    let tokens = lexer::run(&data);
+pub fn run(data: String, args: &str, debug: bool) -> Result<(), Error> {
+    run_file("<input>", data, args, debug)
+}
+
+///Same as `run`, but errors will point at the file `name`
+pub fn run_file(name: &str, data: String, args: &str, debug: bool) -> Result<(), Error> {
+    run_file_with_backend(name, data, args, debug, Backend::default())
+}
+
+///Same as `run_file`, but the script is run by `backend`
+pub fn run_file_with_backend(
+    name: &str,
+    data: String,
+    args: &str,
+    debug: bool,
+    backend: Backend,
+) -> Result<(), Error> {
+    let tokens = lexer::run_file(name, &data)?;
     if debug {
         println!("Generated tokens: {:?}", tokens);
     }
-    match parser::parse(tokens) {
-        Ok(ast) => {
-            let args = parser::read_line(None, &mut lexer::run(&format!("[{}]", args)).iter().peekable(), &vec![&lexer::Expression::Semicolon]).unwrap();
-
-            if debug {
-                println!("{:?}", &ast);
-                println!("{:?}", args);
-            }
-
-            interpreter::run(ast, args)
-        }
-        Err(e) => println!("{}", e),
+    let ast = parser::parse(tokens)?;
+    let args = parser::read_line(
+        &mut lexer::run_file("<args>", &format!("[{}]", args))?
+            .iter()
+            .peekable(),
+        &[&lexer::Expression::Semicolon],
+    )?;
+
+    if debug {
+        println!("{:?}", ast);
+        println!("{:?}", args);
     }
+
+    interpreter::run_with(ast, args, backend)?;
+    Ok(())
 }
 
 pub fn version() -> &'static str {
@@ -2,9 +2,12 @@
 mod tests;
 
 use super::lexer::*;
+use crate::error::{Error, ParseErrorKind};
 use std::iter::Peekable;
 use std::slice::Iter;
 
+type Tokens<'a> = Peekable<Iter<'a, Token>>;
+
 //compiler stuff
 
 //making the nodes hold the actual values instead of the Expressions might be worth it to make
@@ -12,643 +15,881 @@ use std::slice::Iter;
 ///Enum with variants for each type of statement or literal in the lang
 #[derive(PartialEq, Debug, Clone, PartialOrd)]
 pub enum ExprNode {
-    Operation(Box<Expression>, Box<ExprNode>, Box<ExprNode>), //Operator, Left side, Right side
+    Operation(Box<Expression>, Box<Node>, Box<Node>), //Operator, Left side, Right side
     StrLiteral(Box<String>),
-    NumLiteral(Box<f32>),
+    IntLiteral(i64),
+    FloatLiteral(f64),
     BoolLiteral(bool),
     Name(Box<String>),
-    Call(Box<Expression>, Vec<ExprNode>), //name, args
-    MethodCall(Box<ExprNode>, Vec<ExprNode>),
-    Block(Vec<ExprNode>),
-    Func(Box<Expression>, Vec<ExprNode>, Box<ExprNode>), //Name, params, function body
-    Class(Box<Expression>, Box<ExprNode>), //name, body
-    New(Box<Expression>, Vec<ExprNode>), //name params
-    Loop(Box<String>, Box<ExprNode>, Box<ExprNode>),     //loop keyword, condition, block
-    ForLoopDec(Box<ExprNode>, Box<ExprNode>, Box<ExprNode>), //declaration, condition, incrementation
-    Statement(Box<ExprNode>),
-    ReturnVal(Box<ExprNode>),
-    IfStatement(Box<ExprNode>, Box<ExprNode>, Box<ExprNode>), //condition, body, branch
-    ElseStatement(Box<ExprNode>),                             //body
-    Array(Vec<ExprNode>),
-    Index(Box<ExprNode>, Box<ExprNode>), //array identifier, inedex
+    Call(Box<Expression>, Vec<Node>), //name, args
+    Invoke(Box<Node>, Vec<Node>),     //calls whatever the first node evaluates to, args
+    MethodCall(Box<Node>, Vec<Node>),
+    Block(Vec<Node>),
+    Func(Box<Expression>, Vec<Node>, Box<Node>), //Name, params, function body
+    Lambda(Vec<Node>, Box<Node>),                //params, function body
+    Class(Box<Expression>, Box<Node>),           //name, body
+    New(Box<Expression>, Vec<Node>),             //name params
+    Loop(Box<String>, Box<Node>, Box<Node>),     //loop keyword, condition, block
+    ForLoopDec(Box<Node>, Box<Node>, Box<Node>), //declaration, condition, incrementation
+    ForIn(Vec<Node>, Box<Node>),                 //loop variables, what's looped over
+    Range(Box<Node>, Box<Node>, Box<Node>, bool), //start, end, step, whether the end is included
+    Group(Box<Node>),                            //an expression wrapped in parentheses
+    Unary(Box<Expression>, Box<Node>),           //operator, operand
+    ReturnVal(Box<Node>),
+    IfStatement(Box<Node>, Box<Node>, Box<Node>), //condition, body, branch
+    ElseStatement(Box<Node>),                     //body
+    Array(Vec<Node>),
+    Map(Vec<(Node, Node)>),      //key, value
+    Index(Box<Node>, Box<Node>), //array identifier, inedex
     Illegal(Option<Expression>),
     EOF,
 }
 
 impl ExprNode {
     ///Returns the inner value of a node as a string if possible
-    pub fn inner(&self) -> String{
+    pub fn inner(&self) -> Option<String> {
         match self {
-            ExprNode::StrLiteral(l) => l.to_string(),
-            ExprNode::NumLiteral(l) => l.to_string(),
-            ExprNode::BoolLiteral(l) => l.to_string(),
-            ExprNode::Name(l) => l.to_string(),
-            _ => panic!("Can't unwrap {:?}", self)
+            ExprNode::StrLiteral(l) => Some(l.to_string()),
+            ExprNode::IntLiteral(l) => Some(l.to_string()),
+            ExprNode::FloatLiteral(l) => Some(l.to_string()),
+            ExprNode::BoolLiteral(l) => Some(l.to_string()),
+            ExprNode::Name(l) => Some(l.to_string()),
+            _ => None,
+        }
+    }
+}
 
+///Where a local variable lives: how many scopes out from the use it was declared, and its slot
+///in that scope
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
+}
+
+///A node in the tree along with the span of source code it was parsed from
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub kind: ExprNode,
+    pub span: Span,
+    ///Set by the resolver on nodes that name a variable. `None` means it's a global, looked up by name
+    pub var: Option<Slot>,
+    ///Set by the resolver on nodes that open a scope: how many slots its variables need
+    pub locals: usize,
+}
+
+impl Node {
+    pub fn new(kind: ExprNode, span: Span) -> Node {
+        Node {
+            kind,
+            span,
+            var: None,
+            locals: 0,
         }
     }
 }
 
+//Like tokens, nodes are compared by kind only so trees built by hand can be compared to parsed ones
+impl PartialEq for Node {
+    fn eq(&self, other: &Node) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Node) -> Option<std::cmp::Ordering> {
+        self.kind.partial_cmp(&other.kind)
+    }
+}
+
+impl From<ExprNode> for Node {
+    fn from(kind: ExprNode) -> Node {
+        Node::new(kind, Span::default())
+    }
+}
+
+///Creates an error for a token that showed up somewhere it shouldn't have
+fn unexpected(t: &Token, expected: &str) -> Error {
+    if t.kind == Expression::EOF {
+        return unexpected_eof(&t.span, expected);
+    }
+    Error::parse(
+        ParseErrorKind::UnexpectedToken,
+        format!("Expected {}, found {}", expected, t.kind),
+        t.span.clone(),
+    )
+}
+
+///Creates an error for when the tokens run out early, pointing at the last thing we saw
+fn unexpected_eof(span: &Span, expected: &str) -> Error {
+    Error::parse(
+        ParseErrorKind::UnexpectedEof,
+        format!("Expected {}, found end of file", expected),
+        span.clone(),
+    )
+}
+
 ///Starts the parser
-pub fn parse(tokens: Vec<Expression>) -> Result<ExprNode, String> {
-    //let root = vec!();
-    let iter = tokens.iter();
+pub fn parse(mut tokens: Vec<Token>) -> Result<Node, Error> {
+    //always finish with an EOF token so running out of tokens can be pointed at the end of the file
+    if tokens.last().map(|t| &t.kind) != Some(&Expression::EOF) {
+        let eof = eof_after(tokens.last());
+        tokens.push(eof);
+    }
+
+    make_block(&mut tokens.iter().peekable(), None)
+}
+
+///Creates an EOF token that sits right after `last`
+fn eof_after(last: Option<&Token>) -> Token {
+    let span = match last {
+        Some(t) => Span {
+            col: t.span.col + (t.span.end - t.span.start),
+            start: t.span.end,
+            ..t.span.clone()
+        },
+        None => Span::default(),
+    };
+    Token::new(Expression::EOF, span)
+}
 
-    make_block(&mut iter.peekable())
+///Consumes the next token if it's `kind`, otherwise complains that `expected` was missing
+fn expect<'a>(
+    iter: &mut Tokens<'a>,
+    kind: &Expression,
+    expected: &str,
+) -> Result<&'a Token, Error> {
+    match iter.next() {
+        Some(t) if &t.kind == kind => Ok(t),
+        Some(t) => Err(unexpected(t, expected)),
+        None => Err(unexpected_eof(&Span::default(), expected)),
+    }
+}
 
-    // node
+///Reads a comma separated list of items up to and including the `close` token, which is returned alongside the items
+fn comma_list<'a, T, F>(
+    iter: &mut Tokens<'a>,
+    close: &Expression,
+    expected: &str,
+    mut item: F,
+) -> Result<(Vec<T>, &'a Token), Error>
+where
+    F: FnMut(&mut Tokens<'a>) -> Result<T, Error>,
+{
+    let mut items = vec![];
+    loop {
+        if iter.peek().map(|t| &t.kind) == Some(close) {
+            return Ok((items, iter.next().unwrap()));
+        }
+        items.push(item(iter)?);
+        match iter.next() {
+            Some(t) if t.kind == Expression::Comma => continue,
+            Some(t) if &t.kind == close => return Ok((items, t)),
+            Some(t) => return Err(unexpected(t, &format!("\",\" or {}", expected))),
+            None => return Err(unexpected_eof(&Span::default(), expected)),
+        }
+    }
 }
 
-///Loops through expressions to generate all of the nodes in a block of code
-fn make_block(iter: &mut Peekable<Iter<Expression>>) -> Result<ExprNode, String> {
+///Loops through statements to generate all of the nodes in a block of code. Blocks with an `open` brace
+///end at the matching close brace, otherwise they run until the end of the file.
+fn make_block(iter: &mut Tokens, open: Option<&Token>) -> Result<Node, Error> {
     let mut root = vec![];
+    let mut span = match open {
+        Some(t) => t.span.clone(),
+        None => iter.peek().map(|t| t.span.clone()).unwrap_or_default(),
+    };
 
-    while let Some(t) = iter.next() {
-        match t {
-            Expression::EOF => break,
-            Expression::Rbrace => {
-                // iter.next();
-                break;
-            }
-            Expression::Key(s) => {
-                root.push(key_word(iter, Some(t), &s)?);
+    loop {
+        match iter.peek() {
+            Some(Token {
+                kind: Expression::Rbrace,
+                ..
+            }) if open.is_some() => {
+                span = span.to(&iter.next().unwrap().span);
+                return Ok(Node::new(ExprNode::Block(root), span));
             }
-            Expression::Ident(_) => {
-                root.push(expr(iter, Some(t))?);
+            Some(
+                t @ Token {
+                    kind: Expression::EOF,
+                    ..
+                },
+            ) => {
+                if let Some(open) = open {
+                    return Err(unexpected(t, "\"}\"").with_note(format!(
+                        "the block starting at {} was never closed",
+                        open.span
+                    )));
+                }
+                break;
             }
-            Expression::Lbrace => {
-                root.push(make_block(iter)?);
+            Some(Token {
+                kind: Expression::Semicolon,
+                ..
+            }) => {
+                iter.next();
             }
-            _ => {}//root.push(expr(iter, Some(t))?)} //root.push(read_line(Some(&t), iter)?),
+            Some(_) => root.push(statement(iter)?),
+            None => break,
         }
     }
 
-    Ok(ExprNode::Block(root))
+    if let Some(last) = root.last() {
+        span = span.to(&last.span);
+    }
+    Ok(Node::new(ExprNode::Block(root), span))
 }
 
-///Handles all the different keywords
-fn key_word(
-    iter: &mut Peekable<Iter<'_, Expression>>,
-    cur: Option<&Expression>,
-    word: &str,
-) -> Result<ExprNode, String> {
-    match word.trim() {
-        "print" | "println" => Ok(ExprNode::Call(
-            Box::new(Expression::Key(word.to_owned())),
-            vec![read_line(None, iter, &vec![])?],
-        )),
-        "fn" => def_func(iter, cur),
-        "class" => define_class(iter), //get the name of the class and collect the block that should follow
-        "new" => new_object(iter), //call to a function that passes in the class name and the args for the constructor
-        "return" => Ok(ExprNode::ReturnVal(Box::new(expr(iter, cur)?))),
-        "true" => Ok(ExprNode::BoolLiteral(true)),
-        "false" => Ok(ExprNode::BoolLiteral(false)),
-        "null" => Ok(ExprNode::Illegal(None)),
-        "while" => {
-            let con = read_line(None, iter, &vec![&Expression::Rbrace])?;
-            let body = make_block(iter)?;
-            Ok(ExprNode::Loop(
-                Box::new("while".to_string()),
-                Box::new(con),
-                Box::new(body),
-            ))
+///Parses a keyword construct, a nested block, or an expression that gets finished with a semicolon
+fn statement(iter: &mut Tokens) -> Result<Node, Error> {
+    match iter.peek().map(|t| &t.kind) {
+        //these keywords can start an expression, so they get handled along with the rest of them
+        Some(Expression::Key(k))
+            if !matches!(k.as_str(), "true" | "false" | "null" | "new")
+                && (k != "fn" || named_function(iter)) =>
+        {
+            let key = iter.next().unwrap();
+            key_word(iter, key)
+        }
+        Some(Expression::Lbrace) => {
+            let open = iter.next().unwrap();
+            make_block(iter, Some(open))
+        }
+        _ => {
+            let node = expression(iter, 0)?;
+            end_statement(iter)?;
+            Ok(node)
         }
-        "for" => Ok(ExprNode::Loop(
-            Box::new("for".to_string()),
-            Box::new(make_for_loop(iter)?),
-            Box::new(make_block(iter)?),
-        )),
-        "if" => make_if(iter),
-        _ => Err(format!("Unknown keyword {}", word)),
     }
 }
 
-///Generates the nodes needed to define a function
-fn def_func(
-    iter: &mut Peekable<Iter<'_, Expression>>,
-    _cur: Option<&Expression>,
-) -> Result<ExprNode, String> {
-    let mut name: Expression = Expression::Ident("broken".to_owned());
-    let mut params = vec![];
-    let mut body: ExprNode = ExprNode::Illegal(None);
-
-    if let Some(n) = iter.next() {
-        match n {
-            Expression::Ident(_) => name = n.clone(),
-            _ => return Err(format!("Expected indentifier found {:?}", n)),
-        }
-    }
+///Checks if the `fn` coming up is followed by a name, since functions without one are expressions
+fn named_function(iter: &Tokens) -> bool {
+    let mut ahead = iter.clone();
+    ahead.next();
+    matches!(ahead.peek().map(|t| &t.kind), Some(Expression::Ident(_)))
+}
 
-    while let Some(p) = iter.next() {
-        match p {
-            Expression::Lparen => continue,
-            Expression::Rparen => break,
-            Expression::Ident(i) => params.push(ExprNode::Name(Box::new(i.to_string()))),
+///Checks if the parentheses that were just opened hold the parameters of an arrow function, like `(a, b) => a + b`
+fn arrow_params(iter: &Tokens) -> bool {
+    let mut ahead = iter.clone();
+    let mut nest = 1;
+    while nest > 0 {
+        match ahead.next().map(|t| &t.kind) {
+            Some(Expression::Lparen) => nest += 1,
+            Some(Expression::Rparen) => nest -= 1,
+            Some(Expression::EOF) | None => return false,
             _ => {}
         }
     }
+    matches!(ahead.peek().map(|t| &t.kind), Some(Expression::Arrow))
+}
 
-    if let Some(b) = iter.next() {
-        if let Expression::Lbrace = b {
-            body = make_block(iter)?;
+///Makes sure a statement is finished properly. The semicolon can only be left off before the end of a block or the file
+fn end_statement(iter: &mut Tokens) -> Result<(), Error> {
+    match iter.peek() {
+        Some(Token {
+            kind: Expression::Semicolon,
+            ..
+        }) => {
+            iter.next();
+            Ok(())
         }
+        Some(Token {
+            kind: Expression::Rbrace,
+            ..
+        })
+        | Some(Token {
+            kind: Expression::EOF,
+            ..
+        })
+        | None => Ok(()),
+        Some(t) => Err(unexpected(t, "\";\"")),
     }
+}
 
-    Ok(ExprNode::Func(Box::new(name), params, Box::new(body)))
+///Handles all the different keywords that start a statement
+fn key_word(iter: &mut Tokens, key: &Token) -> Result<Node, Error> {
+    let word = match &key.kind {
+        Expression::Key(w) => w.trim(),
+        _ => return Err(unexpected(key, "keyword")),
+    };
+    let span = key.span.clone();
+    match word {
+        "print" | "println" => {
+            let arg = expression(iter, 0)?;
+            end_statement(iter)?;
+            let span = span.to(&arg.span);
+            Ok(Node::new(
+                ExprNode::Call(Box::new(key.kind.clone()), vec![arg]),
+                span,
+            ))
+        }
+        "fn" => def_func(iter, key),
+        "class" => define_class(iter, key), //get the name of the class and collect the block that should follow
+        "return" => {
+            let val = match iter.peek().map(|t| &t.kind) {
+                Some(Expression::Semicolon)
+                | Some(Expression::Rbrace)
+                | Some(Expression::EOF)
+                | None => Node::new(ExprNode::Illegal(None), span.clone()),
+                _ => expression(iter, 0)?,
+            };
+            end_statement(iter)?;
+            let span = span.to(&val.span);
+            Ok(Node::new(ExprNode::ReturnVal(Box::new(val)), span))
+        }
+        "while" => {
+            let con = expression(iter, 0)?;
+            let open = expect(iter, &Expression::Lbrace, "\"{\"")?;
+            let body = make_block(iter, Some(open))?;
+            let span = span.to(&body.span);
+            Ok(Node::new(
+                ExprNode::Loop(Box::new("while".to_string()), Box::new(con), Box::new(body)),
+                span,
+            ))
+        }
+        "for" => {
+            let dec = match iter.peek().map(|t| &t.kind) {
+                Some(Expression::Lparen) => make_for_loop(iter, key)?,
+                _ => make_for_in(iter, key)?,
+            };
+            let open = expect(iter, &Expression::Lbrace, "\"{\"")?;
+            let body = make_block(iter, Some(open))?;
+            let span = span.to(&body.span);
+            Ok(Node::new(
+                ExprNode::Loop(Box::new("for".to_string()), Box::new(dec), Box::new(body)),
+                span,
+            ))
+        }
+        "if" => make_if(iter, key),
+        _ => Err(Error::parse(
+            ParseErrorKind::UnknownKeyword,
+            format!("Unknown keyword {}", word),
+            span,
+        )),
+    }
 }
 
-///Reads to the end of the current line, stopping at the first semicolon or lbrace, or the specified deliminator
-pub fn read_line<'a>(
-    prev: Option<&Vec<Expression>>,
-    iter: &mut Peekable<Iter<Expression>>,
-    delim: &Vec<&Expression>,
-) -> Result<ExprNode, String> {
-    //iterate through the next set of expressions until we get to a ';'
-    let mut accum = if let Some(v) = prev {
-        v.clone()
-    } else {
-        Vec::new()
+///Generates the nodes needed to define a function
+fn def_func(iter: &mut Tokens, key: &Token) -> Result<Node, Error> {
+    let name = match iter.next() {
+        Some(
+            n @ Token {
+                kind: Expression::Ident(_),
+                ..
+            },
+        ) => n.kind.clone(),
+        Some(n) => return Err(unexpected(n, "identifier")),
+        None => return Err(unexpected_eof(&key.span, "identifier")),
     };
 
-    
-    for exp in iter.take_while(|e| !(delim.contains(e)|| Expression::Lbrace == **e)) {
-        match exp {
-            // Expression::Lbracket => {
-            //     return make_array(iter);
-            // }
-            Expression::Operator(op) => {
-                return Ok(if op == &'.' {
-                    let tmp = ExprNode::Operation(
-                    Box::new(exp.clone()),
-                    Box::new(expr(&mut accum.iter().peekable(), None)?),
-                    Box::new(make_node(iter.next().unwrap())));
-                    
-                        match iter.peek() {
-                            Some(Expression::Operator(_)) => {
-                                let operator = iter.next().unwrap();
-                                ExprNode::Operation(
-                                    Box::new(operator.clone()),
-                                    Box::new(tmp),
-                                    Box::new(read_line(None, iter, delim)?))
-                            }
-                            Some(Expression::Equal) => {
-                                //if there is an equal sign after the dot operator, then use the dot operation as the left side of it
-                                iter.next();
-                                ExprNode::Operation(
-                                    Box::new(Expression::Equal),
-                                    Box::new(tmp),
-                                    Box::new(read_line(None, iter, &vec![&Expression::Semicolon])?))
-                            },
-                            Some(Expression::Lparen) => {
-                                iter.next();
-                                ExprNode::MethodCall(Box::new(tmp), find_params(iter)?)
-                            }
-                            _ => tmp,
-                        }
-
-                }else{
-                ExprNode::Operation(
-                    Box::new(exp.clone()),
-                    Box::new(expr(&mut accum.iter().peekable(), None)?),
-                    Box::new(read_line(None, iter, delim)?),
-                )
-                });
-            }
-            Expression::BoolOp(_) => {
-                return Ok(ExprNode::Operation(
-                    Box::new(exp.clone()),
-                    Box::new(expr(&mut accum.iter().peekable(), None)?),
-                    Box::new(read_line(None, iter, delim)?),
-                ))
-            }
-            Expression::CompoundOp(_) => {
-                let left = expr(&mut accum.iter().peekable(), None)?;
-                return make_compound_op(left, exp, iter);
+    expect(iter, &Expression::Lparen, "\"(\"")?;
+    let params = find_params(iter)?;
+    let open = expect(iter, &Expression::Lbrace, "\"{\"")?;
+    let body = make_block(iter, Some(open))?;
+
+    let span = key.span.to(&body.span);
+    Ok(Node::new(
+        ExprNode::Func(Box::new(name), params, Box::new(body)),
+        span,
+    ))
+}
+
+///Returns the left and right binding power of an infix operator, or `None` if the token isn't one.
+///
+///Operators with more power bind tighter, so `2 * 3 + 4` is `(2 * 3) + 4`. Left associative operators
+///have a stronger right side, so `a - b - c` is `(a - b) - c`, and right associative ones have a stronger
+///left side, so `a = b = c` is `a = (b = c)`.
+///
+///| Power | Operators                              | Associativity |
+///|-------|----------------------------------------|---------------|
+///| 1-2   | `=` and the compound assignments       | right         |
+///| 3-4   | `\|\|` `or`                              | left          |
+///| 5-6   | `&&` `and`                             | left          |
+///| 7-8   | `==` `!=`                              | left          |
+///| 9-10  | `<` `<=` `>` `>=`                      | left          |
+///| 11-12 | `+` `-`                                | left          |
+///| 13-14 | `*` `/` `%` `div`                      | left          |
+///| 15    | prefix `-` `+` `!` `not`, see `prefix` |               |
+///| 15-16 | `**`                                   | right         |
+///| 17    | calls, indexing, `.`, `++` `--`, see `postfix_power` | left |
+fn infix_power(op: &Expression) -> Option<(u8, u8)> {
+    match op {
+        Expression::Equal => Some((2, 1)),
+        Expression::CompoundOp(c) if c != "++" && c != "--" => Some((2, 1)),
+        Expression::BoolOp(o) => match o.as_str() {
+            "||" => Some((3, 4)),
+            "&&" => Some((5, 6)),
+            "==" | "!=" => Some((7, 8)),
+            "<" | "<=" | ">" | ">=" => Some((9, 10)),
+            _ => None,
+        },
+        Expression::Operator('+') | Expression::Operator('-') => Some((11, 12)),
+        Expression::Operator('*' | '/' | '%') => Some((13, 14)),
+        Expression::Key(k) if k == "div" => Some((13, 14)),
+        //binds tighter than a prefix on its left but not one on its right, so `-2 ** 2` is `-(2 ** 2)`
+        Expression::Power => Some((16, 15)),
+        _ => None,
+    }
+}
+
+///Power of the operators that come after the thing they work on, which bind tighter than everything else
+fn postfix_power(op: &Expression) -> Option<u8> {
+    match op {
+        Expression::Lparen | Expression::Lbracket | Expression::Operator('.') => Some(17),
+        Expression::CompoundOp(c) if c == "++" || c == "--" => Some(17),
+        _ => None,
+    }
+}
+
+///Power of the prefix operators, which bind tighter than any infix operator but looser than calls and indexing
+const PREFIX_POWER: u8 = 15;
+
+///Parses an expression, only consuming operators that bind at least as tightly as `min_power`
+fn expression(iter: &mut Tokens, min_power: u8) -> Result<Node, Error> {
+    let mut left = prefix(iter)?;
+
+    while let Some(&op) = iter.peek() {
+        if let Some(power) = postfix_power(&op.kind) {
+            if power < min_power {
+                break;
             }
-            Expression::Equal => {
-                return Ok(ExprNode::Operation(
-                    Box::new(exp.clone()),
-                    Box::new(expr(&mut accum.iter().peekable(), None)?),
-                    Box::new(read_line(None, iter, delim)?),
-                ))
+            iter.next();
+            left = postfix(left, op, iter)?;
+        } else if let Some((left_power, right_power)) = infix_power(&op.kind) {
+            if left_power < min_power {
+                break;
             }
-            _ => accum.push(exp.clone()),
+            iter.next();
+            let right = expression(iter, right_power)?;
+            left = infix(left, op, right)?;
+        } else {
+            break;
         }
     }
 
-    Ok(expr(&mut accum.iter().peekable(), None)?)
+    Ok(left)
 }
 
-fn expr(
-    iter: &mut Peekable<Iter<'_, Expression>>,
-    cur: Option<&Expression>,
-) -> Result<ExprNode, String> {
-    let t = iter.next();
-    let mut node: ExprNode = ExprNode::Illegal(None);
+///Parses whatever can start an expression, like literals, names, groups, and array literals
+fn prefix(iter: &mut Tokens) -> Result<Node, Error> {
+    let t = match iter.next() {
+        Some(t) => t,
+        None => return Err(unexpected_eof(&Span::default(), "expression")),
+    };
+    let span = t.span.clone();
 
-    if let Some(next) = iter.peek() {
-        match next {
-            Expression::Operator(_) => match cur {
-                Some(Expression::Lparen) => {
-                    return expr(iter, cur);
-                }
-                Some(_) => return expr(iter, t),
-                None => {}
-            },
-            Expression::BoolOp(_) => return expr(iter, t),
-            _ => {}
+    match &t.kind {
+        Expression::Ident(_) if iter.peek().map(|a| &a.kind) == Some(&Expression::Arrow) => {
+            //a single parameter doesn't need parentheses, like `x => x * 2`
+            let param = make_node(t);
+            iter.next();
+            arrow_body(iter, vec![param], &span)
         }
-    }
-    if let Some(exp) = t {
-        // println!(
-        //     "The expresion was: {:?}, current Expression was {:?}",
-        //     &t, cur
-        // );
-        match exp {
-            Expression::Equal => {
-                if let Some(Expression::Ident(name)) = cur {
-                    node = ExprNode::Operation(
-                        Box::new(t.unwrap().clone()),
-                        Box::new(ExprNode::Name(Box::new(name.to_string()))),
-                        Box::new(read_line(None, iter, &vec![&Expression::Semicolon])?),
-                    )
-                }
-            }
-            Expression::Operator(op) => {
-                if op == &'.' {
-                    let tmp = ExprNode::Operation(
-                    Box::new(t.unwrap().clone()),
-                    Box::new(make_node(cur.unwrap())),
-                    Box::new(make_node(iter.next().unwrap())));
-                    node = 
-                        match iter.peek() {
-                            Some(Expression::Operator(_)) => {
-                                let operator = iter.next().unwrap();
-                                ExprNode::Operation(
-                                    Box::new(operator.clone()),
-                                    Box::new(tmp),
-                                    Box::new(read_line(None, iter, &vec![&Expression::Semicolon])?))
-                            }
-                            Some(Expression::Equal) => {
-                                //if there is an equal sign after the dot operator, then use the dot operation as the left side of it
-                                iter.next();
-                                ExprNode::Operation(
-                                    Box::new(Expression::Equal),
-                                    Box::new(tmp),
-                                    Box::new(read_line(None, iter, &vec![&Expression::Semicolon])?))
-                            },
-                            Some(Expression::Lparen) => {
-                                iter.next();
-                                ExprNode::MethodCall(Box::new(tmp), find_params(iter)?)
-                            }
-                            _ => tmp,
-                        }
-
-                }else{
-                node = ExprNode::Operation(
-                    Box::new(t.unwrap().clone()),
-                    Box::new(make_node(cur.unwrap())),
-                    Box::new(read_line(None, iter, &vec![&Expression::Semicolon])?),
-                )
-                }
-            }
-            Expression::CompoundOp(_) => {
-                node = make_compound_op(make_node(cur.unwrap()), t.unwrap(), iter)?;
-            }
-            Expression::BoolOp(_) => {
-                node = ExprNode::Operation(
-                    Box::new(t.unwrap().clone()),
-                    Box::new(make_node(cur.unwrap())),
-                    Box::new(expr(iter, t)?),
-                )
-            }
-            Expression::Word(s) => node = ExprNode::StrLiteral(Box::new(s.to_string())),
-            Expression::Number(n) => node = ExprNode::NumLiteral(Box::new(*n)),
-            Expression::Key(w) => node = key_word(iter, cur, w)?,
-            Expression::Ident(i) => {
-                // println!("Next exp: {:?}", iter.peek());
-                node = match iter.peek() {
-                    Some(Expression::Lparen) => expr(iter, t)?,
-                    Some(Expression::Lbracket) => index_array(t.unwrap(), iter)?,
-                    Some(Expression::Operator(_)) => expr(iter, t)?,
-                    Some(Expression::Equal) => expr(iter, t)?,
-                    _ => ExprNode::Name(Box::new(i.to_string())),
-                }
-            }
-            Expression::Lparen => {
-                // println!("This is what cur =  {:?}", cur);
-                node = if let Some(Expression::Ident(_)) = cur {
-                    ExprNode::Call(Box::new(cur.unwrap().clone()), find_params(iter)?)
-                //if there was an identifier last before the '(', it should be a function call
-                } else {
-                    //Otherwise it should be a statement
-                    ExprNode::Statement(Box::new(expr(iter, cur)?))
-                }
-            }
-            Expression::Rparen => {
-                node = if let Some(Expression::Semicolon) = iter.peek() {
-                    make_node(cur.unwrap())
-                } else {
-                    let tmp = iter.next();
-                    expr(iter, tmp)?
-                }
+        Expression::Int(_) | Expression::Float(_) | Expression::Word(_) | Expression::Ident(_) => {
+            Ok(make_node(t))
+        }
+        Expression::Key(k) => match k.as_str() {
+            "true" => Ok(Node::new(ExprNode::BoolLiteral(true), span)),
+            "false" => Ok(Node::new(ExprNode::BoolLiteral(false), span)),
+            "null" => Ok(Node::new(ExprNode::Illegal(None), span)),
+            "new" => new_object(iter, t), //call to a function that passes in the class name and the args for the constructor
+            "fn" => {
+                //functions without a name, like `fn (a, b) { return a + b; }`
+                expect(iter, &Expression::Lparen, "\"(\"")?;
+                let params = find_params(iter)?;
+                let open = expect(iter, &Expression::Lbrace, "\"{\"")?;
+                let body = make_block(iter, Some(open))?;
+                let span = span.to(&body.span);
+                Ok(Node::new(ExprNode::Lambda(params, Box::new(body)), span))
             }
+            _ => Err(unexpected(t, "expression")),
+        },
+        Expression::Lparen if arrow_params(iter) => {
+            let params = find_params(iter)?;
+            expect(iter, &Expression::Arrow, "\"=>\"")?;
+            arrow_body(iter, params, &span)
+        }
+        Expression::Lparen => {
+            let inner = expression(iter, 0)?;
+            let close = expect(iter, &Expression::Rparen, "\")\"")?;
+            Ok(Node::new(
+                ExprNode::Group(Box::new(inner)),
+                span.to(&close.span),
+            ))
+        }
+        Expression::Lbracket => {
+            let (items, close) =
+                comma_list(iter, &Expression::Rbracket, "\"]\"", |i| expression(i, 0))?;
+            Ok(Node::new(ExprNode::Array(items), span.to(&close.span)))
+        }
+        Expression::Lbrace => {
+            //braces that start an expression are a map literal, like `{ "a": 1, "b": 2 }`
+            let (entries, close) = comma_list(iter, &Expression::Rbrace, "\"}\"", |i| {
+                let key = expression(i, 0)?;
+                expect(i, &Expression::Colon, "\":\"")?;
+                Ok((key, expression(i, 0)?))
+            })?;
+            Ok(Node::new(ExprNode::Map(entries), span.to(&close.span)))
+        }
+        Expression::Operator('-') | Expression::Operator('+') => unary(iter, t),
+        Expression::BoolOp(o) if o == "!" => unary(iter, t),
+        Expression::FStringStart => interpolation(iter, t),
+        _ => Err(unexpected(t, "expression")),
+    }
+}
+
+///Parses the text and expressions of an f-string, which are joined the same way adding them to a string
+///would, so `f"total: {sum / n}"` is `"total: " + sum / n`
+fn interpolation(iter: &mut Tokens, start: &Token) -> Result<Node, Error> {
+    let empty = || Node::new(ExprNode::StrLiteral(Box::default()), start.span.clone());
+    let mut joined: Option<Node> = None;
+    loop {
+        let t = match iter.next() {
+            Some(t) => t,
+            None => return Err(unexpected_eof(&start.span, "end of f-string")),
+        };
+        let part = match &t.kind {
+            Expression::Word(_) => make_node(t),
             Expression::Lbrace => {
-                node = make_block(iter)?;
+                let inner = expression(iter, 0)?;
+                expect(iter, &Expression::Rbrace, "\"}\"")?;
+                inner
             }
-            Expression::Lbracket => {
-                node = match cur {
-                    Some(Expression::Ident(_)) => read_line(
-                        Some(&vec![cur.unwrap().clone(), t.unwrap().clone()]),
-                        iter,
-                        &vec![],
-                    )?,
-                    _ => make_array(iter)?,
-                }
+            Expression::FStringEnd => {
+                let mut node = joined.unwrap_or_else(empty);
+                node.span = start.span.to(&t.span);
+                return Ok(node);
             }
-            Expression::Semicolon => {}
-            _ => node = expr(iter, cur)?,
-        }
-    } else {
-        node = ExprNode::EOF;
+            _ => return Err(unexpected(t, "f-string")),
+        };
+        joined = Some(match joined {
+            Some(left) => operation(Expression::Operator('+'), left, part),
+            None if matches!(part.kind, ExprNode::StrLiteral(_)) => part,
+            //starting from an empty string makes sure an expression at the start is turned into a string too
+            None => operation(Expression::Operator('+'), empty(), part),
+        });
     }
+}
 
-    Ok(node)
+///Parses the operand of a prefix operator like `-x` or `!done`
+fn unary(iter: &mut Tokens, op: &Token) -> Result<Node, Error> {
+    let operand = expression(iter, PREFIX_POWER)?;
+    let span = op.span.to(&operand.span);
+    Ok(Node::new(
+        ExprNode::Unary(Box::new(op.kind.clone()), Box::new(operand)),
+        span,
+    ))
 }
 
-fn make_compound_op(
-    ident: ExprNode,
-    compop: &Expression,
-    iter: &mut Peekable<Iter<'_, Expression>>,
-) -> Result<ExprNode, String> {
-    //idk if this is good or not but I don't see why such a niche function needs to be defined outside
-    //of the only place it's ever used
-    let make_op = |op, right| {
-        ExprNode::Operation(
-            Box::new(Expression::Equal),
-            Box::new(ident.clone()),
-            Box::new(ExprNode::Operation(
-                Box::new(op),
-                Box::new(ident),
-                Box::new(right),
-            )),
-        )
+///Reads what comes after the `=>` of an arrow function, which is either a block or a single expression that gets returned
+fn arrow_body(iter: &mut Tokens, params: Vec<Node>, start: &Span) -> Result<Node, Error> {
+    let body = match iter.peek().map(|t| &t.kind) {
+        Some(Expression::Lbrace) => {
+            let open = iter.next().unwrap();
+            make_block(iter, Some(open))?
+        }
+        _ => expression(iter, 0)?,
     };
+    let span = start.to(&body.span);
+    Ok(Node::new(ExprNode::Lambda(params, Box::new(body)), span))
+}
 
-    if let Expression::CompoundOp(tmp) = compop {
-        match tmp.as_str() {
-            "+=" => {
-                let op = Expression::Operator('+');
-                let right = read_line(None, iter, &vec![])?;
-                //converts 'x += y' to 'x = x + y'
-                Ok(make_op(op, right))
-            }
-            "-=" => {
-                let op = Expression::Operator('-');
-                let right = read_line(None, iter, &vec![])?;
-                Ok(make_op(op, right))
-            }
-            "*=" => {
-                let op = Expression::Operator('*');
-                let right = read_line(None, iter, &vec![])?;
-                Ok(make_op(op, right))
-            }
-            "/=" => {
-                let op = Expression::Operator('/');
-                let right = read_line(None, iter, &vec![])?;
-                Ok(make_op(op, right))
-            }
-            "++" => {
-                let op = Expression::Operator('+');
-                let right = ExprNode::NumLiteral(Box::new(1.0));
-                Ok(make_op(op, right))
-            }
-            "--" => {
-                let op = Expression::Operator('-');
-                let right = ExprNode::NumLiteral(Box::new(1.0));
-                Ok(make_op(op, right))
-            }
-            _ => Err(format!("Unknown compound operator {}", tmp)),
-        }
-    } else {
-        Err(format!("Compound op wasn't a compound op"))
-    }
+///Builds an operation node that spans both of its sides
+fn operation(op: Expression, left: Node, right: Node) -> Node {
+    let span = left.span.to(&right.span);
+    Node::new(
+        ExprNode::Operation(Box::new(op), Box::new(left), Box::new(right)),
+        span,
+    )
 }
 
-fn build_chain_back(
-    ident: &Expression,
-    iter: &mut Peekable<std::iter::Rev<Iter<'_, ExprNode>>>,
-) -> Option<ExprNode> {
-    if let Some(_) = iter.peek() {
-        let index = iter.next().unwrap();
+///Builds the node for an infix operator once both of its sides have been parsed
+fn infix(left: Node, op: &Token, right: Node) -> Result<Node, Error> {
+    match &op.kind {
+        Expression::Equal => Ok(operation(Expression::Equal, assignable(left)?, right)),
+        Expression::CompoundOp(_) => make_compound_op(assignable(left)?, op, right),
+        _ => Ok(operation(op.kind.clone(), left, right)),
+    }
+}
 
-        if let Some(op) = build_chain_back(ident, iter) {
-            //If the next call in the chain returns something, build it normally
-            Some(ExprNode::Operation(
-                Box::new(Expression::Lbracket),
-                Box::new(op),
-                Box::new(index.clone()),
-            ))
-        } else {
-            //If not, we've reached the end and shoud return the root node with the actual identifier
-            Some(ExprNode::Operation(
-                Box::new(Expression::Lbracket),
-                Box::new(make_node(ident)),
-                Box::new(index.clone()),
+///Builds the node for a call, index, member access, or increment applied to `left`
+fn postfix(left: Node, op: &Token, iter: &mut Tokens) -> Result<Node, Error> {
+    match &op.kind {
+        Expression::Lparen => {
+            let (args, close) =
+                comma_list(iter, &Expression::Rparen, "\")\"", |i| expression(i, 0))?;
+            let span = left.span.to(&close.span);
+            match left.kind {
+                ExprNode::Name(n) => Ok(Node::new(
+                    ExprNode::Call(Box::new(Expression::Ident(*n)), args),
+                    span,
+                )),
+                ExprNode::Operation(ref o, _, _) if **o == Expression::Operator('.') => {
+                    Ok(Node::new(ExprNode::MethodCall(Box::new(left), args), span))
+                }
+                _ => Ok(Node::new(ExprNode::Invoke(Box::new(left), args), span)),
+            }
+        }
+        Expression::Lbracket => {
+            let index = expression(iter, 0)?;
+            let close = expect(iter, &Expression::Rbracket, "\"]\"")?;
+            let span = left.span.to(&close.span);
+            Ok(Node::new(
+                ExprNode::Index(Box::new(left), Box::new(index)),
+                span,
             ))
         }
-    } else {
-        None
+        Expression::Operator('.') => {
+            let member = match iter.next() {
+                Some(
+                    m @ Token {
+                        kind: Expression::Ident(_),
+                        ..
+                    },
+                ) => make_node(m),
+                Some(m) => return Err(unexpected(m, "member name")),
+                None => return Err(unexpected_eof(&op.span, "member name")),
+            };
+            Ok(operation(op.kind.clone(), left, member))
+        }
+        Expression::CompoundOp(_) => {
+            //the implicit one in 'x++' and 'x--' lives wherever the operator does
+            let one = Node::new(ExprNode::IntLiteral(1), op.span.clone());
+            make_compound_op(assignable(left)?, op, one)
+        }
+        _ => Err(unexpected(op, "operator")),
     }
 }
 
-fn index_array(
-    ident: &Expression,
-    iter: &mut Peekable<Iter<'_, Expression>>,
-) -> Result<ExprNode, String> {
-    //check if we need to skip the bracket or not
-    if let Some(Expression::Lbracket) = iter.peek() {
-        iter.next();
+///Makes sure the left side of an assignment is something that can actually be assigned to
+fn assignable(target: Node) -> Result<Node, Error> {
+    match &target.kind {
+        ExprNode::Name(_) | ExprNode::Index(..) => Ok(target),
+        ExprNode::Operation(o, _, _) if **o == Expression::Operator('.') => Ok(target),
+        _ => Err(Error::parse(
+            ParseErrorKind::InvalidAssignment,
+            "Invalid left side of assignment",
+            target.span.clone(),
+        )
+        .with_note("only variables, array elements, and object members can be assigned to")),
+    }
+}
+
+///Reads a single expression, stopping at and consuming the first of the specified deliminators
+pub fn read_line(iter: &mut Tokens, delim: &[&Expression]) -> Result<Node, Error> {
+    let node = expression(iter, 0)?;
+    match iter.next() {
+        Some(t) if delim.contains(&&t.kind) || t.kind == Expression::EOF => Ok(node),
+        Some(t) => Err(unexpected(t, "end of expression")),
+        None => Ok(node),
     }
-    let mut multidex = vec![];
-    let index = expr(iter, None)?;
-    iter.next(); //skip the closing rbracket
-    if let Some(Expression::Lbracket) = iter.peek() {
-        //accumulate all of the index operations
-        multidex.push(index);
-        while let Some(Expression::Lbracket) = iter.next() {
-            multidex.push(expr(iter, None)?);
-            iter.next(); //skip the closing rbracket
+}
+
+fn make_compound_op(ident: Node, compop: &Token, right: Node) -> Result<Node, Error> {
+    //idk if this is good or not but I don't see why such a niche function needs to be defined outside
+    //of the only place it's ever used
+    let make_op = |op, right: Node| {
+        let inner = operation(op, ident.clone(), right);
+        operation(Expression::Equal, ident.clone(), inner)
+    };
+
+    if let Expression::CompoundOp(tmp) = &compop.kind {
+        //converts 'x += y' to 'x = x + y'
+        match tmp.as_str() {
+            "+=" | "++" => Ok(make_op(Expression::Operator('+'), right)),
+            "-=" | "--" => Ok(make_op(Expression::Operator('-'), right)),
+            "*=" => Ok(make_op(Expression::Operator('*'), right)),
+            "/=" => Ok(make_op(Expression::Operator('/'), right)),
+            "%=" => Ok(make_op(Expression::Operator('%'), right)),
+            "**=" => Ok(make_op(Expression::Power, right)),
+            _ => Err(unexpected(compop, "compound operator")),
         }
-        Ok(build_chain_back(ident, &mut multidex.iter().rev().peekable()).unwrap())
     } else {
-        //Don't neext to do all that fancy stuff if there's only one index instruction
-        Ok(ExprNode::Index(Box::new(make_node(ident)), Box::new(index)))
+        Err(unexpected(compop, "compound operator"))
     }
 }
 
-fn make_node(exp: &Expression) -> ExprNode {
+fn make_node(exp: &Token) -> Node {
     //feels bad to clone here but I don't know if it's avoidable
-    match exp.clone() {
+    let kind = match exp.kind.clone() {
         Expression::Word(s) => ExprNode::StrLiteral(Box::new(s)),
-        Expression::Number(n) => ExprNode::NumLiteral(Box::new(n)),
+        Expression::Int(n) => ExprNode::IntLiteral(n),
+        Expression::Float(n) => ExprNode::FloatLiteral(n),
         Expression::Ident(i) => ExprNode::Name(Box::new(i)),
-        _ => ExprNode::Illegal(Some(exp.clone())),
-    }
+        other => ExprNode::Illegal(Some(other)),
+    };
+    Node::new(kind, exp.span.clone())
 }
 
-fn find_params(
-    peekable: &mut Peekable<Iter<'_, Expression>>,
-) -> Result<Vec<ExprNode>, String> {
-    let mut nest = 1;
-    let mut params = vec![];
-    loop {
-        // println!("{:?} is next in params", peekable.peek());
-        // println!("{:?} is the accumulated params", params);
-        // std::io::stdin().read_line(&mut String::new());
-        match peekable.peek() {
-            Some(Expression::Lparen) => {
-                peekable.next();
-                nest+=1;
-                continue;
-            }
-            Some(Expression::Rparen) => {
-                peekable.next();
-                nest-=1;
-                if nest < 1 {
-                    break;
-                }else {
-                    continue;
-                }
-            }
-            Some(Expression::Semicolon) => break,
-            Some(Expression::Lbrace) => {
-                return Err("Can't have block in function parameters".to_owned());
-            }
-            None => break,
-            _ => params.push(read_line(None, peekable, &vec![&Expression::Comma, &Expression::Rparen])?),
-        }
-    }
+///Reads the names of a function's parameters, up to and including the closing paren
+fn find_params(iter: &mut Tokens) -> Result<Vec<Node>, Error> {
+    let (params, _) = comma_list(iter, &Expression::Rparen, "\")\"", |i| match i.next() {
+        Some(
+            p @ Token {
+                kind: Expression::Ident(_),
+                ..
+            },
+        ) => Ok(make_node(p)),
+        Some(
+            p @ Token {
+                kind: Expression::Lbrace,
+                ..
+            },
+        ) => Err(unexpected(p, "parameter name")
+            .with_note("blocks can't be used as function parameters")),
+        Some(p) => Err(unexpected(p, "parameter name")),
+        None => Err(unexpected_eof(&Span::default(), "parameter name")),
+    })?;
     Ok(params)
 }
 
-fn make_for_loop(iter: &mut Peekable<Iter<'_, Expression>>) -> Result<ExprNode, String> {
-    match iter.peek() {
-        Some(Expression::Lparen) => {
-            iter.next(); //skip the lparen after the "for" keyword
-            let name = iter.next(); //grab the next expression to pass it in as cur
-            let dec = expr(iter, name)?; //get the declaration expression (i = 0)
-            if let ExprNode::Operation(op, _, _) = &dec {
-                //double check to make sure this was an assignment op
-                if **op == Expression::Equal {
-                    let condition = read_line(None, iter, &vec![&Expression::Semicolon])?; //get the condition expression (i < 10)
-                    let increment = read_line(None, iter, &vec![&Expression::Rparen])?; //get the incrementation expression (i = i + 1)
-                    return Ok(ExprNode::ForLoopDec(
-                        Box::new(dec),
-                        Box::new(condition),
-                        Box::new(increment),
-                    ));
-                }
-            }
-            //for loops don't need to have an assinment op, so that needs to be supported
-            iter.next(); //skip the last semicolon
-            let increment = read_line(None, iter, &vec![&Expression::Rparen])?; //get the incrementation expression (i = i + 1)
-            iter.next();
-            iter.next(); //skipping the closing paren and opening braceso that the body can be parsed properly
-            Ok(ExprNode::ForLoopDec(
-                Box::new(ExprNode::Illegal(None)),
-                Box::new(dec),
-                Box::new(increment),
-            ))
-        }
-        Some(_) | None => Err(format!("Expected \"(\", found {:?}", iter.next())),
-    }
+fn is_assignment(node: &Node) -> bool {
+    matches!(&node.kind, ExprNode::Operation(op, _, _) if **op == Expression::Equal)
 }
 
-fn make_if(iter: &mut Peekable<Iter<'_, Expression>>) -> Result<ExprNode, String> {
-    // if let Some(Expression::Lparen) = iter.peek() {
-        // iter.next();
-        let condition = read_line(None, iter, &vec![&Expression::Lbrace])?; //get the conditional statement for the if
-        // iter.next(); //skip the closing paren
-        // iter.next(); //skip the opening brace
-        let block = make_block(iter)?; //get the body of the if
+fn make_for_loop(iter: &mut Tokens, key: &Token) -> Result<Node, Error> {
+    expect(iter, &Expression::Lparen, "\"(\"")?;
+    let first = expression(iter, 0)?; //get the declaration expression (i = 0)
+    expect(iter, &Expression::Semicolon, "\";\"")?;
+    let second = expression(iter, 0)?; //get the condition expression (i < 10)
+
+    //for loops don't need to have an assignment op, so 'for(i < 10; i++)' needs to be supported
+    let (dec, condition, increment) = if is_assignment(&first) {
+        expect(iter, &Expression::Semicolon, "\";\"")?;
+        let increment = expression(iter, 0)?; //get the incrementation expression (i = i + 1)
+        (first, second, increment)
+    } else {
+        let none = Node::new(ExprNode::Illegal(None), key.span.clone());
+        (none, first, second)
+    };
+    let close = expect(iter, &Expression::Rparen, "\")\"")?;
 
-        let mut branch = ExprNode::Illegal(None);
+    let span = key.span.to(&close.span);
+    Ok(Node::new(
+        ExprNode::ForLoopDec(Box::new(dec), Box::new(condition), Box::new(increment)),
+        span,
+    ))
+}
 
+///Parses what a for-in loop goes through, like `for x in items`. A second variable counts the items, like
+///`for i, x in items`, and numbers can be counted through with a range, like `for n in 0..=10 step 2`.
+fn make_for_in(iter: &mut Tokens, key: &Token) -> Result<Node, Error> {
+    let var = |iter: &mut Tokens| match iter.next() {
+        Some(
+            t @ Token {
+                kind: Expression::Ident(_),
+                ..
+            },
+        ) => Ok(make_node(t)),
+        Some(t) => Err(unexpected(t, "loop variable")),
+        None => Err(unexpected_eof(&key.span, "loop variable")),
+    };
+    let mut vars = vec![var(iter)?];
+    if iter.peek().map(|t| &t.kind) == Some(&Expression::Comma) {
+        iter.next();
+        vars.push(var(iter)?);
+    }
+    expect(iter, &Expression::Key("in".to_owned()), "\"in\"")?;
 
-        if let Some(Expression::Key(w)) = iter.peek() {
-            match w.as_str() {
-                "else" => {
-                    iter.next(); //skip the else expression
-                    iter.next(); //skip the opening brace
-                    branch = make_block(iter)?; //push on the body of the else statement
-                }
-                "elif" => {
+    let start = expression(iter, 0)?;
+    let over = match iter.peek().map(|t| &t.kind) {
+        Some(&Expression::Range(inclusive)) => {
+            iter.next();
+            let end = expression(iter, 0)?;
+            //`step` is only special here, so it can still be used as a name
+            let step = match iter.peek().map(|t| &t.kind) {
+                Some(Expression::Ident(s)) if s == "step" => {
                     iter.next();
-                    branch = make_if(iter)?;
+                    expression(iter, 0)?
                 }
-                _ => {}
-            }
+                _ => Node::new(ExprNode::Illegal(None), end.span.clone()),
+            };
+            let span = start.span.to(&step.span);
+            Node::new(
+                ExprNode::Range(Box::new(start), Box::new(end), Box::new(step), inclusive),
+                span,
+            )
         }
-        Ok(ExprNode::IfStatement(
-            Box::new(condition),
-            Box::new(block),
-            Box::new(branch),
-        ))
-    // } else {
-        // Err(format!("Expected \"(\" found {}", iter.next().unwrap()))
-    // }
-}
-
-fn make_array(iter: &mut Peekable<Iter<'_, Expression>>) -> Result<ExprNode, String> {
-    let mut res = vec![];
-    loop {
-        // println!("{:?} is next in make_array", iter.peek());
-        // println!("{:?} is the accumulated array", res);
-        // std::io::stdin().read_line(&mut String::new());
-        match iter.peek() {
-            Some(Expression::Rbracket) | Some(Expression::Semicolon) => {
-                iter.next();
-                return Ok(ExprNode::Array(res));
-            }
-            Some(Expression::Lbracket) => {
-                iter.next();
-                res.push(make_array(iter)?)
+        _ => start,
+    };
+
+    let span = key.span.to(&over.span);
+    Ok(Node::new(ExprNode::ForIn(vars, Box::new(over)), span))
+}
+
+fn make_if(iter: &mut Tokens, key: &Token) -> Result<Node, Error> {
+    let condition = expression(iter, 0)?; //get the conditional statement for the if
+    let open = expect(iter, &Expression::Lbrace, "\"{\"")?;
+    let block = make_block(iter, Some(open))?; //get the body of the if
+
+    let mut branch = Node::new(ExprNode::Illegal(None), block.span.clone());
+
+    if let Some(Token {
+        kind: Expression::Key(w),
+        ..
+    }) = iter.peek()
+    {
+        match w.as_str() {
+            "else" => {
+                iter.next(); //skip the else expression
+                let open = expect(iter, &Expression::Lbrace, "\"{\"")?;
+                branch = make_block(iter, Some(open))?; //push on the body of the else statement
             }
-            Some(Expression::Comma) => {
-                iter.next();
-                continue;
+            "elif" => {
+                let elif = iter.next().unwrap();
+                branch = make_if(iter, elif)?;
             }
-            None => return Ok(ExprNode::Array(res)),//return Err("Unexpected end of file".to_owned()),
-            _ => res.push(read_line(None, iter, &vec![&Expression::Comma, &Expression::Rbracket])?),
+            _ => {}
         }
     }
+    let span = key.span.to(&block.span).to(&branch.span);
+    Ok(Node::new(
+        ExprNode::IfStatement(Box::new(condition), Box::new(block), Box::new(branch)),
+        span,
+    ))
 }
 
-fn define_class(iter: &mut Peekable<Iter<'_, Expression>>) -> Result<ExprNode, String> {
-    // println!("defining class");
-    let name = if let Some(Expression::Ident(_)) = iter.peek(){
-        iter.next().unwrap()
-    }else {
-        return Err(format!("Expected identifier, found {:?}", iter.peek()));
+fn define_class(iter: &mut Tokens, key: &Token) -> Result<Node, Error> {
+    let name = match iter.next() {
+        Some(
+            n @ Token {
+                kind: Expression::Ident(_),
+                ..
+            },
+        ) => n,
+        Some(t) => return Err(unexpected(t, "identifier")),
+        None => return Err(unexpected_eof(&key.span, "identifier")),
     };
 
-    iter.next();
-    let body = make_block(iter)?;
+    let open = expect(iter, &Expression::Lbrace, "\"{\"")?;
+    let body = make_block(iter, Some(open))?;
 
-    Ok(ExprNode::Class(Box::new(name.clone()), Box::new(body)))
+    let span = key.span.to(&body.span);
+    Ok(Node::new(
+        ExprNode::Class(Box::new(name.kind.clone()), Box::new(body)),
+        span,
+    ))
 }
 
-fn new_object(iter: &mut Peekable<Iter<'_, Expression>>) -> Result<ExprNode, String> {
-    let name = if let Some(Expression::Ident(_)) = iter.peek() {
-        iter.next().unwrap()
-    }else {
-        return Err(format!("Expected identifier, found {:?}", iter.peek()));
+fn new_object(iter: &mut Tokens, key: &Token) -> Result<Node, Error> {
+    let name = match iter.next() {
+        Some(
+            n @ Token {
+                kind: Expression::Ident(_),
+                ..
+            },
+        ) => n,
+        Some(t) => return Err(unexpected(t, "identifier")),
+        None => return Err(unexpected_eof(&key.span, "identifier")),
     };
 
-    iter.next();
-    match find_params(iter) {
-        Ok(params) => Ok(ExprNode::New(Box::new(name.clone()), params)),
-        Err(e) => Err(e)
-    }
-}
\ No newline at end of file
+    expect(iter, &Expression::Lparen, "\"(\"")?;
+    let (params, close) = comma_list(iter, &Expression::Rparen, "\")\"", |i| expression(i, 0))?;
+    let span = key.span.to(&close.span);
+    Ok(Node::new(
+        ExprNode::New(Box::new(name.kind.clone()), params),
+        span,
+    ))
+}
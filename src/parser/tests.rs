@@ -1,9 +1,11 @@
-use crate::lexer::Expression;
+use crate::error::{Error, ParseErrorKind};
+use crate::lexer;
+use crate::lexer::{Expression, Token};
 use crate::parser::*;
 
 #[test]
 fn ast_generation() {
-    let dummy = vec![
+    let dummy: Vec<Token> = vec![
         Expression::Key("fn".to_owned()),
         Expression::Ident("test".to_owned()),
         Expression::Lparen,
@@ -14,16 +16,444 @@ fn ast_generation() {
         Expression::Semicolon,
         Expression::Rbrace,
         Expression::EOF,
-    ];
+    ]
+    .into_iter()
+    .map(Token::from)
+    .collect();
 
-    let expected = ExprNode::Block(vec![ExprNode::Func(
+    let expected: Node = ExprNode::Block(vec![ExprNode::Func(
         Box::new(Expression::Ident("test".to_owned())),
         vec![],
-        Box::new(ExprNode::Block(vec![ExprNode::Call(
-            Box::new(Expression::Key("print".to_owned())),
-            vec![ExprNode::StrLiteral(Box::new("hello world".to_owned()))],
-        )])),
-    )]);
+        Box::new(
+            ExprNode::Block(vec![ExprNode::Call(
+                Box::new(Expression::Key("print".to_owned())),
+                vec![ExprNode::StrLiteral(Box::new("hello world".to_owned())).into()],
+            )
+            .into()])
+            .into(),
+        ),
+    )
+    .into()])
+    .into();
 
     assert_eq!(parse(dummy).unwrap(), expected);
 }
+
+#[test]
+fn node_spans() {
+    let tokens = lexer::run_file("test.em", "x = 1;\nfoo(x);").unwrap();
+    let ast = parse(tokens).unwrap();
+
+    if let ExprNode::Block(nodes) = &ast.kind {
+        assert_eq!(format!("{}", nodes[0].span), "test.em:1:1");
+        assert_eq!(format!("{}", nodes[1].span), "test.em:2:1");
+        if let ExprNode::Call(_, args) = &nodes[1].kind {
+            assert_eq!(format!("{}", args[0].span), "test.em:2:5");
+            return;
+        }
+    }
+
+    panic!("Unexpected tree {:?}", ast);
+}
+
+///Parses a single expression statement and returns its node
+fn parse_expr(source: &str) -> Node {
+    let ast = parse(lexer::run(source).unwrap()).unwrap();
+    match ast.kind {
+        ExprNode::Block(mut nodes) => nodes.remove(0),
+        other => panic!("Unexpected tree {:?}", other),
+    }
+}
+
+fn num(n: i64) -> Node {
+    ExprNode::IntLiteral(n).into()
+}
+
+fn name(n: &str) -> Node {
+    ExprNode::Name(Box::new(n.to_owned())).into()
+}
+
+fn op(o: Expression, l: Node, r: Node) -> Node {
+    ExprNode::Operation(Box::new(o), Box::new(l), Box::new(r)).into()
+}
+
+#[test]
+fn precedence() {
+    let plus = || Expression::Operator('+');
+    let minus = || Expression::Operator('-');
+    let times = || Expression::Operator('*');
+
+    // 2 * 3 + 4 => (2 * 3) + 4
+    assert_eq!(
+        parse_expr("2 * 3 + 4;"),
+        op(plus(), op(times(), num(2), num(3)), num(4))
+    );
+    // a - b - c => (a - b) - c
+    assert_eq!(
+        parse_expr("a - b - c;"),
+        op(minus(), op(minus(), name("a"), name("b")), name("c"))
+    );
+    // 2 * (3 + 4) keeps the group
+    assert_eq!(
+        parse_expr("2 * (3 + 4);"),
+        op(
+            times(),
+            num(2),
+            ExprNode::Group(Box::new(op(plus(), num(3), num(4)))).into()
+        )
+    );
+    // a = b = 1 + 2 => a = (b = (1 + 2))
+    assert_eq!(
+        parse_expr("a = b = 1 + 2;"),
+        op(
+            Expression::Equal,
+            name("a"),
+            op(Expression::Equal, name("b"), op(plus(), num(1), num(2)))
+        )
+    );
+    // x + 1 < y * 2 == true => ((x + 1) < (y * 2)) == true
+    assert_eq!(
+        parse_expr("x + 1 < y * 2 == true;"),
+        op(
+            Expression::BoolOp("==".to_owned()),
+            op(
+                Expression::BoolOp("<".to_owned()),
+                op(plus(), name("x"), num(1)),
+                op(times(), name("y"), num(2))
+            ),
+            ExprNode::BoolLiteral(true).into()
+        )
+    );
+    // 1.5 * 2 keeps the float and the integer apart
+    assert_eq!(
+        parse_expr("1.5 * 2;"),
+        op(times(), ExprNode::FloatLiteral(1.5).into(), num(2))
+    );
+}
+
+#[test]
+fn postfix_operators() {
+    // calls, indexing, and member access bind tighter than arithmetic
+    assert_eq!(
+        parse_expr("foo(1, 2)[0] * p.x;"),
+        op(
+            Expression::Operator('*'),
+            ExprNode::Index(
+                Box::new(
+                    ExprNode::Call(
+                        Box::new(Expression::Ident("foo".to_owned())),
+                        vec![num(1), num(2)]
+                    )
+                    .into()
+                ),
+                Box::new(num(0))
+            )
+            .into(),
+            op(Expression::Operator('.'), name("p"), name("x"))
+        )
+    );
+    assert_eq!(
+        parse_expr("p.add(1);"),
+        ExprNode::MethodCall(
+            Box::new(op(Expression::Operator('.'), name("p"), name("add"))),
+            vec![num(1)]
+        )
+        .into()
+    );
+}
+
+#[test]
+fn parse_errors() {
+    let err = parse(lexer::run("1 + 2 = 3;").unwrap()).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::ParseError {
+            kind: ParseErrorKind::InvalidAssignment,
+            ..
+        }
+    ));
+
+    let err = parse(lexer::run("x = (1 + 2;").unwrap()).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::ParseError {
+            kind: ParseErrorKind::UnexpectedToken,
+            ..
+        }
+    ));
+
+    let err = parse(lexer::run("fn main() { x = 1;").unwrap()).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::ParseError {
+            kind: ParseErrorKind::UnexpectedEof,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn lambdas() {
+    let body = |n: Node| -> Box<Node> { Box::new(n) };
+    // the short forms return their expression directly
+    assert_eq!(
+        parse_expr("x => x * 2;"),
+        ExprNode::Lambda(
+            vec![name("x")],
+            body(op(Expression::Operator('*'), name("x"), num(2)))
+        )
+        .into()
+    );
+    assert_eq!(
+        parse_expr("(a, b) => a + b;"),
+        ExprNode::Lambda(
+            vec![name("a"), name("b")],
+            body(op(Expression::Operator('+'), name("a"), name("b")))
+        )
+        .into()
+    );
+    // a plain group isn't mistaken for parameters
+    assert_eq!(
+        parse_expr("(a) + b;"),
+        op(
+            Expression::Operator('+'),
+            ExprNode::Group(Box::new(name("a"))).into(),
+            name("b")
+        )
+    );
+    // anonymous functions can be called right away
+    assert_eq!(
+        parse_expr("fn (a) { return a; }(1);"),
+        ExprNode::Invoke(
+            Box::new(
+                ExprNode::Lambda(
+                    vec![name("a")],
+                    body(
+                        ExprNode::Block(vec![ExprNode::ReturnVal(Box::new(name("a"))).into()])
+                            .into()
+                    )
+                )
+                .into()
+            ),
+            vec![num(1)]
+        )
+        .into()
+    );
+}
+
+#[test]
+fn map_literals() {
+    let string = |s: &str| -> Node { ExprNode::StrLiteral(Box::new(s.to_owned())).into() };
+    assert_eq!(
+        parse_expr("m = { \"a\": 1, 2: b };"),
+        op(
+            Expression::Equal,
+            name("m"),
+            ExprNode::Map(vec![(string("a"), num(1)), (num(2), name("b"))]).into()
+        )
+    );
+    assert_eq!(
+        parse_expr("m = {};"),
+        op(Expression::Equal, name("m"), ExprNode::Map(vec![]).into())
+    );
+
+    // braces at the start of a statement are still a block
+    let tree = parse(lexer::run("{ x = 1; }").unwrap()).unwrap();
+    assert!(matches!(&tree.kind, ExprNode::Block(v) if matches!(v[0].kind, ExprNode::Block(_))));
+
+    let err = parse(lexer::run("m = { \"a\" 1 };").unwrap()).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::ParseError {
+            kind: ParseErrorKind::UnexpectedToken,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn unary_and_logical() {
+    let unary =
+        |o: Expression, n: Node| -> Node { ExprNode::Unary(Box::new(o), Box::new(n)).into() };
+    let and = || Expression::BoolOp("&&".to_owned());
+    let or = || Expression::BoolOp("||".to_owned());
+    let not = || Expression::BoolOp("!".to_owned());
+
+    // !a || b && c => (!a) || (b && c)
+    assert_eq!(
+        parse_expr("!a || b && c;"),
+        op(
+            or(),
+            unary(not(), name("a")),
+            op(and(), name("b"), name("c"))
+        )
+    );
+    // a == 1 or not b => (a == 1) || (!b)
+    assert_eq!(
+        parse_expr("a == 1 or not b;"),
+        op(
+            or(),
+            op(Expression::BoolOp("==".to_owned()), name("a"), num(1)),
+            unary(not(), name("b"))
+        )
+    );
+    // -x * 2 => (-x) * 2, but -p.x negates the member
+    assert_eq!(
+        parse_expr("-x * 2;"),
+        op(
+            Expression::Operator('*'),
+            unary(Expression::Operator('-'), name("x")),
+            num(2)
+        )
+    );
+    assert_eq!(
+        parse_expr("+-p.x;"),
+        unary(
+            Expression::Operator('+'),
+            unary(
+                Expression::Operator('-'),
+                op(Expression::Operator('.'), name("p"), name("x"))
+            )
+        )
+    );
+
+    //a single & isn't an operator
+    let err = parse(lexer::run("a & b;").unwrap()).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::ParseError {
+            kind: ParseErrorKind::UnexpectedToken,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn remainder_power_and_floor_division() {
+    let pow = |l: Node, r: Node| op(Expression::Power, l, r);
+    // a ** b ** c => a ** (b ** c)
+    assert_eq!(
+        parse_expr("a ** b ** c;"),
+        pow(name("a"), pow(name("b"), name("c")))
+    );
+    // -a ** 2 => -(a ** 2), but the exponent can still have a sign
+    assert_eq!(
+        parse_expr("-a ** -2;"),
+        ExprNode::Unary(
+            Box::new(Expression::Operator('-')),
+            Box::new(pow(
+                name("a"),
+                ExprNode::Unary(Box::new(Expression::Operator('-')), Box::new(num(2))).into()
+            ))
+        )
+        .into()
+    );
+    // a + b % c div d => a + ((b % c) div d)
+    assert_eq!(
+        parse_expr("a + b % c div d;"),
+        op(
+            Expression::Operator('+'),
+            name("a"),
+            op(
+                Expression::Key("div".to_owned()),
+                op(Expression::Operator('%'), name("b"), name("c")),
+                name("d")
+            )
+        )
+    );
+    // a **= 2 => a = a ** 2
+    assert_eq!(
+        parse_expr("a **= 2;"),
+        op(Expression::Equal, name("a"), pow(name("a"), num(2)))
+    );
+}
+
+#[test]
+fn for_in_loops() {
+    let for_in = |vars: Vec<Node>, over: Node| -> Node {
+        ExprNode::Loop(
+            Box::new("for".to_owned()),
+            Box::new(ExprNode::ForIn(vars, Box::new(over)).into()),
+            Box::new(ExprNode::Block(vec![]).into()),
+        )
+        .into()
+    };
+    let range = |start: Node, end: Node, step: Node, inclusive: bool| -> Node {
+        ExprNode::Range(Box::new(start), Box::new(end), Box::new(step), inclusive).into()
+    };
+    assert_eq!(
+        parse_expr("for x in xs { }"),
+        for_in(vec![name("x")], name("xs"))
+    );
+    assert_eq!(
+        parse_expr("for i, x in xs { }"),
+        for_in(vec![name("i"), name("x")], name("xs"))
+    );
+    //without a step, the range counts by one
+    assert_eq!(
+        parse_expr("for i in 0..n + 1 { }"),
+        for_in(
+            vec![name("i")],
+            range(
+                num(0),
+                op(Expression::Operator('+'), name("n"), num(1)),
+                ExprNode::Illegal(None).into(),
+                false
+            )
+        )
+    );
+    assert_eq!(
+        parse_expr("for i in 10..=0 step -2 { }"),
+        for_in(
+            vec![name("i")],
+            range(
+                num(10),
+                num(0),
+                ExprNode::Unary(Box::new(Expression::Operator('-')), Box::new(num(2))).into(),
+                true
+            )
+        )
+    );
+
+    for source in ["for in xs { }", "for x xs { }", "for 1 in xs { }"] {
+        let err = parse(lexer::run(source).unwrap()).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::ParseError {
+                kind: ParseErrorKind::UnexpectedToken,
+                ..
+            }
+        ));
+    }
+}
+
+#[test]
+fn fstrings() {
+    let plus = || Expression::Operator('+');
+    let text = |s: &str| -> Node { ExprNode::StrLiteral(Box::new(s.to_owned())).into() };
+    // f"a {b * 2} c" => "a " + (b * 2) + " c"
+    assert_eq!(
+        parse_expr("f\"a {b * 2} c\";"),
+        op(
+            plus(),
+            op(
+                plus(),
+                text("a "),
+                op(Expression::Operator('*'), name("b"), num(2))
+            ),
+            text(" c")
+        )
+    );
+    //an expression at the start is joined onto an empty string so it's turned into one
+    assert_eq!(parse_expr("f\"{b}\";"), op(plus(), text(""), name("b")));
+    assert_eq!(parse_expr("f\"\";"), text(""));
+
+    let err = parse(lexer::run("x = f\"{}\";").unwrap()).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::ParseError {
+            kind: ParseErrorKind::UnexpectedToken,
+            ..
+        }
+    ));
+}